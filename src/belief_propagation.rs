@@ -0,0 +1,316 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with sum-product belief propagation over discrete factor graphs
+
+use *;
+use model::Model;
+use table::log_sum_exp;
+
+/// Subtract the log-sum-exp from a log-message so that it sums to one.
+fn normalize(message: &mut [f64]) {
+    let log_z = log_sum_exp(message);
+    if log_z.is_finite() {
+        for m in message.iter_mut() {
+            *m -= log_z;
+        }
+    }
+}
+
+/// Compute the message from the variable at position `pos` of factor `f` to that factor.
+fn variable_message(model: &Model, evidence: &[Vec<f64>], to_var: &[Vec<Vec<f64>>], f: usize, pos: usize) -> Vec<f64> {
+    let var = model.scopes[f][pos];
+    let mut message = evidence[var].clone();
+    for &g in model.var_factors[var].iter() {
+        for (other_pos, &other) in model.scopes[g].iter().enumerate() {
+            if other == var && (g, other_pos) != (f, pos) {
+                for (m, incoming) in message.iter_mut().zip(to_var[g][other_pos].iter()) {
+                    *m += incoming;
+                }
+            }
+        }
+    }
+
+    normalize(&mut message);
+    message
+}
+
+/// Compute the message from factor `f` to the variable at position `pos` of its scope.
+fn factor_message(model: &Model, to_factor: &[Vec<Vec<f64>>], f: usize, pos: usize) -> Vec<f64> {
+    let table = &model.tables[f];
+    let mut terms: Vec<Vec<f64>> = vec![vec!(); model.cardinalities[model.scopes[f][pos]]];
+    for index in 0..table.size() {
+        let assignment = table.assignment_at(index);
+        let mut value = table.get_log_values()[index];
+        for (other_pos, &val) in assignment.iter().enumerate() {
+            if other_pos != pos {
+                value += to_factor[f][other_pos][val as usize];
+            }
+        }
+        terms[assignment[pos] as usize].push(value);
+    }
+
+    let mut message: Vec<f64> = terms.iter().map(|term| log_sum_exp(term)).collect();
+    normalize(&mut message);
+    message
+}
+
+/// Struct holding the beliefs computed by belief propagation.
+#[derive(Clone, Debug)]
+pub struct BeliefPropagationResult {
+    /// Belief over the values of every variable.
+    pub marginals: Marginals,
+
+    /// Normalized belief over the scope of every factor, in the order of `FactorGraph::get_factors`.
+    ///
+    /// Each table's variables are the factor's variables, in the order `Factor::get_variables` gives them.
+    pub factor_beliefs: Vec<Table>,
+
+    /// Number of message passing iterations that were run.
+    pub iterations: usize,
+
+    /// Whether the messages converged before the iteration limit.
+    pub converged: bool,
+}
+
+impl BeliefPropagationResult {
+    /// Get the belief over the scope of a factor of the graph the beliefs were computed on.
+    pub fn get_factor_belief(&self, graph: &FactorGraph, factor: &Factor) -> Option<&Table> {
+        graph.get_factors().iter()
+            .position(|f| f.get_id() == factor.get_id())
+            .and_then(|index| self.factor_beliefs.get(index))
+    }
+}
+
+/// Struct running loopy sum-product belief propagation with parallel message updates.
+///
+/// On graphs without cycles the beliefs are the exact marginals.
+#[derive(Debug)]
+pub struct BeliefPropagation<'a> {
+    graph: &'a FactorGraph,
+    evidence: Assignment,
+    max_iterations: usize,
+    tolerance: f64,
+    damping: f64,
+}
+
+impl<'a> BeliefPropagation<'a> {
+    /// Create a new BeliefPropagation for the graph, running at most 100 iterations without damping.
+    pub fn new(graph: &'a FactorGraph) -> BeliefPropagation<'a> {
+        BeliefPropagation {
+            graph,
+            evidence: Assignment::new(),
+            max_iterations: 100,
+            tolerance: 1e-8,
+            damping: 0.0,
+        }
+    }
+
+    /// Clamp the given variables to observed values.
+    pub fn with_evidence(mut self, evidence: Assignment) -> BeliefPropagation<'a> {
+        self.evidence = evidence;
+        self
+    }
+
+    /// Set the maximum number of message passing iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> BeliefPropagation<'a> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Stop once no message changes by more than `tolerance` in an iteration.
+    pub fn with_tolerance(mut self, tolerance: f64) -> BeliefPropagation<'a> {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Keep a fraction `damping` of each old message, in the log domain, to help loopy graphs converge.
+    pub fn with_damping(mut self, damping: f64) -> BeliefPropagation<'a> {
+        if !(0.0..1.0).contains(&damping) {
+            panic!("Damping must be in [0, 1), got {}", damping);
+        }
+
+        self.damping = damping;
+        self
+    }
+
+    /// Pass messages until convergence and compute the beliefs.
+    pub fn run(&self) -> BeliefPropagationResult {
+        let model = Model::new(self.graph);
+        let clamped = model.clamped(&self.evidence);
+
+        // Log-indicator of the evidence for every variable.
+        let evidence: Vec<Vec<f64>> = model.cardinalities.iter().zip(clamped.iter())
+            .map(|(&card, clamp)| (0..card as u32)
+                .map(|val| match *clamp {
+                    Some(observed) if observed != val => f64::NEG_INFINITY,
+                    _ => 0.0,
+                })
+                .collect())
+            .collect();
+
+        // Messages are indexed by factor and position within the factor's scope.
+        let uniform = |f: usize| -> Vec<Vec<f64>> {
+            model.scopes[f].iter().map(|&var| vec![0.0; model.cardinalities[var]]).collect()
+        };
+        let mut to_factor: Vec<Vec<Vec<f64>>> = (0..model.tables.len()).map(uniform).collect();
+        let mut to_var = to_factor.clone();
+
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.max_iterations && !converged {
+            iterations += 1;
+
+            to_factor = model.scopes.iter().enumerate()
+                .map(|(f, scope)| (0..scope.len())
+                    .map(|pos| variable_message(&model, &evidence, &to_var, f, pos))
+                    .collect())
+                .collect();
+
+            let mut max_change: f64 = 0.0;
+            let mut new_to_var = to_var.clone();
+            for (f, messages) in new_to_var.iter_mut().enumerate() {
+                for (pos, message) in messages.iter_mut().enumerate() {
+                    *message = factor_message(&model, &to_factor, f, pos);
+                    if self.damping > 0.0 {
+                        for (m, old) in message.iter_mut().zip(to_var[f][pos].iter()) {
+                            if old.is_finite() && m.is_finite() {
+                                *m = (1.0 - self.damping) * *m + self.damping * old;
+                            }
+                        }
+                        normalize(message);
+                    }
+
+                    for (m, old) in message.iter().zip(to_var[f][pos].iter()) {
+                        max_change = max_change.max((m.exp() - old.exp()).abs());
+                    }
+                }
+            }
+
+            to_var = new_to_var;
+            converged = max_change < self.tolerance;
+        }
+
+        let factor_beliefs = model.tables.iter().enumerate()
+            .map(|(f, table)| {
+                let incoming: Vec<Vec<f64>> = (0..model.scopes[f].len())
+                    .map(|pos| variable_message(&model, &evidence, &to_var, f, pos))
+                    .collect();
+                let log_values = (0..table.size())
+                    .map(|index| {
+                        let assignment = table.assignment_at(index);
+                        assignment.iter().zip(incoming.iter())
+                            .fold(table.get_log_values()[index], |acc, (&val, m)| acc + m[val as usize])
+                    })
+                    .collect();
+                Table::new(table.get_variables().clone(), table.get_cardinalities().clone(), log_values).normalized()
+            })
+            .collect();
+
+        let mut marginals: Vec<Vec<f64>> = evidence.clone();
+        for (f, scope) in model.scopes.iter().enumerate() {
+            for (pos, &var) in scope.iter().enumerate() {
+                for (b, incoming) in marginals[var].iter_mut().zip(to_var[f][pos].iter()) {
+                    *b += incoming;
+                }
+            }
+        }
+
+        for belief in marginals.iter_mut() {
+            normalize(belief);
+            for b in belief.iter_mut() {
+                *b = b.exp();
+            }
+        }
+
+        BeliefPropagationResult {
+            marginals: model.names.iter().cloned().zip(marginals).collect(),
+            factor_beliefs,
+            iterations,
+            converged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agree(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 3 } else { 1 }
+    }
+
+    fn prefer(vals: &[u32]) -> i32 {
+        vals[0] as i32 + 1
+    }
+
+    fn add_pair(graph: &mut FactorGraph, x: &str, y: &str) {
+        graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), agree);
+    }
+
+    #[test]
+    fn beliefs_are_exact_on_trees() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d"] {
+            graph.add_discrete_var(name, vec![0, 1, 2]);
+        }
+        add_pair(&mut graph, "a", "b");
+        add_pair(&mut graph, "b", "c");
+        add_pair(&mut graph, "b", "d");
+        graph.add_factor::<i32>(vec!(String::from("c")), prefer);
+
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("d"), 0);
+        let result = BeliefPropagation::new(&graph).with_evidence(evidence.clone()).run();
+        let exact = Enumeration::new(&graph).with_evidence(evidence.clone()).run().unwrap();
+
+        assert!(result.converged);
+        for (name, marginal) in exact.marginals.iter() {
+            for (b, p) in result.marginals[name].iter().zip(marginal.iter()) {
+                assert!((b - p).abs() < 1e-9);
+            }
+        }
+
+        for factor in graph.get_factors() {
+            let scope: Vec<&str> = factor.get_variables().iter().map(|v| v.as_str()).collect();
+            let belief = result.get_factor_belief(&graph, factor).unwrap();
+            let expected = graph.query(&scope, &evidence);
+
+            assert_eq!(belief.get_variables(), factor.get_variables());
+            for (b, p) in belief.probabilities().iter().zip(expected.probabilities().iter()) {
+                assert!((b - p).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn factor_beliefs_agree_with_marginals_on_loops() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+        add_pair(&mut graph, "a", "b");
+        add_pair(&mut graph, "b", "c");
+        add_pair(&mut graph, "c", "a");
+        graph.add_factor::<i32>(vec!(String::from("a")), prefer);
+
+        let result = BeliefPropagation::new(&graph).with_damping(0.2).run();
+        assert!(result.converged);
+
+        for (factor, belief) in graph.get_factors().iter().zip(result.factor_beliefs.iter()) {
+            let first = &factor.get_variables()[0];
+            let mut marginal = belief.clone();
+            for var in factor.get_variables().iter().skip(1) {
+                marginal = marginal.sum_out(var);
+            }
+
+            for (b, p) in marginal.probabilities().iter().zip(result.marginals[first].iter()) {
+                assert!((b - p).abs() < 1e-6);
+            }
+        }
+    }
+}
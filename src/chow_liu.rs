@@ -0,0 +1,236 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with Chow-Liu learning of tree-structured models from data
+
+use *;
+
+/// Struct learning the tree-structured distribution closest to the data by the Chow-Liu algorithm.
+///
+/// The empirical mutual information of every pair of variables weights a complete graph, whose maximum weight
+/// spanning tree is the tree maximizing the likelihood of the data. The learned graph holds the empirical
+/// distribution of the root as a unary table and, for every other variable, its conditional distribution given
+/// its parent as a pairwise table, so it is normalized. Counts can be smoothed with a pseudo-count, as the
+/// empirical tables give probability zero to anything the data does not contain.
+#[derive(Debug)]
+pub struct ChowLiu<'a> {
+    data: &'a [Assignment],
+    variables: Vec<String>,
+    cardinalities: Vec<usize>,
+    pseudo_count: f64,
+}
+
+impl<'a> ChowLiu<'a> {
+    /// Create a new ChowLiu for complete assignments of the same variables.
+    ///
+    /// The variables are sorted by name, and the domain of each is taken to run up to the largest value observed.
+    pub fn new(data: &'a [Assignment]) -> ChowLiu<'a> {
+        if data.is_empty() {
+            panic!("Structure learning needs at least one assignment");
+        }
+
+        let mut variables: Vec<String> = data[0].keys().cloned().collect();
+        variables.sort();
+        let cardinalities = variables.iter()
+            .map(|var| {
+                data.iter()
+                    .map(|assignment| match assignment.get(var) {
+                        Some(&x) => x as usize + 1,
+                        None => panic!("The variable {} is not observed in every assignment", var)
+                    })
+                    .max()
+                    .unwrap()
+            })
+            .collect();
+        if data.iter().any(|assignment| assignment.len() != variables.len()) {
+            panic!("Every assignment needs to observe the same variables");
+        }
+
+        ChowLiu {
+            data,
+            variables,
+            cardinalities,
+            pseudo_count: 0.0,
+        }
+    }
+
+    /// Set the domain size of a variable, which must cover every value observed.
+    pub fn with_domain_size(mut self, var: &str, size: usize) -> ChowLiu<'a> {
+        let pos = match self.variables.iter().position(|v| v == var) {
+            Some(x) => x,
+            None => panic!("The variable {} is not in the data", var)
+        };
+        if size < self.cardinalities[pos] {
+            panic!("The variable {} takes values up to {}", var, self.cardinalities[pos] - 1);
+        }
+
+        self.cardinalities[pos] = size;
+        self
+    }
+
+    /// Add a pseudo-count to every entry of the count tables.
+    pub fn with_pseudo_count(mut self, pseudo_count: f64) -> ChowLiu<'a> {
+        if pseudo_count < 0.0 {
+            panic!("The pseudo-count must be non-negative, got {}", pseudo_count);
+        }
+
+        self.pseudo_count = pseudo_count;
+        self
+    }
+
+    /// Get the variables, in the order used by `mutual_information` and the tree.
+    pub fn get_variables(&self) -> &Vec<String> {
+        &self.variables
+    }
+
+    /// Compute the smoothed joint distribution of a pair of variables, in row-major order over `[first, second]`.
+    fn pair_distribution(&self, first: usize, second: usize) -> Vec<f64> {
+        let columns = self.cardinalities[second];
+        let mut counts = vec![self.pseudo_count; self.cardinalities[first] * columns];
+        for assignment in self.data.iter() {
+            counts[assignment[&self.variables[first]] as usize * columns + assignment[&self.variables[second]] as usize] += 1.0;
+        }
+
+        let total: f64 = counts.iter().sum();
+        counts.iter().map(|c| c / total).collect()
+    }
+
+    /// Compute the empirical mutual information, in nats, of a pair of variables.
+    fn pair_information(&self, first: usize, second: usize) -> f64 {
+        let joint = self.pair_distribution(first, second);
+        let columns = self.cardinalities[second];
+        let rows: Vec<f64> = joint.chunks(columns).map(|row| row.iter().sum()).collect();
+        let cols: Vec<f64> = (0..columns).map(|b| joint.iter().skip(b).step_by(columns).sum()).collect();
+
+        joint.iter().enumerate()
+            .filter(|&(_, &p)| p > 0.0)
+            .map(|(index, &p)| p * (p / (rows[index / columns] * cols[index % columns])).ln())
+            .sum()
+    }
+
+    /// Compute the empirical mutual information, in nats, of every pair of variables.
+    pub fn mutual_information(&self) -> Vec<Vec<f64>> {
+        let n = self.variables.len();
+        (0..n)
+            .map(|first| (0..n)
+                .map(|second| if first == second { 0.0 } else { self.pair_information(first.min(second), first.max(second)) })
+                .collect())
+            .collect()
+    }
+
+    /// Build the maximum weight spanning tree of the mutual information, rooted at the first variable.
+    pub fn tree(&self) -> SpanningTree {
+        SpanningTree::maximum_weight(&self.variables, &self.mutual_information())
+    }
+
+    /// Learn the tree-structured model.
+    pub fn run(&self) -> FactorGraph {
+        let tree = self.tree();
+        let mut graph = FactorGraph::new();
+        for (var, &card) in self.variables.iter().zip(self.cardinalities.iter()) {
+            graph.add_discrete_var(var, (0..card as u32).collect());
+        }
+
+        let root = &self.variables[0];
+        let mut counts = vec![self.pseudo_count; self.cardinalities[0]];
+        for assignment in self.data.iter() {
+            counts[assignment[root] as usize] += 1.0;
+        }
+        let total: f64 = counts.iter().sum();
+        graph.add_table_factor(Table::new(vec!(root.clone()), vec!(self.cardinalities[0]),
+                                          counts.iter().map(|c| (c / total).ln()).collect()));
+
+        for node in tree.all_nodes.iter() {
+            if let Some(parent) = node.get_parent() {
+                let first = self.position(&tree.all_nodes[parent].get_name());
+                let second = self.position(&node.get_name());
+                let columns = self.cardinalities[second];
+                let log_values = self.pair_distribution(first, second).chunks(columns)
+                    .flat_map(|row| {
+                        // A parent value that was never seen, without smoothing, gets a uniform conditional.
+                        let total: f64 = row.iter().sum();
+                        let uniform = (1.0 / columns as f64).ln();
+                        row.iter().map(move |p| if total > 0.0 { (p / total).ln() } else { uniform })
+                    })
+                    .collect();
+                graph.add_table_factor(Table::new(vec!(self.variables[first].clone(), self.variables[second].clone()),
+                                                  vec!(self.cardinalities[first], columns), log_values));
+            }
+        }
+        graph
+    }
+
+    /// Get the position of a variable in the sorted order.
+    fn position(&self, var: &str) -> usize {
+        self.variables.iter().position(|v| v == var).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::Rng;
+
+    /// Draw assignments from the tree a - b, b - c, b - d with noisy copies along each edge.
+    fn sample(num_samples: usize) -> Vec<Assignment> {
+        let mut rng = Rng::new(9);
+        let mut copy = |parent: u32, flip: f64| if rng.next_f64() < flip { 1 - parent } else { parent };
+        (0..num_samples)
+            .map(|_| {
+                let a = copy(0, 0.4);
+                let b = copy(a, 0.1);
+                let c = copy(b, 0.2);
+                let d = copy(b, 0.3);
+                [("a", a), ("b", b), ("c", c), ("d", d)].iter().map(|&(v, x)| (String::from(v), x)).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recovers_the_tree() {
+        let data = sample(3000);
+        let learner = ChowLiu::new(&data);
+        let tree = learner.tree();
+
+        let mut edges: Vec<(String, String)> = tree.all_nodes.iter()
+            .filter_map(|node| node.get_parent().map(|parent| {
+                let mut edge = [tree.all_nodes[parent].get_name(), node.get_name()];
+                edge.sort();
+                (edge[0].clone(), edge[1].clone())
+            }))
+            .collect();
+        edges.sort();
+        let expected: Vec<(String, String)> = [("a", "b"), ("b", "c"), ("b", "d")].iter()
+            .map(|&(x, y)| (String::from(x), String::from(y)))
+            .collect();
+        assert_eq!(edges, expected);
+
+        let information = learner.mutual_information();
+        for node in tree.all_nodes.iter().skip(1) {
+            let parent = &tree.all_nodes[node.get_parent().unwrap()];
+            let (i, j) = (learner.position(&parent.get_name()), learner.position(&node.get_name()));
+            assert_eq!(node.get_weight(), information[i][j]);
+        }
+    }
+
+    #[test]
+    fn learned_model_matches_the_empirical_marginals() {
+        let data = sample(500);
+        let graph = ChowLiu::new(&data).with_pseudo_count(0.0).run();
+        let exact = Enumeration::new(&graph).run().unwrap();
+        assert!(exact.log_partition.abs() < 1e-12);
+
+        for var in ["a", "b", "c", "d"].iter() {
+            let frequency = data.iter().filter(|assignment| assignment[*var] == 1).count() as f64 / 500.0;
+            assert!((exact.marginals[*var][1] - frequency).abs() < 1e-12);
+        }
+
+        let smoothed = ChowLiu::new(&data).with_pseudo_count(1.0).with_domain_size("d", 3).run();
+        let exact = Enumeration::new(&smoothed).run().unwrap();
+        assert!(exact.marginals["d"][2] > 0.0);
+    }
+}
@@ -0,0 +1,245 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with contrastive divergence learning of log-linear weights, using Gibbs chains for the model's
+//! expectations
+
+use *;
+use mle::{FeatureTables, complete_states};
+use model::Model;
+use rng::Rng;
+
+/// Struct holding the result of contrastive divergence.
+#[derive(Clone, Debug)]
+pub struct CdResult {
+    /// Fitted parameters, which the graph is also left with.
+    pub parameters: Vec<f64>,
+
+    /// Norm of the estimated gradient at every update.
+    pub gradient_norms: Vec<f64>,
+
+    /// Number of updates that were made.
+    pub iterations: usize,
+}
+
+/// Struct fitting the parameters of a graph's log-linear factors by contrastive divergence.
+///
+/// Each update takes a minibatch of assignments and moves the weights by the learning rate times the difference
+/// between their features and the features of samples from the model, less `l2 * w`. Those samples come from
+/// `k` sweeps of `GibbsSampler`: for CD-k, the chains start at the minibatch; for persistent CD, a fixed set of
+/// chains started at random assignments from the data carries on from where the previous update left it. Neither
+/// needs the partition function, so both scale to graphs far beyond exact inference.
+#[derive(Debug)]
+pub struct ContrastiveDivergence<'a> {
+    graph: &'a mut FactorGraph,
+    data: &'a [Assignment],
+    k: usize,
+    persistent: bool,
+    num_chains: usize,
+    batch_size: usize,
+    learning_rate: f64,
+    iterations: usize,
+    l2: f64,
+    seed: u64,
+}
+
+impl<'a> ContrastiveDivergence<'a> {
+    /// Create a new ContrastiveDivergence fitting the graph to complete assignments of its variables.
+    ///
+    /// Defaults to CD-1 with minibatches of 10 assignments, running 1000 updates with a learning rate of 0.1.
+    pub fn new(graph: &'a mut FactorGraph, data: &'a [Assignment]) -> ContrastiveDivergence<'a> {
+        if data.is_empty() {
+            panic!("Contrastive divergence needs at least one assignment");
+        }
+
+        ContrastiveDivergence {
+            graph,
+            data,
+            k: 1,
+            persistent: false,
+            num_chains: 10,
+            batch_size: 10,
+            learning_rate: 0.1,
+            iterations: 1000,
+            l2: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Set the number of Gibbs sweeps made by the chains at every update.
+    pub fn with_k(mut self, k: usize) -> ContrastiveDivergence<'a> {
+        if k == 0 {
+            panic!("Contrastive divergence needs at least one Gibbs sweep per update");
+        }
+
+        self.k = k;
+        self
+    }
+
+    /// Use the given number of persistent chains rather than restarting the chains at the data.
+    pub fn with_persistent_chains(mut self, num_chains: usize) -> ContrastiveDivergence<'a> {
+        if num_chains == 0 {
+            panic!("Persistent contrastive divergence needs at least one chain");
+        }
+
+        self.persistent = true;
+        self.num_chains = num_chains;
+        self
+    }
+
+    /// Set the number of assignments in every minibatch.
+    pub fn with_batch_size(mut self, batch_size: usize) -> ContrastiveDivergence<'a> {
+        if batch_size == 0 {
+            panic!("Minibatches need at least one assignment");
+        }
+
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Set the step size of the updates.
+    pub fn with_learning_rate(mut self, learning_rate: f64) -> ContrastiveDivergence<'a> {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the number of updates.
+    pub fn with_iterations(mut self, iterations: usize) -> ContrastiveDivergence<'a> {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Decay the weights by `l2 * w` at every update, which is the gradient of the penalty `l2 / 2 * |w|^2`.
+    pub fn with_l2(mut self, l2: f64) -> ContrastiveDivergence<'a> {
+        if l2 < 0.0 {
+            panic!("The L2 penalty must be non-negative, got {}", l2);
+        }
+
+        self.l2 = l2;
+        self
+    }
+
+    /// Set the seed of the random number generator for the minibatches and the chains.
+    pub fn with_seed(mut self, seed: u64) -> ContrastiveDivergence<'a> {
+        self.seed = seed;
+        self
+    }
+
+    /// Run the updates, leaving the graph with the fitted parameters.
+    pub fn run(self) -> CdResult {
+        let model = Model::new(self.graph);
+        let features = FeatureTables::new(self.graph, &model);
+        let states = complete_states(&model, self.data);
+
+        let mut rng = Rng::new(self.seed);
+        let mut sampler = GibbsSampler::new(self.graph, rng.next_u64());
+        let mut chains: Vec<Assignment> = (0..self.num_chains)
+            .map(|_| self.data[(rng.next_u64() % self.data.len() as u64) as usize].clone())
+            .collect();
+
+        let mut weights = self.graph.get_parameters();
+        let mut gradient_norms = vec!();
+        let mut order: Vec<usize> = (0..states.len()).collect();
+        let mut next = order.len();
+        for _ in 0..self.iterations {
+            let batch: Vec<usize> = (0..self.batch_size.min(states.len()))
+                .map(|_| {
+                    if next == order.len() {
+                        for i in (1..order.len()).rev() {
+                            order.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+                        }
+                        next = 0;
+                    }
+                    next += 1;
+                    order[next - 1]
+                })
+                .collect();
+
+            let mut gradient: Vec<f64> = weights.iter().map(|w| -self.l2 * w).collect();
+            for &i in batch.iter() {
+                features.add_state(&model, &states[i], 1.0 / batch.len() as f64, &mut gradient);
+            }
+
+            sampler.reload(self.graph);
+            if !self.persistent {
+                chains = batch.iter().map(|&i| self.data[i].clone()).collect();
+            }
+            let scale = 1.0 / chains.len() as f64;
+            for chain in chains.iter_mut() {
+                sampler.set_state(chain);
+                for _ in 0..self.k {
+                    sampler.sweep();
+                }
+                *chain = sampler.get_state();
+
+                let state: Vec<u32> = model.clamped(chain).into_iter().map(|val| val.unwrap()).collect();
+                features.add_state(&model, &state, -scale, &mut gradient);
+            }
+
+            gradient_norms.push(gradient.iter().map(|g| g * g).sum::<f64>().sqrt());
+            for (w, g) in weights.iter_mut().zip(gradient.iter()) {
+                *w += self.learning_rate * g;
+            }
+            self.graph.set_parameters(&weights);
+        }
+
+        CdResult {
+            parameters: weights,
+            gradient_norms,
+            iterations: self.iterations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::{make_cycle, sample_exact};
+
+    #[test]
+    fn cd_and_pcd_approach_maximum_likelihood() {
+        let mut truth = make_cycle();
+        truth.set_parameters(&[0.5, -0.3, 0.2, 0.0, 0.8]);
+        let data = sample_exact(&truth, 1000, 7);
+        let mut mle_graph = make_cycle();
+        let mle = MaximumLikelihood::new(&mut mle_graph, &data).run();
+
+        let mut cd_graph = make_cycle();
+        let cd = ContrastiveDivergence::new(&mut cd_graph, &data).with_batch_size(100).with_learning_rate(0.2)
+            .with_iterations(300).run();
+        let mut pcd_graph = make_cycle();
+        let pcd = ContrastiveDivergence::new(&mut pcd_graph, &data).with_persistent_chains(100).with_batch_size(100)
+            .with_learning_rate(0.2).with_iterations(300).with_seed(1).run();
+
+        assert_eq!(cd.gradient_norms.len(), 300);
+        assert_eq!(pcd_graph.get_parameters(), pcd.parameters);
+        for (k, w) in mle.parameters.iter().enumerate() {
+            assert!((cd.parameters[k] - w).abs() < 0.15);
+            assert!((pcd.parameters[k] - w).abs() < 0.15);
+        }
+    }
+
+    #[test]
+    fn persistent_chains_fit_strong_coupling_and_decay_weights() {
+        let mut truth = make_cycle();
+        truth.set_parameters(&[0.1, 0.1, -0.2, 0.0, 1.0]);
+        let data = sample_exact(&truth, 1000, 7);
+        let mut mle_graph = make_cycle();
+        let mle = MaximumLikelihood::new(&mut mle_graph, &data).run();
+
+        let mut pcd_graph = make_cycle();
+        let pcd = ContrastiveDivergence::new(&mut pcd_graph, &data).with_persistent_chains(100).with_batch_size(100)
+            .with_learning_rate(0.2).with_iterations(300).run();
+        assert!((pcd.parameters[4] - mle.parameters[4]).abs() < 0.15);
+
+        let mut l2_graph = make_cycle();
+        let l2 = ContrastiveDivergence::new(&mut l2_graph, &data).with_persistent_chains(100).with_batch_size(100)
+            .with_learning_rate(0.2).with_iterations(300).with_l2(1.0).run();
+        let norm = |w: &[f64]| w.iter().map(|x| x * x).sum::<f64>();
+        assert!(norm(&l2.parameters) < norm(&pcd.parameters));
+    }
+}
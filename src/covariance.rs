@@ -0,0 +1,146 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with recovery of marginal and joint covariances from a square-root information factor
+
+use *;
+
+/// Struct recovering blocks of the covariance of a Gaussian Bayes net without forming the dense inverse.
+///
+/// Each conditional `R_i x_i + sum_k S_ik x_k = d_i` gives, for variables `j` eliminated no earlier than `i`,
+///
+/// `Cov(i, j) = -R_i^-1 sum_k S_ik Cov(k, j)` when `j != i`, and
+/// `Cov(i, i) = R_i^-1 R_i^-T - R_i^-1 sum_k S_ik Cov(k, i)`,
+///
+/// so only the blocks reachable through the parents of the requested variables are ever computed. Computed
+/// blocks are cached, which makes later queries that share them cheap.
+#[derive(Debug)]
+pub struct CovarianceRecovery<'a> {
+    bayes_net: &'a GaussianBayesNet,
+    positions: HashMap<String, usize>,
+    r_inverses: Vec<Matrix>,
+    cache: HashMap<(usize, usize), Matrix>,
+}
+
+impl<'a> CovarianceRecovery<'a> {
+    /// Create a new CovarianceRecovery for the Bayes net, such as one from `NonlinearOptimizer::eliminate`.
+    pub fn new(bayes_net: &'a GaussianBayesNet) -> CovarianceRecovery<'a> {
+        let conditionals = bayes_net.get_conditionals();
+        CovarianceRecovery {
+            bayes_net,
+            positions: conditionals.iter().enumerate().map(|(i, c)| (c.get_variable().clone(), i)).collect(),
+            r_inverses: conditionals.iter().map(|c| c.get_r().inverse().unwrap()).collect(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Get the marginal covariance matrix of a variable.
+    pub fn marginal_covariance(&mut self, variable: &str) -> Matrix {
+        let i = self.position(variable);
+        self.block(i, i)
+    }
+
+    /// Get the joint covariance matrix of several variables, with their components stacked in the given order.
+    pub fn joint_covariance(&mut self, variables: &[&str]) -> Matrix {
+        let positions: Vec<usize> = variables.iter().map(|v| self.position(v)).collect();
+        let dimensions: Vec<usize> = positions.iter()
+            .map(|&i| self.bayes_net.get_conditionals()[i].get_d().len())
+            .collect();
+
+        let size = dimensions.iter().sum();
+        let mut covariance = Matrix::zeros(size, size);
+        let mut row = 0;
+        for (&i, &rows) in positions.iter().zip(dimensions.iter()) {
+            let mut col = 0;
+            for (&j, &cols) in positions.iter().zip(dimensions.iter()) {
+                covariance.add_block(row, col, &self.block(i, j));
+                col += cols;
+            }
+            row += rows;
+        }
+        covariance
+    }
+
+    /// Get the elimination position of a variable.
+    fn position(&self, variable: &str) -> usize {
+        match self.positions.get(variable) {
+            Some(&i) => i,
+            None => panic!("The variable {} is not in the Bayes net.", variable)
+        }
+    }
+
+    /// Get the covariance block between the variables at elimination positions `i` and `j`.
+    fn block(&mut self, i: usize, j: usize) -> Matrix {
+        if i > j {
+            return self.block(j, i).transpose();
+        }
+        if let Some(cached) = self.cache.get(&(i, j)) {
+            return cached.clone();
+        }
+
+        let conditional = &self.bayes_net.get_conditionals()[i];
+        let mut sum = Matrix::zeros(conditional.get_d().len(), self.bayes_net.get_conditionals()[j].get_d().len());
+        for (parent, s) in conditional.get_parents().iter().zip(conditional.get_s().iter()) {
+            let k = self.position(parent);
+            sum = &sum + &(s * &self.block(k, j));
+        }
+
+        let r_inverse = &self.r_inverses[i];
+        let result = if i == j {
+            &(r_inverse * &r_inverse.transpose()) - &(r_inverse * &sum)
+        } else {
+            (r_inverse * &sum).scaled(-1.0)
+        };
+
+        self.cache.insert((i, j), result.clone());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factor(variables: &[&str], dimensions: Vec<usize>, precision: &[Vec<f64>]) -> GaussianFactor {
+        let size = precision.len();
+        GaussianFactor::new(variables.iter().map(|v| String::from(*v)).collect(), dimensions,
+                            GaussianPotential::new(Matrix::from_rows(precision), vec![0.0; size]))
+    }
+
+    #[test]
+    fn covariances_match_dense_inverse() {
+        let factors = vec!(
+            factor(&["x"], vec!(2), &[vec!(2.0, 0.3), vec!(0.3, 1.0)]),
+            factor(&["x", "y"], vec!(2, 1), &[vec!(1.0, 0.0, -1.0), vec!(0.0, 0.5, 0.2), vec!(-1.0, 0.2, 1.5)]),
+            factor(&["y", "z"], vec!(1, 1), &[vec!(1.0, -1.0), vec!(-1.0, 1.0)]),
+            factor(&["z"], vec!(1), &[vec!(0.5)]),
+        );
+        let dense = Matrix::from_rows(&[vec!(3.0, 0.3, -1.0, 0.0), vec!(0.3, 1.5, 0.2, 0.0),
+                                        vec!(-1.0, 0.2, 2.5, -1.0), vec!(0.0, 0.0, -1.0, 1.5)]);
+        let expected = dense.inverse().unwrap();
+
+        let order: Vec<String> = ["x", "y", "z"].iter().map(|v| String::from(*v)).collect();
+        let bayes_net = GaussianBayesNet::eliminate(factors, &order).unwrap();
+        let mut recovery = CovarianceRecovery::new(&bayes_net);
+
+        let x = recovery.marginal_covariance("x");
+        for i in 0..2 {
+            for j in 0..2 {
+                assert!((x[(i, j)] - expected[(i, j)]).abs() < 1e-12);
+            }
+        }
+
+        // Joint blocks in a different order than the elimination, including the non-adjacent pair z, x.
+        let joint = recovery.joint_covariance(&["z", "x"]);
+        let stacked = [3, 0, 1];
+        for (a, &i) in stacked.iter().enumerate() {
+            for (b, &j) in stacked.iter().enumerate() {
+                assert!((joint[(a, b)] - expected[(i, j)]).abs() < 1e-12);
+            }
+        }
+    }
+}
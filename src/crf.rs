@@ -0,0 +1,271 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with conditional random fields, whose structure and features are built from each input
+
+use std::rc::Rc;
+use std::slice;
+
+use *;
+use mle::{FeatureTables, add_expected_statistics, complete_states};
+use model::Model;
+
+type TemplateFunc<X> = dyn Fn(&X, &mut FactorGraph);
+
+/// Struct representing a conditional random field `p(y | x)` over discrete labels `y` given inputs of type `X`.
+///
+/// The template adds the label variables and factors for one input to a graph that already holds the field's
+/// parameters, so its log-linear factors can have features computed from the input while sharing weights with
+/// every other instance. Factors that are not log-linear are held fixed by training.
+#[derive(Clone)]
+pub struct ConditionalRandomField<X> {
+    template: Rc<TemplateFunc<X>>,
+    parameters: Vec<f64>,
+}
+
+impl<X> ConditionalRandomField<X> {
+    /// Create a new ConditionalRandomField with `num_parameters` weights, all zero, and the given template.
+    ///
+    /// The template must not add parameters of its own to the graph.
+    pub fn new<F: Fn(&X, &mut FactorGraph) + 'static>(num_parameters: usize, template: F) -> ConditionalRandomField<X> {
+        ConditionalRandomField {
+            template: Rc::new(template),
+            parameters: vec![0.0; num_parameters],
+        }
+    }
+
+    /// Get the current value of every parameter.
+    pub fn get_parameters(&self) -> Vec<f64> {
+        self.parameters.clone()
+    }
+
+    /// Set every parameter.
+    pub fn set_parameters(&mut self, parameters: &[f64]) {
+        if parameters.len() != self.parameters.len() {
+            panic!("The field has {} parameters, got {}", self.parameters.len(), parameters.len());
+        }
+
+        self.parameters.copy_from_slice(parameters);
+    }
+
+    /// Build the graph over the labels of one input, under the current parameters.
+    pub fn instance(&self, input: &X) -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        for &w in self.parameters.iter() {
+            graph.add_parameter(w);
+        }
+
+        (self.template)(input, &mut graph);
+        if graph.num_parameters() != self.parameters.len() {
+            panic!("The template added {} parameters to the field's {}", graph.num_parameters() - self.parameters.len(),
+                   self.parameters.len());
+        }
+        graph
+    }
+
+    /// Get the most probable labels for an input, which on a chain is found by the Viterbi algorithm.
+    pub fn predict(&self, input: &X) -> Assignment {
+        let graph = self.instance(input);
+        VariableElimination::new(&graph).map(&Assignment::new()).0
+    }
+
+    /// Get the marginal distribution of every label given an input.
+    pub fn marginals(&self, input: &X, method: InferenceMethod) -> Marginals {
+        let graph = self.instance(input);
+        match method {
+            InferenceMethod::Exact => {
+                let tree = JunctionTree::new(&graph, &Assignment::new());
+                graph.get_variable_names().into_iter()
+                    .map(|var| {
+                        let marginal = tree.marginal(slice::from_ref(&var)).unwrap().probabilities();
+                        (var, marginal)
+                    })
+                    .collect()
+            },
+            InferenceMethod::BeliefPropagation => BeliefPropagation::new(&graph).run().marginals
+        }
+    }
+}
+
+impl<X> std::fmt::Debug for ConditionalRandomField<X> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ConditionalRandomField {{ parameters: {:?}, <template> }}", self.parameters)
+    }
+}
+
+/// Struct fitting the parameters of a conditional random field by maximizing the conditional log-likelihood.
+///
+/// The objective is the average of `-log p(y | x)` over the labelled inputs plus `l2 / 2 * |w|^2 + l1 * |w|_1`.
+/// Its gradient needs the expected features of every instance, which exact inference gives efficiently on
+/// chains and trees. The same method is used for every instance, and exact inference builds a junction tree
+/// whose cost is exponential in the instance's treewidth, so callers should choose belief propagation, which
+/// gives approximate expectations, when the template makes instances with many cycles.
+#[derive(Debug)]
+pub struct ConditionalLikelihood<'a, X: 'a> {
+    crf: &'a mut ConditionalRandomField<X>,
+    data: &'a [(X, Assignment)],
+    method: InferenceMethod,
+    l2: f64,
+    l1: f64,
+    optimizer: Lbfgs,
+}
+
+impl<'a, X> ConditionalLikelihood<'a, X> {
+    /// Create a new ConditionalLikelihood fitting the field to inputs paired with a complete labelling.
+    ///
+    /// Defaults to exact inference without regularization, starting from the field's current parameters. The
+    /// default is not changed for loopy templates; set `InferenceMethod::BeliefPropagation` for those.
+    pub fn new(crf: &'a mut ConditionalRandomField<X>, data: &'a [(X, Assignment)]) -> ConditionalLikelihood<'a, X> {
+        if data.is_empty() {
+            panic!("Conditional likelihood needs at least one labelled input");
+        }
+
+        ConditionalLikelihood {
+            crf,
+            data,
+            method: InferenceMethod::Exact,
+            l2: 0.0,
+            l1: 0.0,
+            optimizer: Lbfgs::new(),
+        }
+    }
+
+    /// Set how the expected features of each instance are computed.
+    pub fn with_method(mut self, method: InferenceMethod) -> ConditionalLikelihood<'a, X> {
+        self.method = method;
+        self
+    }
+
+    /// Add the penalty `l2 / 2 * |w|^2` to the objective.
+    pub fn with_l2(mut self, l2: f64) -> ConditionalLikelihood<'a, X> {
+        if l2 < 0.0 {
+            panic!("The L2 penalty must be non-negative, got {}", l2);
+        }
+
+        self.l2 = l2;
+        self
+    }
+
+    /// Add the penalty `l1 * |w|_1` to the objective.
+    pub fn with_l1(mut self, l1: f64) -> ConditionalLikelihood<'a, X> {
+        self.optimizer = self.optimizer.with_l1(l1);
+        self.l1 = l1;
+        self
+    }
+
+    /// Set the maximum number of optimizer iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> ConditionalLikelihood<'a, X> {
+        self.optimizer = self.optimizer.with_max_iterations(max_iterations);
+        self
+    }
+
+    /// Set the gradient norm under which the optimizer stops.
+    pub fn with_tolerance(mut self, tolerance: f64) -> ConditionalLikelihood<'a, X> {
+        self.optimizer = self.optimizer.with_tolerance(tolerance);
+        self
+    }
+
+    /// Fit the parameters, leaving the field with the fitted parameters.
+    pub fn run(self) -> TrainingResult {
+        let scale = 1.0 / self.data.len() as f64;
+        let mut empirical = vec![0.0; self.crf.parameters.len()];
+        let mut fixed = 0.0;
+        let mut instances: Vec<(FactorGraph, FeatureTables)> = self.data.iter()
+            .map(|(input, labels)| {
+                let graph = self.crf.instance(input);
+                let model = Model::new(&graph);
+                let features = FeatureTables::new(&graph, &model);
+                let state = &complete_states(&model, slice::from_ref(labels))[0];
+
+                features.add_state(&model, state, scale, &mut empirical);
+                fixed += scale * (0..model.tables.len())
+                    .filter(|f| !features.factors.contains(f))
+                    .map(|f| model.factor_log_potential(f, state))
+                    .sum::<f64>();
+                (graph, features)
+            })
+            .collect();
+
+        let (method, l2) = (self.method, self.l2);
+        let initial = self.crf.get_parameters();
+        let result = self.optimizer.minimize(&initial, |parameters| {
+            let mut gradient: Vec<f64> = empirical.iter().zip(parameters.iter()).map(|(e, w)| l2 * w - e).collect();
+            let mut log_partition = 0.0;
+            for (graph, features) in instances.iter_mut() {
+                graph.set_parameters(parameters);
+                log_partition += scale * add_expected_statistics(graph, features, method, scale, &mut gradient);
+            }
+
+            let linear: f64 = parameters.iter().zip(empirical.iter()).map(|(w, e)| w * e).sum();
+            let penalty: f64 = parameters.iter().map(|w| 0.5 * l2 * w * w).sum();
+            (log_partition - linear - fixed + penalty, gradient)
+        });
+
+        self.crf.set_parameters(&result.parameters);
+        TrainingResult::from_minimization(result, self.l2, self.l1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::Rng;
+    use test_support::{make_tagger, sample_exact};
+
+    /// Draw labelled sequences of five random observations from the tagger with the given parameters.
+    fn sample(parameters: &[f64], num_samples: usize) -> Vec<(Vec<f64>, Assignment)> {
+        let mut crf = make_tagger();
+        crf.set_parameters(parameters);
+        let mut rng = Rng::new(11);
+        (0..num_samples)
+            .map(|_| {
+                let observations: Vec<f64> = (0..5).map(|_| 2.0 * rng.next_gaussian()).collect();
+                let labels = sample_exact(&crf.instance(&observations), 1, rng.next_u64()).remove(0);
+                (observations, labels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn training_recovers_the_parameters() {
+        let truth = [1.5, -0.5, 0.8];
+        let data = sample(&truth, 400);
+
+        let mut crf = make_tagger();
+        let result = ConditionalLikelihood::new(&mut crf, &data).run();
+        assert!(result.converged);
+        assert_eq!(crf.get_parameters(), result.parameters);
+        for (w, v) in result.parameters.iter().zip(truth.iter()) {
+            assert!((w - v).abs() < 0.2);
+        }
+
+        // Belief propagation is exact on chains.
+        let mut bp_crf = make_tagger();
+        let bp = ConditionalLikelihood::new(&mut bp_crf, &data).with_method(InferenceMethod::BeliefPropagation).run();
+        assert!((bp.log_likelihood - result.log_likelihood).abs() < 1e-8);
+        for (w, v) in bp.parameters.iter().zip(result.parameters.iter()) {
+            assert!((w - v).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn prediction_agrees_with_enumeration() {
+        let mut crf = make_tagger();
+        crf.set_parameters(&[1.0, 0.2, 1.2]);
+        let observations = vec!(0.5, -1.0, -0.2, 0.9, 1.4, -0.1);
+
+        let exact = Enumeration::new(&crf.instance(&observations)).run().unwrap();
+        assert_eq!(crf.predict(&observations), exact.map_assignment);
+
+        for method in [InferenceMethod::Exact, InferenceMethod::BeliefPropagation].iter() {
+            let marginals = crf.marginals(&observations, *method);
+            for (var, marginal) in exact.marginals.iter() {
+                assert!((marginals[var][1] - marginal[1]).abs() < 1e-8);
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with Bayesian estimation of table factors under Dirichlet priors
+
+use *;
+use mle::{complete_states, network_tables};
+use model::Model;
+
+/// Compute the log of the gamma function for positive arguments, by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8,
+                                    771.323_428_777_653_1, -176.615_029_162_140_6, 12.507_343_278_686_905,
+                                    -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6, 1.505_632_735_149_311_6e-7];
+    if x < 0.5 {
+        // Reflection formula.
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFICIENTS[1..].iter().enumerate()
+        .fold(COEFFICIENTS[0], |acc, (i, c)| acc + c / (x + i as f64 + 1.0));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+/// Enum representing a Dirichlet prior over every conditional distribution of a table factor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DirichletPrior {
+    /// The same pseudo-count on every entry of every table.
+    Uniform(f64),
+
+    /// The BDeu prior with the given equivalent sample size, spread evenly over the entries of each table.
+    ///
+    /// Every table gets the same total pseudo-count, so Markov equivalent networks score the same.
+    Bdeu(f64),
+}
+
+impl DirichletPrior {
+    /// Get the pseudo-count of every entry of a table with `size` entries.
+    fn pseudo_count(&self, size: usize) -> f64 {
+        match *self {
+            DirichletPrior::Uniform(alpha) => alpha,
+            DirichletPrior::Bdeu(equivalent_sample_size) => equivalent_sample_size / size as f64
+        }
+    }
+}
+
+/// Struct holding the result of Bayesian estimation.
+#[derive(Clone, Debug)]
+pub struct BayesianResult {
+    /// Indices of the table factors that were estimated, among the graph's factors.
+    pub factors: Vec<usize>,
+
+    /// Log marginal likelihood of the data with the tables integrated out under the prior.
+    pub log_marginal_likelihood: f64,
+
+    /// Average log-likelihood of the data under the posterior mean tables, or zero without data.
+    pub log_likelihood: f64,
+}
+
+/// Struct estimating the table factors of a Bayesian network from data under Dirichlet priors.
+///
+/// Every table factor is read as the conditional distribution of its last variable given the others, with an
+/// independent Dirichlet prior on each of its conditionals. The posterior is Dirichlet with the counts from the
+/// data added to the pseudo-counts, and each table is replaced by its posterior mean, which unlike the
+/// maximum-likelihood estimate never gives probability zero to values the data misses. The marginal likelihood
+/// is the Bayesian-Dirichlet score of the network, for comparing structures. The graph must be a normalized
+/// Bayesian network, so every variable must have exactly one table, the tables may not have a directed cycle from
+/// parents to children, and no other factor may touch a variable. Without data the posterior is the prior and the
+/// log marginal likelihood is zero.
+#[derive(Debug)]
+pub struct BayesianEstimation<'a> {
+    graph: &'a mut FactorGraph,
+    data: &'a [Assignment],
+    prior: DirichletPrior,
+}
+
+impl<'a> BayesianEstimation<'a> {
+    /// Create a new BayesianEstimation from complete assignments, with a pseudo-count of one on every entry.
+    pub fn new(graph: &'a mut FactorGraph, data: &'a [Assignment]) -> BayesianEstimation<'a> {
+        BayesianEstimation {
+            graph,
+            data,
+            prior: DirichletPrior::Uniform(1.0),
+        }
+    }
+
+    /// Set the prior.
+    pub fn with_prior(mut self, prior: DirichletPrior) -> BayesianEstimation<'a> {
+        let strength = match prior {
+            DirichletPrior::Uniform(alpha) => alpha,
+            DirichletPrior::Bdeu(equivalent_sample_size) => equivalent_sample_size
+        };
+        if strength <= 0.0 {
+            panic!("Dirichlet priors need positive pseudo-counts, got {:?}", prior);
+        }
+
+        self.prior = prior;
+        self
+    }
+
+    /// Estimate the tables, leaving the graph with the posterior mean tables.
+    pub fn run(self) -> BayesianResult {
+        let model = Model::new(self.graph);
+        let states = complete_states(&model, self.data);
+        let factors = network_tables(self.graph);
+        for (var, name) in model.names.iter().enumerate() {
+            if !factors.iter().any(|&f| model.scopes[f].last() == Some(&var)) {
+                panic!("The variable {} is not the last variable of a table factor", name);
+            }
+        }
+
+        let mut log_marginal_likelihood = 0.0;
+        for &f in factors.iter() {
+            let table = &model.tables[f];
+            let mut counts = vec![0.0; table.size()];
+            for state in states.iter() {
+                let values: Vec<u32> = model.scopes[f].iter().map(|&var| state[var]).collect();
+                counts[table.index_of(&values)] += 1.0;
+            }
+
+            let alpha = self.prior.pseudo_count(table.size());
+            let card = table.get_cardinalities()[table.get_cardinalities().len() - 1];
+            let mut log_values = Vec::with_capacity(table.size());
+            for row in counts.chunks(card) {
+                let total: f64 = row.iter().sum();
+                let row_alpha = alpha * card as f64;
+                log_marginal_likelihood += ln_gamma(row_alpha) - ln_gamma(row_alpha + total);
+                for &count in row.iter() {
+                    log_marginal_likelihood += ln_gamma(alpha + count) - ln_gamma(alpha);
+                    log_values.push(((count + alpha) / (total + row_alpha)).ln());
+                }
+            }
+            self.graph.set_table(f, Table::new(table.get_variables().clone(), table.get_cardinalities().clone(), log_values));
+        }
+
+        let posterior = Model::new(self.graph);
+        let log_likelihood = if states.is_empty() {
+            0.0
+        } else {
+            states.iter().map(|state| posterior.log_potential(state)).sum::<f64>() / states.len() as f64
+        };
+        BayesianResult {
+            factors,
+            log_marginal_likelihood,
+            log_likelihood,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::names;
+
+    fn make_data(states: &[[u32; 2]]) -> Vec<Assignment> {
+        states.iter().map(|state| names(&["a", "b"]).into_iter().zip(state.iter().cloned()).collect()).collect()
+    }
+
+    /// Build the network with an edge from `parent` to `child` over the binary variables a and b.
+    fn make_network(parent: &str, child: &str) -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        graph.add_discrete_var("a", vec![0, 1]);
+        graph.add_discrete_var("b", vec![0, 1]);
+        graph.add_table_factor(Table::uniform(names(&[parent]), vec!(2)));
+        graph.add_table_factor(Table::uniform(names(&[parent, child]), vec!(2, 2)));
+        graph
+    }
+
+    #[test]
+    fn uniform_prior_gives_laplace_smoothing() {
+        let mut graph = FactorGraph::new();
+        graph.add_discrete_var("a", vec![0, 1]);
+        graph.add_table_factor(Table::uniform(names(&["a"]), vec!(2)));
+        let data: Vec<Assignment> = [1, 1, 1, 0].iter().map(|&x| vec!((String::from("a"), x)).into_iter().collect()).collect();
+
+        let result = BayesianEstimation::new(&mut graph, &data).run();
+        assert_eq!(result.factors, vec!(0));
+
+        // With three ones and a zero, the marginal likelihood is 3! 1! / 5! and the posterior mean is 4 / 6.
+        assert!((result.log_marginal_likelihood - (6.0f64 / 120.0).ln()).abs() < 1e-10);
+        let exact = Enumeration::new(&graph).run().unwrap();
+        assert!((exact.marginals["a"][1] - 4.0 / 6.0).abs() < 1e-12);
+        assert!(exact.log_partition.abs() < 1e-12);
+    }
+
+    #[test]
+    fn bdeu_scores_equivalent_networks_equally() {
+        // The data never has a = 0 with b = 1, which maximum likelihood would make impossible.
+        let data = make_data(&[[0, 0], [0, 0], [1, 1], [1, 0], [1, 1], [1, 1]]);
+        let prior = DirichletPrior::Bdeu(2.0);
+
+        let mut forward = make_network("a", "b");
+        let a_to_b = BayesianEstimation::new(&mut forward, &data).with_prior(prior).run();
+        let mut backward = make_network("b", "a");
+        let b_to_a = BayesianEstimation::new(&mut backward, &data).with_prior(prior).run();
+        assert!((a_to_b.log_marginal_likelihood - b_to_a.log_marginal_likelihood).abs() < 1e-10);
+
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("a"), 0);
+        let exact = Enumeration::new(&forward).with_evidence(evidence).run().unwrap();
+        assert!((exact.marginals["b"][1] - 0.5 / 3.0).abs() < 1e-12);
+
+        // The K2 prior, a pseudo-count of one everywhere, is not score equivalent.
+        let mut forward = make_network("a", "b");
+        let k2_a_to_b = BayesianEstimation::new(&mut forward, &data).run();
+        let mut backward = make_network("b", "a");
+        let k2_b_to_a = BayesianEstimation::new(&mut backward, &data).run();
+        assert!((k2_a_to_b.log_marginal_likelihood - k2_b_to_a.log_marginal_likelihood).abs() > 1e-3);
+    }
+
+    #[test]
+    fn no_data_gives_the_prior() {
+        let mut graph = make_network("a", "b");
+        let result = BayesianEstimation::new(&mut graph, &[]).with_prior(DirichletPrior::Bdeu(2.0)).run();
+        assert_eq!(result.log_marginal_likelihood, 0.0);
+        assert_eq!(result.log_likelihood, 0.0);
+
+        let exact = Enumeration::new(&graph).run().unwrap();
+        assert!((exact.marginals["b"][1] - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "not the last variable")]
+    fn every_variable_needs_a_table() {
+        let mut graph = make_network("a", "b");
+        graph.add_discrete_var("c", vec![0, 1]);
+        let mut data = make_data(&[[0, 1]]);
+        data[0].insert(String::from("c"), 0);
+        BayesianEstimation::new(&mut graph, &data).run();
+    }
+}
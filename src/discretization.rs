@@ -0,0 +1,343 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with discretization of bounded scalar continuous variables into discrete variables
+
+use *;
+use table::log_sum_exp;
+
+/// Enum representing how the bounds of a variable are split into bins.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinningStrategy {
+    /// Bins of equal width.
+    Uniform,
+
+    /// Bins of equal mass under the density.
+    Quantile,
+
+    /// Bins refined by repeatedly halving the bin over which the density is worst approximated by a constant.
+    Adaptive,
+}
+
+/// Enum representing how the values of a discretized variable are named.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinLabel {
+    /// Name each value by the midpoint of its bin.
+    Midpoint,
+
+    /// Name each value by its bin as a half-open interval.
+    Interval,
+}
+
+/// Struct representing a partition of an interval into consecutive bins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binning {
+    edges: Vec<f64>,
+}
+
+impl Binning {
+    /// Create a new Binning from the edges of its bins, which must be strictly increasing.
+    pub fn from_edges(edges: Vec<f64>) -> Binning {
+        if edges.len() < 2 {
+            panic!("A binning needs at least two edges, got {}", edges.len());
+        }
+        if edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+            panic!("Bin edges must be strictly increasing, got {:?}", edges);
+        }
+
+        Binning {
+            edges,
+        }
+    }
+
+    /// Function to get the edges of the bins.
+    pub fn get_edges(&self) -> &Vec<f64> {
+        &self.edges
+    }
+
+    /// Get the number of bins.
+    pub fn num_bins(&self) -> usize {
+        self.edges.len() - 1
+    }
+
+    /// Get the midpoint of every bin.
+    pub fn midpoints(&self) -> Vec<f64> {
+        self.edges.windows(2).map(|pair| 0.5 * (pair[0] + pair[1])).collect()
+    }
+
+    /// Get the bin holding a value, or None if it is out of bounds.
+    ///
+    /// Every bin includes its lower edge, and the last bin also includes the upper bound.
+    pub fn bin_of(&self, x: f64) -> Option<u32> {
+        let last = self.edges.len() - 1;
+        if !(self.edges[0]..=self.edges[last]).contains(&x) {
+            return None;
+        }
+
+        let bin = self.edges.iter().skip(1).take_while(|&&edge| edge <= x).count();
+        Some(bin.min(last - 1) as u32)
+    }
+
+    /// Get `resolution` evenly spaced points inside a bin, at the midpoints of equal sub-intervals.
+    fn points(&self, bin: usize, resolution: usize) -> Vec<f64> {
+        let (lower, upper) = (self.edges[bin], self.edges[bin + 1]);
+        let step = (upper - lower) / resolution as f64;
+        (0..resolution).map(|k| lower + (k as f64 + 0.5) * step).collect()
+    }
+
+    /// Get the log of the integral of the density over a bin, by the midpoint rule.
+    fn log_mass<F: Fn(f64) -> f64>(&self, bin: usize, log_density: &F, resolution: usize) -> f64 {
+        let values: Vec<f64> = self.points(bin, resolution).into_iter().map(log_density).collect();
+        let width = self.edges[bin + 1] - self.edges[bin];
+        log_sum_exp(&values) + (width / resolution as f64).ln()
+    }
+}
+
+/// Tabulate a log-potential over discretized variables, averaging the potential over each cell of the bins.
+///
+/// The potential receives one value per variable, in the order of `variables`, and is evaluated at
+/// `resolution` points per variable in every cell. Averaging, rather than integrating, leaves the measure of
+/// each bin to the variable's own factor from `Discretizer::add_variable`, so that it is counted once however
+/// many factors the variable is in.
+pub fn tabulate<F: Fn(&[f64]) -> f64>(variables: Vec<String>, binnings: &[&Binning], log_potential: F,
+                                      resolution: usize) -> Table {
+    if variables.len() != binnings.len() {
+        panic!("Table has {} variables but {} binnings", variables.len(), binnings.len());
+    }
+    if resolution == 0 {
+        panic!("Tabulation needs at least one point per bin");
+    }
+
+    let cardinalities: Vec<usize> = binnings.iter().map(|b| b.num_bins()).collect();
+    let cells = Table::uniform(variables.clone(), cardinalities.clone());
+    let points_per_cell = resolution.pow(binnings.len() as u32);
+    let log_values = (0..cells.size())
+        .map(|index| {
+            let assignment = cells.assignment_at(index);
+            let points: Vec<Vec<f64>> = binnings.iter().zip(assignment.iter())
+                .map(|(binning, &bin)| binning.points(bin as usize, resolution))
+                .collect();
+
+            let values: Vec<f64> = (0..points_per_cell)
+                .map(|point| {
+                    let mut rest = point;
+                    let x: Vec<f64> = points.iter()
+                        .map(|p| {
+                            let k = rest % resolution;
+                            rest /= resolution;
+                            p[k]
+                        })
+                        .collect();
+                    log_potential(&x)
+                })
+                .collect();
+            log_sum_exp(&values) - (points_per_cell as f64).ln()
+        })
+        .collect();
+
+    Table::new(variables, cardinalities, log_values)
+}
+
+/// Struct discretizing a bounded scalar continuous variable, given the log of its density.
+///
+/// The density need not be normalized, and only its shape matters to the quantile and adaptive strategies.
+#[derive(Clone, Copy, Debug)]
+pub struct Discretizer {
+    lower: f64,
+    upper: f64,
+    num_bins: usize,
+    strategy: BinningStrategy,
+    label: BinLabel,
+    resolution: usize,
+}
+
+impl Discretizer {
+    /// Create a new Discretizer splitting `[lower, upper]` into the given number of bins.
+    ///
+    /// Defaults to uniform bins named by their midpoints, with 16 points per bin for integrals.
+    pub fn new(lower: f64, upper: f64, num_bins: usize) -> Discretizer {
+        if lower >= upper {
+            panic!("The lower bound {} must be below the upper bound {}", lower, upper);
+        }
+        if num_bins == 0 {
+            panic!("Discretization needs at least one bin");
+        }
+
+        Discretizer {
+            lower,
+            upper,
+            num_bins,
+            strategy: BinningStrategy::Uniform,
+            label: BinLabel::Midpoint,
+            resolution: 16,
+        }
+    }
+
+    /// Set how the bounds are split into bins.
+    pub fn with_strategy(mut self, strategy: BinningStrategy) -> Discretizer {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set how the values of the discrete variable are named.
+    pub fn with_label(mut self, label: BinLabel) -> Discretizer {
+        self.label = label;
+        self
+    }
+
+    /// Set the number of points per bin used to integrate the density and place the bins.
+    pub fn with_resolution(mut self, resolution: usize) -> Discretizer {
+        if resolution == 0 {
+            panic!("Discretization needs at least one point per bin");
+        }
+
+        self.resolution = resolution;
+        self
+    }
+
+    /// Split the bounds into bins for the given log-density.
+    pub fn binning<F: Fn(f64) -> f64>(&self, log_density: F) -> Binning {
+        match self.strategy {
+            BinningStrategy::Uniform => self.uniform(self.num_bins),
+            BinningStrategy::Quantile => self.quantile(&log_density),
+            BinningStrategy::Adaptive => self.adaptive(&log_density),
+        }
+    }
+
+    /// Add a discrete variable for the binned continuous variable to the graph, with a factor holding the
+    /// mass of the density over each bin.
+    ///
+    /// Factors coupling the variable to others can be added with `tabulate` and `FactorGraph::add_table_factor`.
+    pub fn add_variable<F: Fn(f64) -> f64>(&self, graph: &mut FactorGraph, name: &str, log_density: F) -> Binning {
+        let binning = self.binning(&log_density);
+        match self.label {
+            BinLabel::Midpoint => graph.add_discrete_var(name, binning.midpoints()),
+            BinLabel::Interval => {
+                let intervals: Vec<String> = binning.edges.windows(2)
+                    .map(|pair| format!("[{}, {})", pair[0], pair[1]))
+                    .collect();
+                graph.add_discrete_var(name, intervals)
+            }
+        }
+
+        let log_masses = (0..binning.num_bins())
+            .map(|bin| binning.log_mass(bin, &log_density, self.resolution))
+            .collect();
+        graph.add_table_factor(Table::new(vec!(String::from(name)), vec!(binning.num_bins()), log_masses));
+        binning
+    }
+
+    /// Split the bounds into bins of equal width.
+    fn uniform(&self, num_bins: usize) -> Binning {
+        let step = (self.upper - self.lower) / num_bins as f64;
+        let mut edges: Vec<f64> = (0..num_bins).map(|i| self.lower + i as f64 * step).collect();
+        edges.push(self.upper);
+        Binning::from_edges(edges)
+    }
+
+    /// Split the bounds at the quantiles of the density, interpolating its distribution function linearly
+    /// over a fine uniform grid.
+    fn quantile<F: Fn(f64) -> f64>(&self, log_density: &F) -> Binning {
+        let grid = self.uniform(self.num_bins * self.resolution * 4);
+        let log_masses: Vec<f64> = (0..grid.num_bins()).map(|cell| grid.log_mass(cell, log_density, 1)).collect();
+        let log_total = log_sum_exp(&log_masses);
+        if !log_total.is_finite() {
+            panic!("The density has no mass between {} and {}", self.lower, self.upper);
+        }
+
+        let mut edges = vec!(self.lower);
+        let mut cumulative = 0.0;
+        let mut cell = 0;
+        for i in 1..self.num_bins {
+            let target = i as f64 / self.num_bins as f64;
+            loop {
+                let mass = (log_masses[cell] - log_total).exp();
+                if cumulative + mass >= target || cell + 1 == grid.num_bins() {
+                    let fraction = if mass > 0.0 { ((target - cumulative) / mass).clamp(0.0, 1.0) } else { 0.5 };
+                    edges.push(grid.edges[cell] + fraction * (grid.edges[cell + 1] - grid.edges[cell]));
+                    break;
+                }
+                cumulative += mass;
+                cell += 1;
+            }
+        }
+        edges.push(self.upper);
+        Binning::from_edges(edges)
+    }
+
+    /// Start from a single bin and repeatedly halve the bin with the largest integrated absolute deviation of
+    /// the density from its mean over the bin.
+    fn adaptive<F: Fn(f64) -> f64>(&self, log_density: &F) -> Binning {
+        let deviation = |lower: f64, upper: f64| {
+            let binning = Binning { edges: vec!(lower, upper) };
+            let values: Vec<f64> = binning.points(0, self.resolution).into_iter().map(|x| log_density(x).exp()).collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            (upper - lower) * values.iter().map(|v| (v - mean).abs()).sum::<f64>() / values.len() as f64
+        };
+
+        let mut edges = vec!(self.lower, self.upper);
+        let mut deviations = vec!(deviation(self.lower, self.upper));
+        while deviations.len() < self.num_bins {
+            let worst = (0..deviations.len())
+                .fold(0, |best, bin| if deviations[bin] > deviations[best] { bin } else { best });
+            let middle = 0.5 * (edges[worst] + edges[worst + 1]);
+            let left = deviation(edges[worst], middle);
+            let right = deviation(middle, edges[worst + 1]);
+
+            edges.insert(worst + 1, middle);
+            deviations[worst] = left;
+            deviations.insert(worst + 1, right);
+        }
+        Binning::from_edges(edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standard_normal(x: f64) -> f64 {
+        -0.5 * x * x
+    }
+
+    #[test]
+    fn strategies_place_bins_by_density() {
+        let quantile = Discretizer::new(-6.0, 6.0, 4).with_strategy(BinningStrategy::Quantile).binning(standard_normal);
+        assert!(quantile.get_edges()[2].abs() < 1e-3);
+        assert!((quantile.get_edges()[3] - 0.6745).abs() < 1e-2);
+
+        let adaptive = Discretizer::new(-6.0, 6.0, 16).with_strategy(BinningStrategy::Adaptive).binning(standard_normal);
+        let widths: Vec<f64> = adaptive.get_edges().windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let center = adaptive.bin_of(0.1).unwrap() as usize;
+        assert!(widths[center] < widths[0]);
+        assert!(widths[center] < widths[widths.len() - 1]);
+
+        assert_eq!(adaptive.bin_of(6.0), Some(15));
+        assert_eq!(adaptive.bin_of(6.5), None);
+    }
+
+    #[test]
+    fn discretized_chain_matches_continuous_means() {
+        // The prior x ~ N(0, 1), y ~ N(x, 0.5) and an observation of y at 2 with variance 0.5 have
+        // posterior means 1 for x and 1.5 for y.
+        for &strategy in &[BinningStrategy::Uniform, BinningStrategy::Adaptive] {
+            let discretizer = Discretizer::new(-4.0, 6.0, 40).with_strategy(strategy);
+            let mut graph = FactorGraph::new();
+            let x = discretizer.add_variable(&mut graph, "x", standard_normal);
+            let y = discretizer.add_variable(&mut graph, "y", |y| -(y - 2.0) * (y - 2.0));
+            graph.add_table_factor(tabulate(vec!(String::from("x"), String::from("y")), &[&x, &y],
+                                            |v| -(v[1] - v[0]) * (v[1] - v[0]), 4));
+
+            let exact = Enumeration::new(&graph).run().unwrap();
+            let mean = |name: &str, binning: &Binning| -> f64 {
+                exact.marginals[name].iter().zip(binning.midpoints().iter()).map(|(p, m)| p * m).sum()
+            };
+            assert!((mean("x", &x) - 1.0).abs() < 0.05);
+            assert!((mean("y", &y) - 1.5).abs() < 0.05);
+        }
+    }
+}
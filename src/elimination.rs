@@ -0,0 +1,239 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with variable elimination functionality
+
+use std::collections::HashSet;
+
+use *;
+
+/// Compute an elimination order for `variables` with the greedy min-fill heuristic.
+///
+/// `scopes` holds the variables of each factor. At every step the variable whose elimination adds the
+/// fewest new edges to the interaction graph is chosen, breaking ties by the order of `variables`.
+pub fn min_fill_order(variables: &[String], scopes: &[Vec<String>]) -> Vec<String> {
+    let index_of = |name: &String| match variables.iter().position(|v| v == name) {
+        Some(x) => x,
+        None => panic!("The variable {} was not found in the elimination variables.", name)
+    };
+
+    let mut neighbours: Vec<HashSet<usize>> = vec![HashSet::new(); variables.len()];
+    for scope in scopes {
+        let indices: Vec<usize> = scope.iter().map(&index_of).collect();
+        for &i in indices.iter() {
+            for &j in indices.iter() {
+                if i != j {
+                    neighbours[i].insert(j);
+                }
+            }
+        }
+    }
+
+    let mut eliminated = vec![false; variables.len()];
+    let mut order = Vec::with_capacity(variables.len());
+
+    for _ in 0..variables.len() {
+        let mut best: Option<(usize, usize)> = None;
+        for var in (0..variables.len()).filter(|&v| !eliminated[v]) {
+            let adjacent: Vec<usize> = neighbours[var].iter().cloned().collect();
+            let mut fill = 0;
+            for (k, &a) in adjacent.iter().enumerate() {
+                fill += adjacent[k + 1..].iter().filter(|&&b| !neighbours[a].contains(&b)).count();
+            }
+
+            if best.is_none_or(|(_, best_fill)| fill < best_fill) {
+                best = Some((var, fill));
+            }
+        }
+
+        let (var, _) = best.unwrap();
+        let adjacent: Vec<usize> = neighbours[var].iter().cloned().collect();
+        for &a in adjacent.iter() {
+            neighbours[a].remove(&var);
+            for &b in adjacent.iter() {
+                if a != b {
+                    neighbours[a].insert(b);
+                }
+            }
+        }
+
+        eliminated[var] = true;
+        order.push(variables[var].clone());
+    }
+
+    order
+}
+
+/// Compute the clique formed by each variable and its neighbours when eliminating in `order`.
+pub(crate) fn elimination_cliques(order: &[String], scopes: &[Vec<String>]) -> Vec<Vec<String>> {
+    let mut neighbours: Vec<HashSet<String>> = vec![HashSet::new(); order.len()];
+    let position = |name: &String| order.iter().position(|v| v == name).unwrap();
+    for scope in scopes {
+        for a in scope.iter() {
+            neighbours[position(a)].extend(scope.iter().filter(|&b| b != a).cloned());
+        }
+    }
+
+    let mut cliques = Vec::with_capacity(order.len());
+    for (i, var) in order.iter().enumerate() {
+        // Only neighbours eliminated later are still in the graph.
+        let mut remaining: Vec<String> = neighbours[i].iter().filter(|n| position(n) > i).cloned().collect();
+        remaining.sort_by_key(|n| position(n));
+        for a in remaining.iter() {
+            neighbours[position(a)].extend(remaining.iter().filter(|&b| b != a).cloned());
+        }
+
+        let mut clique = vec!(var.clone());
+        clique.extend(remaining);
+        cliques.push(clique);
+    }
+
+    cliques
+}
+
+/// Tabulate every factor of the graph, fixing the observed variables to their values.
+pub(crate) fn evidence_tables(graph: &FactorGraph, evidence: &Assignment) -> Vec<Table> {
+    graph.get_factors().iter()
+        .map(|factor| {
+            let mut table = Table::from_factor(graph, factor);
+            for var in factor.get_variables() {
+                if let Some(&val) = evidence.get(var) {
+                    if table.contains(var) {
+                        table = table.restrict(var, val);
+                    }
+                }
+            }
+            table
+        })
+        .collect()
+}
+
+/// Struct answering queries by sum-product variable elimination, and MAP queries by max-product elimination.
+#[derive(Debug)]
+pub struct VariableElimination<'a> {
+    graph: &'a FactorGraph,
+}
+
+impl<'a> VariableElimination<'a> {
+    /// Create a new VariableElimination for the graph.
+    pub fn new(graph: &'a FactorGraph) -> VariableElimination<'a> {
+        VariableElimination {
+            graph,
+        }
+    }
+
+    /// Compute the unnormalized joint table over the unobserved `query` variables given the evidence.
+    ///
+    /// Every other unobserved variable is summed out in min-fill order, so the log partition function of
+    /// the result is the log partition function restricted to the evidence.
+    pub fn joint(&self, query: &[String], evidence: &Assignment) -> Table {
+        for var in query.iter() {
+            if evidence.contains_key(var) {
+                panic!("Query variable {} is also observed", var);
+            }
+        }
+
+        let mut pool = evidence_tables(self.graph, evidence);
+        let hidden: Vec<String> = self.graph.get_variable_names().into_iter()
+            .filter(|var| !evidence.contains_key(var) && !query.contains(var))
+            .collect();
+        let scopes: Vec<Vec<String>> = pool.iter()
+            .map(|t| t.get_variables().iter().filter(|v| hidden.contains(v)).cloned().collect())
+            .collect();
+
+        for var in min_fill_order(&hidden, &scopes) {
+            let (bucket, rest): (Vec<Table>, Vec<Table>) = pool.into_iter().partition(|t| t.contains(&var));
+            pool = rest;
+
+            let cardinality = self.graph.domain_size(&var);
+            let joint = bucket.iter().fold(Table::uniform(vec!(var.clone()), vec!(cardinality)), |acc, t| acc.product(t));
+            pool.push(joint.sum_out(&var));
+        }
+
+        let cardinalities = query.iter().map(|var| self.graph.domain_size(var)).collect();
+        pool.iter().fold(Table::uniform(query.to_vec(), cardinalities), |acc, t| acc.product(t))
+    }
+
+    /// Compute the distribution over the unobserved `query` variables conditioned on the evidence.
+    pub fn query(&self, query: &[String], evidence: &Assignment) -> Table {
+        self.joint(query, evidence).normalized()
+    }
+
+    /// Compute the most probable assignment to every variable given the evidence, and its log-potential.
+    ///
+    /// Every unobserved variable is maximized out in min-fill order, keeping the table of each bucket, and the
+    /// values are then read back in reverse order. On a chain this is the Viterbi algorithm.
+    pub fn map(&self, evidence: &Assignment) -> (Assignment, f64) {
+        let mut pool = evidence_tables(self.graph, evidence);
+        let hidden: Vec<String> = self.graph.get_variable_names().into_iter()
+            .filter(|var| !evidence.contains_key(var))
+            .collect();
+        let scopes: Vec<Vec<String>> = pool.iter().map(|t| t.get_variables().clone()).collect();
+
+        let mut buckets = vec!();
+        for var in min_fill_order(&hidden, &scopes) {
+            let (bucket, rest): (Vec<Table>, Vec<Table>) = pool.into_iter().partition(|t| t.contains(&var));
+            pool = rest;
+
+            let cardinality = self.graph.domain_size(&var);
+            let joint = bucket.iter().fold(Table::uniform(vec!(var.clone()), vec!(cardinality)), |acc, t| acc.product(t));
+            pool.push(joint.max_out(&var));
+            buckets.push((var, joint));
+        }
+        let log_potential = pool.iter().map(|t| t.get_log_values()[0]).sum();
+
+        let mut assignment = evidence.clone();
+        for (var, joint) in buckets.into_iter().rev() {
+            let slice = joint.get_variables().iter()
+                .filter(|v| **v != var)
+                .fold(joint.clone(), |table, v| table.restrict(v, assignment[v]));
+            let best = (0..slice.size()).fold(0, |best, val| {
+                if slice.get_log_values()[val] > slice.get_log_values()[best] { val } else { best }
+            });
+            assignment.insert(var, best as u32);
+        }
+        (assignment, log_potential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::names;
+
+    #[test]
+    fn chain_is_eliminated_from_the_ends() {
+        let variables = names(&["a", "b", "c", "d"]);
+        let scopes = vec!(names(&["b", "c"]), names(&["a", "b"]), names(&["c", "d"]));
+
+        let order = min_fill_order(&variables, &scopes);
+        assert_eq!(order.len(), 4);
+        assert!(order[0] == "a" || order[0] == "d");
+    }
+
+    #[test]
+    fn max_product_agrees_with_enumeration() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d"] {
+            graph.add_discrete_var(name, vec![0, 1, 2]);
+        }
+        let tables = [(names(&["a", "b"]), 1.0), (names(&["b", "c"]), -0.5), (names(&["c", "d"]), 2.0),
+                      (names(&["d", "a"]), 0.3), (names(&["b"]), 0.7)];
+        for &(ref vars, scale) in tables.iter() {
+            let size = 3usize.pow(vars.len() as u32);
+            let log_values = (0..size).map(|i| scale * ((i * 7 % 5) as f64 - 2.0)).collect();
+            graph.add_table_factor(Table::new(vars.clone(), vec![3; vars.len()], log_values));
+        }
+
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("c"), 1);
+        let (assignment, log_potential) = VariableElimination::new(&graph).map(&evidence);
+        let exact = Enumeration::new(&graph).with_evidence(evidence).run().unwrap();
+        assert_eq!(assignment, exact.map_assignment);
+        assert!((log_potential - exact.map_log_potential).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,243 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with exact inference by enumerating every joint assignment
+
+use std::error::Error;
+use std::fmt;
+
+use *;
+use model::Model;
+use table::log_sum_exp;
+
+/// Error returned when the joint state space of a graph is larger than the enumeration limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StateSpaceTooLarge {
+    /// Number of joint assignments that would have to be enumerated.
+    pub num_states: u128,
+
+    /// Largest number of joint assignments the enumeration was allowed to visit.
+    pub limit: usize,
+}
+
+impl fmt::Display for StateSpaceTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "state space of {} assignments exceeds the enumeration limit of {}", self.num_states, self.limit)
+    }
+}
+
+impl Error for StateSpaceTooLarge {}
+
+/// Enum representing the ways enumeration can fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnumerationError {
+    /// The joint state space is larger than the enumeration limit.
+    TooLarge(StateSpaceTooLarge),
+
+    /// Every assignment consistent with the evidence has zero potential, so there is no distribution to report.
+    InconsistentEvidence,
+}
+
+impl fmt::Display for EnumerationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EnumerationError::TooLarge(ref error) => error.fmt(f),
+            EnumerationError::InconsistentEvidence => {
+                write!(f, "every assignment consistent with the evidence has zero potential")
+            }
+        }
+    }
+}
+
+impl Error for EnumerationError {}
+
+/// Struct holding the exact results of inference on a factor graph.
+#[derive(Clone, Debug)]
+pub struct ExactResult {
+    /// Log partition function, summing only over assignments consistent with the evidence.
+    pub log_partition: f64,
+
+    /// Marginal distribution of every variable given the evidence.
+    pub marginals: Marginals,
+
+    /// Most probable assignment to every variable given the evidence.
+    pub map_assignment: Assignment,
+
+    /// Unnormalized log-probability of the most probable assignment.
+    pub map_log_potential: f64,
+}
+
+/// Struct computing exact marginals, MAP and log partition function by brute-force enumeration.
+///
+/// This is only feasible for small graphs, but gives a trusted ground truth for the other algorithms.
+#[derive(Debug)]
+pub struct Enumeration<'a> {
+    graph: &'a FactorGraph,
+    evidence: Assignment,
+    max_states: usize,
+}
+
+impl<'a> Enumeration<'a> {
+    /// Create a new Enumeration for the graph, refusing state spaces of more than 2^20 assignments.
+    pub fn new(graph: &'a FactorGraph) -> Enumeration<'a> {
+        Enumeration {
+            graph,
+            evidence: Assignment::new(),
+            max_states: 1 << 20,
+        }
+    }
+
+    /// Clamp the given variables to observed values.
+    pub fn with_evidence(mut self, evidence: Assignment) -> Enumeration<'a> {
+        self.evidence = evidence;
+        self
+    }
+
+    /// Set the largest number of joint assignments to enumerate.
+    pub fn with_max_states(mut self, max_states: usize) -> Enumeration<'a> {
+        self.max_states = max_states;
+        self
+    }
+
+    /// Enumerate every assignment consistent with the evidence.
+    ///
+    /// Fails if the state space is too large, or if the partition function is zero.
+    pub fn run(&self) -> Result<ExactResult, EnumerationError> {
+        let model = Model::new(self.graph);
+        let clamped = model.clamped(&self.evidence);
+        let free: Vec<usize> = (0..model.names.len()).filter(|&var| clamped[var].is_none()).collect();
+
+        let num_states = free.iter()
+            .fold(1u128, |acc, &var| acc.saturating_mul(model.cardinalities[var] as u128));
+        if num_states > self.max_states as u128 {
+            return Err(EnumerationError::TooLarge(StateSpaceTooLarge { num_states, limit: self.max_states }));
+        }
+
+        let initial: Vec<u32> = clamped.iter().map(|clamp| clamp.unwrap_or(0)).collect();
+
+        // First pass finds the partition function and MAP, the second accumulates the marginals.
+        let mut log_values = Vec::with_capacity(num_states as usize);
+        let mut state = initial.clone();
+        let mut map_state = state.clone();
+        let mut map_log_potential = f64::NEG_INFINITY;
+        loop {
+            let log_value = model.log_potential(&state);
+            if log_value > map_log_potential {
+                map_log_potential = log_value;
+                map_state = state.clone();
+            }
+            log_values.push(log_value);
+
+            if !advance(&mut state, &free, &model.cardinalities) {
+                break;
+            }
+        }
+
+        let log_partition = log_sum_exp(&log_values);
+        if log_partition == f64::NEG_INFINITY {
+            return Err(EnumerationError::InconsistentEvidence);
+        }
+
+        let mut marginals: Vec<Vec<f64>> = model.cardinalities.iter().map(|&card| vec![0.0; card]).collect();
+        let mut state = initial;
+        for log_value in log_values {
+            let prob = (log_value - log_partition).exp();
+            for (var, &val) in state.iter().enumerate() {
+                marginals[var][val as usize] += prob;
+            }
+            advance(&mut state, &free, &model.cardinalities);
+        }
+
+        Ok(ExactResult {
+            log_partition,
+            marginals: model.names.iter().cloned().zip(marginals).collect(),
+            map_assignment: model.to_assignment(&map_state),
+            map_log_potential,
+        })
+    }
+}
+
+/// Step the free variables of `state` to the next joint assignment, returning false once all have been seen.
+fn advance(state: &mut [u32], free: &[usize], cardinalities: &[usize]) -> bool {
+    for &var in free.iter().rev() {
+        state[var] += 1;
+        if (state[var] as usize) < cardinalities[var] {
+            return true;
+        }
+        state[var] = 0;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefer_one(vals: &[u32]) -> i32 {
+        vals[0] as i32 + 1
+    }
+
+    fn agree(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 2 } else { 1 }
+    }
+
+    fn make_pair() -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        graph.add_discrete_var("a", vec![0, 1]);
+        graph.add_discrete_var("b", vec!["x", "y", "z"]);
+        graph.add_factor::<i32>(vec!(String::from("a")), prefer_one);
+        graph.add_factor::<i32>(vec!(String::from("a"), String::from("b")), agree);
+        graph
+    }
+
+    #[test]
+    fn enumerates_small_graph() {
+        let graph = make_pair();
+        let result = Enumeration::new(&graph).run().unwrap();
+
+        // a = 0 contributes 1 * (2 + 1 + 1) and a = 1 contributes 2 * (1 + 2 + 1).
+        assert!((result.log_partition - 12f64.ln()).abs() < 1e-12);
+        assert!((result.marginals["a"][1] - 8.0 / 12.0).abs() < 1e-12);
+        assert!((result.marginals["b"][0] - 4.0 / 12.0).abs() < 1e-12);
+        assert_eq!(result.map_assignment["a"], 1);
+        assert_eq!(result.map_assignment["b"], 1);
+        assert!((result.map_log_potential - 4f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn evidence_restricts_the_sum() {
+        let graph = make_pair();
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("b"), 2);
+
+        let result = Enumeration::new(&graph).with_evidence(evidence).run().unwrap();
+        assert!((result.log_partition - 3f64.ln()).abs() < 1e-12);
+        assert_eq!(result.marginals["b"], vec!(0.0, 0.0, 1.0));
+        assert!((result.marginals["a"][0] - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn refuses_large_state_spaces() {
+        let graph = make_pair();
+
+        assert_eq!(Enumeration::new(&graph).with_max_states(5).run().unwrap_err(),
+                   EnumerationError::TooLarge(StateSpaceTooLarge { num_states: 6, limit: 5 }));
+    }
+
+    #[test]
+    fn rejects_evidence_with_zero_probability() {
+        let mut graph = FactorGraph::new();
+        graph.add_discrete_var("a", vec![0, 1]);
+        graph.add_factor::<i32>(vec!(String::from("a")), |vals: &[u32]| 1 - vals[0] as i32);
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("a"), 1);
+
+        assert_eq!(Enumeration::new(&graph).with_evidence(evidence).run().unwrap_err(),
+                   EnumerationError::InconsistentEvidence);
+    }
+}
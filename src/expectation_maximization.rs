@@ -0,0 +1,507 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with expectation-maximization learning from data with missing values and latent variables
+
+use *;
+use mle::{FeatureTables, fit_statistics, network_tables};
+use model::Model;
+use rng::Rng;
+
+type PosteriorOracle = dyn Fn(&FactorGraph, &Assignment) -> (f64, Vec<Vec<f64>>);
+
+/// Get the built-in posterior oracle for an inference method.
+fn method_oracle(method: InferenceMethod) -> Box<PosteriorOracle> {
+    match method {
+        InferenceMethod::Exact => Box::new(exact_posterior),
+        InferenceMethod::BeliefPropagation => Box::new(|graph: &FactorGraph, evidence: &Assignment| {
+            let beliefs = BeliefPropagation::new(graph).with_evidence(evidence.clone()).run();
+            let posteriors = beliefs.factor_beliefs.iter().map(|belief| belief.probabilities()).collect();
+            (BetheFreeEnergy::new(graph, &beliefs).log_partition(), posteriors)
+        })
+    }
+}
+
+/// Compute the log partition function with the evidence applied and the posterior over the entries of every
+/// factor's table from a junction tree.
+fn exact_posterior(graph: &FactorGraph, evidence: &Assignment) -> (f64, Vec<Vec<f64>>) {
+    let tree = JunctionTree::new(graph, evidence);
+    let posteriors = graph.get_factors().iter()
+        .map(|factor| {
+            let scope = factor.get_variables();
+            let table = Table::uniform(scope.clone(), scope.iter().map(|var| graph.domain_size(var)).collect());
+            let free: Vec<String> = scope.iter().filter(|&var| !evidence.contains_key(var)).cloned().collect();
+            let marginal = if free.is_empty() { None } else { tree.marginal(&free) };
+
+            (0..table.size())
+                .map(|index| {
+                    let assignment = table.assignment_at(index);
+                    let consistent = scope.iter().zip(assignment.iter())
+                        .all(|(var, &val)| evidence.get(var).is_none_or(|&observed| observed == val));
+                    if !consistent {
+                        return 0.0;
+                    }
+                    match marginal {
+                        Some(ref marginal) => {
+                            let values: Vec<u32> = scope.iter().zip(assignment.iter())
+                                .filter(|&(var, _)| !evidence.contains_key(var))
+                                .map(|(_, &val)| val)
+                                .collect();
+                            marginal.log_value(&values).exp()
+                        },
+                        None => 1.0
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    (tree.log_partition(), posteriors)
+}
+
+/// Struct holding the result of expectation-maximization.
+#[derive(Clone, Debug)]
+pub struct EmResult {
+    /// Average log-likelihood of the observed values at every E-step of the best restart.
+    pub log_likelihoods: Vec<f64>,
+
+    /// Final average log-likelihood of every restart.
+    pub restart_log_likelihoods: Vec<f64>,
+
+    /// Index of the restart the graph was left with.
+    pub best_restart: usize,
+
+    /// Number of M-steps of the best restart.
+    pub iterations: usize,
+
+    /// Whether the best restart converged before the iteration limit.
+    pub converged: bool,
+}
+
+/// Struct fitting the table and log-linear factors of a graph by expectation-maximization.
+///
+/// Each assignment in the data may leave any variables unobserved, including latent variables that are never
+/// observed. The E-step runs inference with every assignment as evidence to get the expected counts of the
+/// entries of every factor. The M-step then re-estimates each table factor as the conditional distribution of
+/// its last variable given the others, as in a Bayesian network, and fits the log-linear weights to their
+/// expected features as in `MaximumLikelihood`. Other factors are held fixed. That M-step is only exact when the
+/// tables form a locally normalized network, so every variable may be the last variable of at most one table,
+/// the tables may not have a directed cycle from parents to children, and no other factor may touch a variable of
+/// a table.
+///
+/// The posteriors come from the inference method unless another oracle is set, for instance one running a
+/// different approximation on graphs too wide for a junction tree.
+///
+/// The first run starts from the graph as it is. Each further restart starts from random weights in `[-1, 1]`
+/// and random conditional tables, and the graph is left with the run reaching the highest log-likelihood.
+pub struct ExpectationMaximization<'a> {
+    graph: &'a mut FactorGraph,
+    data: &'a [Assignment],
+    method: InferenceMethod,
+    oracle: Option<Box<PosteriorOracle>>,
+    max_iterations: usize,
+    tolerance: f64,
+    restarts: usize,
+    seed: u64,
+    l2: f64,
+    pseudo_count: f64,
+    optimizer: Lbfgs,
+}
+
+impl<'a> ExpectationMaximization<'a> {
+    /// Create a new ExpectationMaximization fitting the graph to partial assignments of its variables.
+    ///
+    /// Defaults to exact inference, at most 100 iterations and a single run without regularization.
+    pub fn new(graph: &'a mut FactorGraph, data: &'a [Assignment]) -> ExpectationMaximization<'a> {
+        if data.is_empty() {
+            panic!("Expectation-maximization needs at least one assignment");
+        }
+
+        ExpectationMaximization {
+            graph,
+            data,
+            method: InferenceMethod::Exact,
+            oracle: None,
+            max_iterations: 100,
+            tolerance: 1e-8,
+            restarts: 1,
+            seed: 0,
+            l2: 0.0,
+            pseudo_count: 0.0,
+            optimizer: Lbfgs::new(),
+        }
+    }
+
+    /// Set how the M-step computes the expected features of the log-linear weights, and how the E-step computes
+    /// the expected counts unless an oracle is set.
+    pub fn with_method(mut self, method: InferenceMethod) -> ExpectationMaximization<'a> {
+        self.method = method;
+        self
+    }
+
+    /// Set the oracle giving the E-step the log partition function of a graph with evidence applied, and the
+    /// normalized posterior over the entries of every factor's table, in the order of the graph's factors.
+    pub fn with_oracle<F: Fn(&FactorGraph, &Assignment) -> (f64, Vec<Vec<f64>>) + 'static>(mut self, oracle: F)
+                                                                                    -> ExpectationMaximization<'a> {
+        self.oracle = Some(Box::new(oracle));
+        self
+    }
+
+    /// Set the maximum number of M-steps in each run.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> ExpectationMaximization<'a> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Set the increase in average log-likelihood under which a run is considered converged.
+    pub fn with_tolerance(mut self, tolerance: f64) -> ExpectationMaximization<'a> {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set the number of runs, all but the first starting from random parameters.
+    pub fn with_restarts(mut self, restarts: usize) -> ExpectationMaximization<'a> {
+        if restarts == 0 {
+            panic!("Expectation-maximization needs at least one run");
+        }
+
+        self.restarts = restarts;
+        self
+    }
+
+    /// Set the seed of the random number generator for the restarts.
+    pub fn with_seed(mut self, seed: u64) -> ExpectationMaximization<'a> {
+        self.seed = seed;
+        self
+    }
+
+    /// Add the penalty `l2 / 2 * |w|^2` to the M-step for the log-linear weights.
+    pub fn with_l2(mut self, l2: f64) -> ExpectationMaximization<'a> {
+        if l2 < 0.0 {
+            panic!("The L2 penalty must be non-negative, got {}", l2);
+        }
+
+        self.l2 = l2;
+        self
+    }
+
+    /// Add a pseudo-count to every entry of the expected counts of the table factors.
+    pub fn with_pseudo_count(mut self, pseudo_count: f64) -> ExpectationMaximization<'a> {
+        if pseudo_count < 0.0 {
+            panic!("The pseudo-count must be non-negative, got {}", pseudo_count);
+        }
+
+        self.pseudo_count = pseudo_count;
+        self
+    }
+
+    /// Run every restart, leaving the graph with the parameters and tables of the best one.
+    pub fn run(mut self) -> EmResult {
+        let mut rng = Rng::new(self.seed);
+        let tables = network_tables(self.graph);
+
+        let mut result = EmResult {
+            log_likelihoods: vec!(),
+            restart_log_likelihoods: vec!(),
+            best_restart: 0,
+            iterations: 0,
+            converged: false,
+        };
+        let mut best = None;
+        for restart in 0..self.restarts {
+            if restart > 0 {
+                self.randomize(&tables, &mut rng);
+            }
+
+            let (log_likelihoods, converged) = self.fit(&tables);
+            let last = log_likelihoods[log_likelihoods.len() - 1];
+            result.restart_log_likelihoods.push(last);
+            if restart == 0 || last > result.log_likelihoods[result.log_likelihoods.len() - 1] {
+                result.best_restart = restart;
+                result.iterations = log_likelihoods.len() - 1;
+                result.log_likelihoods = log_likelihoods;
+                result.converged = converged;
+                best = Some(self.save(&tables));
+            }
+        }
+
+        if let Some((parameters, saved)) = best {
+            self.graph.set_parameters(&parameters);
+            for (index, table) in tables.iter().zip(saved) {
+                self.graph.set_table(*index, table);
+            }
+        }
+        result
+    }
+
+    /// Alternate E-steps and M-steps from the graph's current state, giving the log-likelihood at each E-step.
+    fn fit(&mut self, tables: &[usize]) -> (Vec<f64>, bool) {
+        let mut log_likelihoods: Vec<f64> = vec!();
+        loop {
+            let model = Model::new(self.graph);
+            let (log_likelihood, counts) = self.expected_counts(&model);
+            let previous = log_likelihoods.last().cloned();
+            log_likelihoods.push(log_likelihood);
+
+            if previous.is_some_and(|p| (log_likelihood - p).abs() < self.tolerance) {
+                return (log_likelihoods, true);
+            }
+            if log_likelihoods.len() > self.max_iterations {
+                return (log_likelihoods, false);
+            }
+
+            self.maximize(&model, tables, &counts);
+        }
+    }
+
+    /// Compute the average log-likelihood of the data and the average expected count of every factor entry.
+    fn expected_counts(&self, model: &Model) -> (f64, Vec<Vec<f64>>) {
+        let default = method_oracle(self.method);
+        let oracle = self.oracle.as_ref().unwrap_or(&default);
+        let scale = 1.0 / self.data.len() as f64;
+        let (log_partition, _) = self.posterior(&**oracle, model, &Assignment::new());
+
+        let mut log_likelihood = 0.0;
+        let mut counts: Vec<Vec<f64>> = model.tables.iter().map(|table| vec![0.0; table.size()]).collect();
+        for evidence in self.data.iter() {
+            let (log_evidence, posteriors) = self.posterior(&**oracle, model, evidence);
+            log_likelihood += scale * (log_evidence - log_partition);
+            for (count, posterior) in counts.iter_mut().zip(posteriors.iter()) {
+                for (c, p) in count.iter_mut().zip(posterior.iter()) {
+                    *c += scale * p;
+                }
+            }
+        }
+        (log_likelihood, counts)
+    }
+
+    /// Compute the log partition function with the evidence applied, and the posterior over the entries of every
+    /// factor's table, asking the oracle unless the evidence is complete.
+    fn posterior(&self, oracle: &PosteriorOracle, model: &Model, evidence: &Assignment) -> (f64, Vec<Vec<f64>>) {
+        let clamped = model.clamped(evidence);
+        if clamped.iter().all(|c| c.is_some()) {
+            let state: Vec<u32> = clamped.iter().map(|c| c.unwrap()).collect();
+            let posteriors = model.tables.iter().enumerate()
+                .map(|(f, table)| {
+                    let values: Vec<u32> = model.scopes[f].iter().map(|&var| state[var]).collect();
+                    let mut posterior = vec![0.0; table.size()];
+                    posterior[table.index_of(&values)] = 1.0;
+                    posterior
+                })
+                .collect();
+            return (model.log_potential(&state), posteriors);
+        }
+
+        oracle(self.graph, evidence)
+    }
+
+    /// Re-estimate the table factors and the log-linear weights from the expected counts.
+    fn maximize(&mut self, model: &Model, tables: &[usize], counts: &[Vec<f64>]) {
+        for &f in tables.iter() {
+            let table = &model.tables[f];
+            let card = table.get_cardinalities()[table.get_cardinalities().len() - 1];
+            let log_values = counts[f].chunks(card)
+                .flat_map(|row| {
+                    let total: f64 = row.iter().map(|c| c + self.pseudo_count).sum();
+                    row.iter()
+                        .map(|c| if total > 0.0 { ((c + self.pseudo_count) / total).ln() } else { -(card as f64).ln() })
+                        .collect::<Vec<f64>>()
+                })
+                .collect();
+            self.graph.set_table(f, Table::new(table.get_variables().clone(), table.get_cardinalities().clone(),
+                                                     log_values));
+        }
+
+        let features = FeatureTables::new(self.graph, model);
+        if !features.factors.is_empty() {
+            let mut statistics = vec![0.0; self.graph.num_parameters()];
+            for (k, &f) in features.factors.iter().enumerate() {
+                features.add_expected(k, &counts[f], 1.0, &mut statistics);
+            }
+            let result = fit_statistics(self.graph, &features, &statistics, 0.0, self.method, self.l2, &self.optimizer);
+            self.graph.set_parameters(&result.parameters);
+        }
+    }
+
+    /// Draw random weights in `[-1, 1]` and random conditional tables.
+    fn randomize(&mut self, tables: &[usize], rng: &mut Rng) {
+        let parameters: Vec<f64> = (0..self.graph.num_parameters()).map(|_| 2.0 * rng.next_f64() - 1.0).collect();
+        self.graph.set_parameters(&parameters);
+
+        for &f in tables.iter() {
+            let table = Table::from_factor(self.graph, &self.graph.get_factors()[f]);
+            let card = table.get_cardinalities()[table.get_cardinalities().len() - 1];
+            let log_values = (0..table.size() / card)
+                .flat_map(|_| {
+                    let row: Vec<f64> = (0..card).map(|_| 1.0 - rng.next_f64()).collect();
+                    let total: f64 = row.iter().sum();
+                    row.into_iter().map(move |r| (r / total).ln())
+                })
+                .collect();
+            self.graph.set_table(f, Table::new(table.get_variables().clone(), table.get_cardinalities().clone(),
+                                                     log_values));
+        }
+    }
+
+    /// Copy the parameters and the tables of the table factors.
+    fn save(&self, tables: &[usize]) -> (Vec<f64>, Vec<Table>) {
+        let saved = tables.iter().map(|&f| Table::from_factor(self.graph, &self.graph.get_factors()[f])).collect();
+        (self.graph.get_parameters(), saved)
+    }
+}
+
+impl<'a> std::fmt::Debug for ExpectationMaximization<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let oracle = if self.oracle.is_some() { "Some(<oracle>)" } else { "None" };
+        write!(f, "ExpectationMaximization {{ graph: {:?}, method: {:?}, oracle: {}, restarts: {} }}", self.graph,
+               self.method, oracle, self.restarts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use test_support::{make_chain, make_chain_data, names, sample_exact};
+
+    /// Build a mixture of three binary variables given a hidden class z, with conditional tables that are
+    /// uniform unless probabilities of the value 1 are given.
+    fn make_mixture(prior: Option<f64>, conditionals: Option<[[f64; 2]; 3]>) -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        for name in &["z", "x1", "x2", "x3"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+
+        let bernoulli = |p: f64| vec!((1.0 - p).ln(), p.ln());
+        graph.add_table_factor(match prior {
+            Some(p) => Table::new(names(&["z"]), vec!(2), bernoulli(p)),
+            None => Table::uniform(names(&["z"]), vec!(2))
+        });
+        for (i, x) in ["x1", "x2", "x3"].iter().enumerate() {
+            graph.add_table_factor(match conditionals {
+                Some(c) => Table::new(names(&["z", x]), vec!(2, 2), [bernoulli(c[i][0]), bernoulli(c[i][1])].concat()),
+                None => Table::uniform(names(&["z", x]), vec!(2, 2))
+            });
+        }
+        graph
+    }
+
+    #[test]
+    fn recovers_a_latent_class_model() {
+        let conditionals = [[0.1, 0.9], [0.2, 0.7], [0.15, 0.85]];
+        let mut truth = make_mixture(Some(0.4), Some(conditionals));
+        let mut data = sample_exact(&truth, 500, 3);
+        for assignment in data.iter_mut() {
+            assignment.remove("z");
+        }
+
+        let reference = ExpectationMaximization::new(&mut truth, &data).with_max_iterations(0).run();
+
+        // Starting from uniform tables is a saddle point, which only the random restarts escape.
+        let mut graph = make_mixture(None, None);
+        let result = ExpectationMaximization::new(&mut graph, &data).with_restarts(4).with_seed(5).with_tolerance(1e-6)
+            .run();
+        assert!(result.converged);
+        assert!(result.best_restart > 0);
+        assert!(result.restart_log_likelihoods[0] < result.restart_log_likelihoods[result.best_restart] - 0.01);
+        assert!(result.log_likelihoods.windows(2).all(|pair| pair[1] >= pair[0] - 1e-12));
+        assert!(result.log_likelihoods[result.iterations] >= reference.log_likelihoods[0]);
+
+        // The classes may come out swapped.
+        let fitted = Model::new(&graph);
+        let p = |f: usize, z: u32| fitted.tables[f].log_value(&[z, 1]).exp();
+        let swapped = p(1, 0) > 0.5;
+        for (i, c) in conditionals.iter().enumerate() {
+            let (low, high) = if swapped { (p(i + 1, 1), p(i + 1, 0)) } else { (p(i + 1, 0), p(i + 1, 1)) };
+            assert!((low - c[0]).abs() < 0.08 && (high - c[1]).abs() < 0.08);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn tables_must_be_conditionals_of_distinct_variables() {
+        let mut graph = make_mixture(None, None);
+        graph.add_table_factor(Table::uniform(names(&["x2", "x1"]), vec!(2, 2)));
+        let data: Vec<Assignment> = vec!(names(&["x1", "x2", "x3"]).into_iter().map(|var| (var, 0)).collect());
+        ExpectationMaximization::new(&mut graph, &data).run();
+    }
+
+    #[test]
+    #[should_panic(expected = "directed cycle")]
+    fn tables_must_be_acyclic() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+        for &(parent, child) in [("a", "b"), ("b", "c"), ("c", "a")].iter() {
+            graph.add_table_factor(Table::uniform(names(&[parent, child]), vec!(2, 2)));
+        }
+        let data: Vec<Assignment> = vec!(names(&["a", "b", "c"]).into_iter().map(|var| (var, 0)).collect());
+        ExpectationMaximization::new(&mut graph, &data).run();
+    }
+
+    #[test]
+    #[should_panic(expected = "of a table factor")]
+    fn other_factors_must_not_touch_tables() {
+        let mut graph = make_mixture(None, None);
+        graph.add_factor::<i32>(names(&["x1"]), |vals: &[u32]| vals[0] as i32 + 1);
+        let data: Vec<Assignment> = vec!(names(&["x1", "x2", "x3"]).into_iter().map(|var| (var, 0)).collect());
+        ExpectationMaximization::new(&mut graph, &data).run();
+    }
+
+    #[test]
+    fn e_step_uses_the_given_oracle() {
+        let mut data = make_chain_data();
+        data[0].remove("b");
+        data[3].remove("a");
+
+        let mut expected_graph = make_chain();
+        let expected = ExpectationMaximization::new(&mut expected_graph, &data).run();
+
+        // Complete assignments need no inference, so each E-step asks for the partition function and two posteriors.
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut graph = make_chain();
+        let result = ExpectationMaximization::new(&mut graph, &data)
+            .with_oracle(move |graph: &FactorGraph, evidence: &Assignment| {
+                counter.set(counter.get() + 1);
+                exact_posterior(graph, evidence)
+            })
+            .run();
+        assert_eq!(calls.get(), 3 * result.log_likelihoods.len());
+        assert_eq!(result.log_likelihoods, expected.log_likelihoods);
+        assert_eq!(graph.get_parameters(), expected_graph.get_parameters());
+    }
+
+    #[test]
+    fn complete_data_gives_maximum_likelihood() {
+        let mut data = make_chain_data();
+
+        let mut mle_graph = make_chain();
+        let mle = MaximumLikelihood::new(&mut mle_graph, &data).with_tolerance(1e-9).run();
+        let mut em_graph = make_chain();
+        let em = ExpectationMaximization::new(&mut em_graph, &data).run();
+        assert!((em.log_likelihoods[em.iterations] - mle.log_likelihood).abs() < 1e-9);
+        for (w, v) in em_graph.get_parameters().iter().zip(mle.parameters.iter()) {
+            assert!((w - v).abs() < 1e-5);
+        }
+
+        // Hiding some values, belief propagation on the chain gives the same exact posteriors.
+        data[0].remove("b");
+        data[3].remove("a");
+        data[5].remove("c");
+        let mut exact_graph = make_chain();
+        let exact = ExpectationMaximization::new(&mut exact_graph, &data).run();
+        let mut bp_graph = make_chain();
+        let bp = ExpectationMaximization::new(&mut bp_graph, &data).with_method(InferenceMethod::BeliefPropagation).run();
+        assert!(exact.converged && bp.converged);
+        assert!(exact.log_likelihoods.windows(2).all(|pair| pair[1] >= pair[0] - 1e-9));
+        for (w, v) in exact_graph.get_parameters().iter().zip(bp_graph.get_parameters().iter()) {
+            assert!((w - v).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,639 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with expectation propagation, projecting beliefs onto Gaussian and categorical families
+
+use std::error::Error;
+use std::fmt;
+
+use *;
+use particle_bp::factor_log_value;
+use table::log_sum_exp;
+
+/// Get the nodes and weights of the `n`-point Gauss-Hermite rule for expectations under a standard normal.
+///
+/// The roots of the Hermite polynomials are found by Newton's method from asymptotic initial guesses.
+fn hermite_rule(n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut roots = vec![0.0; n];
+    let mut nodes = vec![0.0; n];
+    let mut weights = vec![0.0; n];
+    let pi_quarter = std::f64::consts::PI.powf(-0.25);
+
+    let mut z: f64 = 0.0;
+    for i in 0..n.div_ceil(2) {
+        z = match i {
+            0 => (2.0 * n as f64 + 1.0).sqrt() - 1.85575 * (2.0 * n as f64 + 1.0).powf(-0.16667),
+            1 => z - 1.14 * (n as f64).powf(0.426) / z,
+            2 => 1.86 * z - 0.86 * roots[0],
+            3 => 1.91 * z - 0.91 * roots[1],
+            _ => 2.0 * z - roots[i - 2]
+        };
+
+        let mut derivative = 0.0;
+        for _ in 0..100 {
+            let mut p1 = pi_quarter;
+            let mut p2 = 0.0;
+            for j in 0..n {
+                let p3 = p2;
+                p2 = p1;
+                p1 = z * (2.0 / (j as f64 + 1.0)).sqrt() * p2 - (j as f64 / (j as f64 + 1.0)).sqrt() * p3;
+            }
+            derivative = (2.0 * n as f64).sqrt() * p2;
+
+            let previous = z;
+            z = previous - p1 / derivative;
+            if (z - previous).abs() <= 1e-14 {
+                break;
+            }
+        }
+
+        // Rescale from the weight exp(-t^2) to the standard normal density.
+        roots[i] = z;
+        let weight = 2.0 / (derivative * derivative) / std::f64::consts::PI.sqrt();
+        nodes[i] = z * std::f64::consts::SQRT_2;
+        nodes[n - 1 - i] = -z * std::f64::consts::SQRT_2;
+        weights[i] = weight;
+        weights[n - 1 - i] = weight;
+    }
+    (nodes, weights)
+}
+
+/// Error returned when a factor needs more quadrature points than the limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TooManyQuadraturePoints {
+    /// Name of the factor whose moments could not be computed.
+    pub factor: String,
+
+    /// Number of points the factor's moments would be summed over.
+    pub num_points: u128,
+
+    /// Largest number of points a factor was allowed.
+    pub limit: usize,
+}
+
+impl fmt::Display for TooManyQuadraturePoints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "factor {} needs {} quadrature points, more than the limit of {}", self.factor, self.num_points,
+               self.limit)
+    }
+}
+
+impl Error for TooManyQuadraturePoints {}
+
+/// Weighted sums of the statistics of the points, scaled by the largest weight seen so far.
+#[derive(Debug)]
+enum Moments {
+    /// Total weight of each value of a discrete variable.
+    Categorical(Vec<f64>),
+
+    /// Running mean and scatter matrix of a continuous variable.
+    Gaussian(Vec<f64>, Matrix),
+}
+
+/// Natural parameters of a member of the family the beliefs over a variable are projected onto.
+#[derive(Clone, Debug)]
+enum Natural {
+    /// Unnormalized log-probabilities of a categorical distribution over a discrete variable.
+    Categorical(Vec<f64>),
+
+    /// Precision and information of a Gaussian over a continuous variable.
+    Gaussian(GaussianPotential),
+}
+
+impl Natural {
+    /// Get the natural parameters of the flat member of a variable's family.
+    fn flat(graph: &FactorGraph, var: &str) -> Natural {
+        match graph.dimension(var) {
+            0 => Natural::Categorical(vec![0.0; graph.domain_size(var)]),
+            d => Natural::Gaussian(GaussianPotential::new(Matrix::zeros(d, d), vec![0.0; d]))
+        }
+    }
+
+    /// Compute the natural parameters `self + scale * other`, raising `other` to the power `scale`.
+    fn add_scaled(&self, other: &Natural, scale: f64) -> Natural {
+        match (self, other) {
+            (Natural::Categorical(a), Natural::Categorical(b)) =>
+                Natural::Categorical(a.iter().zip(b.iter()).map(|(x, y)| x + scale * y).collect()),
+            (Natural::Gaussian(a), Natural::Gaussian(b)) =>
+                Natural::Gaussian(a.combine(&GaussianPotential::new(b.get_precision().scaled(scale),
+                                                                   b.get_information().iter().map(|h| scale * h).collect()))),
+            _ => panic!("Cannot combine categorical and Gaussian parameters")
+        }
+    }
+
+    /// Compute the natural parameters `scale * self`.
+    fn scaled(&self, scale: f64) -> Natural {
+        Natural::flat_like(self).add_scaled(self, scale)
+    }
+
+    /// Get the flat member of the same family and size.
+    fn flat_like(other: &Natural) -> Natural {
+        match *other {
+            Natural::Categorical(ref a) => Natural::Categorical(vec![0.0; a.len()]),
+            Natural::Gaussian(ref a) =>
+                Natural::Gaussian(GaussianPotential::new(Matrix::zeros(a.dimension(), a.dimension()), vec![0.0; a.dimension()]))
+        }
+    }
+
+    /// Get the largest absolute difference between the parameters of two members of the same family.
+    fn distance(&self, other: &Natural) -> f64 {
+        match (self, other) {
+            (Natural::Categorical(a), Natural::Categorical(b)) =>
+                a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs()).fold(0.0, f64::max),
+            (Natural::Gaussian(a), Natural::Gaussian(b)) => {
+                let precision = (a.get_precision() - b.get_precision()).get_data().iter()
+                    .map(|x| x.abs())
+                    .fold(0.0, f64::max);
+                a.get_information().iter().zip(b.get_information().iter())
+                    .map(|(x, y)| (x - y).abs())
+                    .fold(precision, f64::max)
+            },
+            _ => panic!("Cannot compare categorical and Gaussian parameters")
+        }
+    }
+}
+
+/// Struct holding the beliefs computed by expectation propagation.
+#[derive(Clone, Debug)]
+pub struct ExpectationPropagationResult {
+    /// Belief over the values of every discrete variable.
+    pub marginals: Marginals,
+
+    /// Mean of the belief over every continuous variable whose belief is a proper Gaussian.
+    pub means: Values,
+
+    /// Covariance of the belief over every continuous variable whose belief is a proper Gaussian.
+    pub covariances: HashMap<String, Matrix>,
+
+    /// Number of sweeps over the factors that were run.
+    pub iterations: usize,
+
+    /// Whether the site approximations converged before the iteration limit.
+    pub converged: bool,
+}
+
+/// Struct running expectation propagation with fully factorized site approximations.
+///
+/// Every factor is approximated by a product of sites, one per variable in its scope, in the family of that
+/// variable: categorical for discrete variables and Gaussian for continuous ones. Each update divides the
+/// factor's sites out of the beliefs to get the cavity, multiplies the cavity by the factor, and projects
+/// the result back onto the family by matching moments. The moments are exact for Gaussian factors and
+/// computed by enumeration of the discrete values and Gauss-Hermite quadrature under the Gaussian cavity
+/// otherwise, so every continuous variable needs a proper cavity, for instance from a Gaussian prior factor.
+/// Factors whose cavity is not yet proper are skipped until it is. The number of points grows exponentially
+/// with the number of continuous components, so factors over more than a few of them need few quadrature
+/// points or a higher limit on the points.
+///
+/// With power `alpha`, each update uses the factor raised to `alpha` against a cavity removing only
+/// `alpha` of its sites, which is more stable when the factors are far from the family. On discrete graphs
+/// with unit power this is belief propagation with sequential updates.
+#[derive(Debug)]
+pub struct ExpectationPropagation<'a> {
+    graph: &'a FactorGraph,
+    max_iterations: usize,
+    tolerance: f64,
+    damping: f64,
+    power: f64,
+    quadrature_points: usize,
+    max_points: usize,
+}
+
+impl<'a> ExpectationPropagation<'a> {
+    /// Create a new ExpectationPropagation for the graph.
+    ///
+    /// Defaults to at most 100 sweeps, unit power without damping, and 20 quadrature points per component, with
+    /// at most 2^22 points per factor.
+    pub fn new(graph: &'a FactorGraph) -> ExpectationPropagation<'a> {
+        ExpectationPropagation {
+            graph,
+            max_iterations: 100,
+            tolerance: 1e-8,
+            damping: 0.0,
+            power: 1.0,
+            quadrature_points: 20,
+            max_points: 1 << 22,
+        }
+    }
+
+    /// Set the maximum number of sweeps over the factors.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> ExpectationPropagation<'a> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Set the largest change in a site's parameters under which the sites are considered converged.
+    pub fn with_tolerance(mut self, tolerance: f64) -> ExpectationPropagation<'a> {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Keep a fraction `damping` of each old site, in natural parameters, to help convergence.
+    pub fn with_damping(mut self, damping: f64) -> ExpectationPropagation<'a> {
+        if !(0.0..1.0).contains(&damping) {
+            panic!("Damping must be in [0, 1), got {}", damping);
+        }
+
+        self.damping = damping;
+        self
+    }
+
+    /// Set the power each factor is raised to in its update, running power EP.
+    pub fn with_power(mut self, power: f64) -> ExpectationPropagation<'a> {
+        if power <= 0.0 || power > 1.0 {
+            panic!("Power must be in (0, 1], got {}", power);
+        }
+
+        self.power = power;
+        self
+    }
+
+    /// Set the number of Gauss-Hermite points in each continuous component.
+    pub fn with_quadrature_points(mut self, quadrature_points: usize) -> ExpectationPropagation<'a> {
+        if quadrature_points == 0 {
+            panic!("Quadrature needs at least one point");
+        }
+
+        self.quadrature_points = quadrature_points;
+        self
+    }
+
+    /// Set the largest number of points, over the discrete values and quadrature nodes, of any factor.
+    pub fn with_max_points(mut self, max_points: usize) -> ExpectationPropagation<'a> {
+        self.max_points = max_points;
+        self
+    }
+
+    /// Update the sites until convergence and compute the beliefs.
+    ///
+    /// Returns an error if a factor that is not Gaussian needs more points than the limit.
+    pub fn run(&self) -> Result<ExpectationPropagationResult, TooManyQuadraturePoints> {
+        let names = self.graph.get_variable_names();
+        let factors = self.graph.get_factors();
+        for factor in factors.iter().filter(|f| !matches!(*f.get_potential(), Potential::Gaussian(_))) {
+            let num_points = factor.get_variables().iter().fold(1u128, |acc, var| {
+                let points = match self.graph.dimension(var) {
+                    0 => self.graph.domain_size(var) as u128,
+                    d => (self.quadrature_points as u128).saturating_pow(d as u32)
+                };
+                acc.saturating_mul(points)
+            });
+            if num_points > self.max_points as u128 {
+                return Err(TooManyQuadraturePoints { factor: factor.get_name(), num_points, limit: self.max_points });
+            }
+        }
+
+        let scopes: Vec<Vec<usize>> = factors.iter()
+            .map(|f| f.get_variables().iter().map(|var| names.iter().position(|v| v == var).unwrap()).collect())
+            .collect();
+
+        let mut sites: Vec<Vec<Natural>> = scopes.iter()
+            .map(|scope| scope.iter().map(|&v| Natural::flat(self.graph, &names[v])).collect())
+            .collect();
+        let mut beliefs: Vec<Natural> = names.iter().map(|name| Natural::flat(self.graph, name)).collect();
+        let rule = hermite_rule(self.quadrature_points);
+
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.max_iterations && !converged {
+            iterations += 1;
+            let mut change: f64 = 0.0;
+            for (f, scope) in scopes.iter().enumerate() {
+                let cavities: Vec<Natural> = scope.iter().enumerate()
+                    .map(|(pos, &v)| beliefs[v].add_scaled(&sites[f][pos], -self.power))
+                    .collect();
+                let projected = match self.project(&factors[f], &cavities, &rule) {
+                    Some(x) => x,
+                    None => {
+                        change = f64::INFINITY;
+                        continue;
+                    }
+                };
+
+                for (pos, &v) in scope.iter().enumerate() {
+                    let target = projected[pos].add_scaled(&cavities[pos], -1.0).scaled(1.0 / self.power);
+                    let site = target.scaled(1.0 - self.damping).add_scaled(&sites[f][pos], self.damping);
+                    change = change.max(site.distance(&sites[f][pos]));
+                    beliefs[v] = beliefs[v].add_scaled(&site, 1.0).add_scaled(&sites[f][pos], -1.0);
+                    sites[f][pos] = site;
+                }
+            }
+            converged = change < self.tolerance;
+        }
+
+        let mut result = ExpectationPropagationResult {
+            marginals: Marginals::new(),
+            means: Values::new(),
+            covariances: HashMap::new(),
+            iterations,
+            converged,
+        };
+        for (name, belief) in names.iter().zip(beliefs.iter()) {
+            match *belief {
+                Natural::Categorical(ref log_values) => {
+                    let log_z = log_sum_exp(log_values);
+                    result.marginals.insert(name.clone(), log_values.iter().map(|l| (l - log_z).exp()).collect());
+                },
+                Natural::Gaussian(ref potential) => {
+                    if potential.get_precision().cholesky().is_some() {
+                        result.means.insert(name.clone(), potential.mean().unwrap());
+                        result.covariances.insert(name.clone(), potential.covariance().unwrap());
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Project the product of a factor raised to the power and the cavities onto the families of its variables.
+    ///
+    /// Returns None if the cavities do not give the product finite moments.
+    fn project(&self, factor: &Factor, cavities: &[Natural], rule: &(Vec<f64>, Vec<f64>)) -> Option<Vec<Natural>> {
+        if let Potential::Gaussian(ref potential) = *factor.get_potential() {
+            return self.project_gaussian(potential, cavities);
+        }
+
+        // Each point is an assignment of the discrete variables and a quadrature node of the continuous ones,
+        // with its log weight under the cavities.
+        let mut discrete: Vec<(usize, Vec<f64>)> = vec!();
+        let mut continuous: Vec<(usize, Vec<f64>, Matrix)> = vec!();
+        let mut moments = Vec::with_capacity(cavities.len());
+        for (pos, cavity) in cavities.iter().enumerate() {
+            match *cavity {
+                Natural::Categorical(ref log_values) => {
+                    let log_z = log_sum_exp(log_values);
+                    discrete.push((pos, log_values.iter().map(|l| l - log_z).collect()));
+                    moments.push(Moments::Categorical(vec![0.0; log_values.len()]));
+                },
+                Natural::Gaussian(ref potential) => {
+                    potential.get_precision().cholesky()?;
+                    continuous.push((pos, potential.mean()?, potential.covariance()?.cholesky()?));
+                    moments.push(Moments::Gaussian(vec![0.0; potential.dimension()],
+                                                   Matrix::zeros(potential.dimension(), potential.dimension())));
+                }
+            }
+        }
+
+        let configurations: usize = discrete.iter().map(|d| d.1.len()).product();
+        let dimension: usize = continuous.iter().map(|c| c.1.len()).sum();
+        let nodes = self.quadrature_points.pow(dimension as u32);
+
+        // The moments are accumulated one point at a time, with weights relative to the largest log weight so
+        // far and running means and scatter matrices updated by West's algorithm.
+        let mut max_log_weight = f64::NEG_INFINITY;
+        let mut total = 0.0;
+        let mut assignment = vec![0; cavities.len()];
+        let mut values: Vec<Vec<f64>> = vec![vec!(); cavities.len()];
+        for configuration in 0..configurations {
+            let mut log_weight = 0.0;
+            let mut rest = configuration;
+            for &(pos, ref log_probabilities) in discrete.iter().rev() {
+                assignment[pos] = (rest % log_probabilities.len()) as u32;
+                rest /= log_probabilities.len();
+                log_weight += log_probabilities[assignment[pos] as usize];
+            }
+
+            for node in 0..nodes {
+                let mut point_weight = log_weight;
+                let mut rest = node;
+                for &(pos, ref mean, ref root) in continuous.iter() {
+                    let standard: Vec<f64> = (0..mean.len())
+                        .map(|_| {
+                            let k = rest % self.quadrature_points;
+                            rest /= self.quadrature_points;
+                            point_weight += rule.1[k].ln();
+                            rule.0[k]
+                        })
+                        .collect();
+                    values[pos] = mean.iter().zip(root.mul_vec(&standard).iter()).map(|(m, x)| m + x).collect();
+                }
+
+                let log_value = self.log_factor(factor, &assignment, &values, &discrete);
+                let point_weight = point_weight + self.power * log_value;
+                if point_weight == f64::NEG_INFINITY {
+                    continue;
+                }
+                if point_weight > max_log_weight {
+                    let rescale = (max_log_weight - point_weight).exp();
+                    total *= rescale;
+                    for moment in moments.iter_mut() {
+                        match *moment {
+                            Moments::Categorical(ref mut sums) => sums.iter_mut().for_each(|w| *w *= rescale),
+                            Moments::Gaussian(_, ref mut scatter) => *scatter = scatter.scaled(rescale)
+                        }
+                    }
+                    max_log_weight = point_weight;
+                }
+
+                let weight = (point_weight - max_log_weight).exp();
+                total += weight;
+                for (pos, moment) in moments.iter_mut().enumerate() {
+                    match *moment {
+                        Moments::Categorical(ref mut sums) => sums[assignment[pos] as usize] += weight,
+                        Moments::Gaussian(ref mut mean, ref mut scatter) => {
+                            let before: Vec<f64> = values[pos].iter().zip(mean.iter()).map(|(x, m)| x - m).collect();
+                            for (m, delta) in mean.iter_mut().zip(before.iter()) {
+                                *m += weight / total * delta;
+                            }
+                            let after: Vec<f64> = values[pos].iter().zip(mean.iter()).map(|(x, m)| x - m).collect();
+                            for (i, b) in before.iter().enumerate() {
+                                for (j, a) in after.iter().enumerate() {
+                                    scatter[(i, j)] += weight * b * a;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if !(max_log_weight + total.ln()).is_finite() {
+            return None;
+        }
+
+        let mut projected = Vec::with_capacity(cavities.len());
+        for moment in moments {
+            match moment {
+                Moments::Categorical(sums) => {
+                    projected.push(Natural::Categorical(
+                        sums.iter().map(|w| (w / total).max(f64::MIN_POSITIVE).ln()).collect()));
+                },
+                Moments::Gaussian(mean, scatter) => {
+                    let covariance = scatter.scaled(1.0 / total);
+                    covariance.cholesky()?;
+                    let precision = covariance.inverse()?;
+                    let information = precision.mul_vec(&mean);
+                    projected.push(Natural::Gaussian(GaussianPotential::new(precision, information)));
+                }
+            }
+        }
+        Some(projected)
+    }
+
+    /// Project the product of a Gaussian factor raised to the power and the cavities, which is exact.
+    fn project_gaussian(&self, potential: &GaussianPotential, cavities: &[Natural]) -> Option<Vec<Natural>> {
+        let mut precision = potential.get_precision().scaled(self.power);
+        let mut information: Vec<f64> = potential.get_information().iter().map(|h| self.power * h).collect();
+        let mut ranges = vec!();
+        let mut offset = 0;
+        for cavity in cavities.iter() {
+            let cavity = match *cavity {
+                Natural::Gaussian(ref x) => x,
+                Natural::Categorical(_) => panic!("Gaussian factors can only be over continuous variables")
+            };
+            precision.add_block(offset, offset, cavity.get_precision());
+            for (i, h) in cavity.get_information().iter().enumerate() {
+                information[offset + i] += h;
+            }
+            ranges.push((offset..offset + cavity.dimension()).collect::<Vec<usize>>());
+            offset += cavity.dimension();
+        }
+
+        let joint = GaussianPotential::new(precision, information);
+        joint.get_precision().cholesky()?;
+        ranges.iter()
+            .map(|range| joint.marginalize(range).map(Natural::Gaussian))
+            .collect()
+    }
+
+    /// Evaluate the log-potential of a factor at a point.
+    fn log_factor(&self, factor: &Factor, assignment: &[u32], values: &[Vec<f64>], discrete: &[(usize, Vec<f64>)]) -> f64 {
+        match *factor.get_potential() {
+            Potential::Discrete(_) | Potential::Table(_) | Potential::LogLinear(_) => factor.log_potential(assignment),
+            Potential::Hybrid(ref potential) => {
+                let index = discrete.iter().fold(0, |index, &(pos, ref log_probabilities)| {
+                    index * log_probabilities.len() + assignment[pos] as usize
+                });
+                let form = &potential.get_components()[index];
+                let x: Vec<f64> = values.iter().flat_map(|v| v.iter().cloned()).collect();
+                let px = form.get_potential().get_precision().mul_vec(&x);
+                form.get_log_scale() + x.iter().zip(px.iter()).zip(form.get_potential().get_information().iter())
+                    .map(|((xi, pxi), hi)| hi * xi - 0.5 * xi * pxi)
+                    .sum::<f64>()
+            },
+            _ => {
+                let slices: Vec<&[f64]> = values.iter().map(|v| v.as_slice()).collect();
+                factor_log_value(factor, &slices)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Complementary error function, with fractional error below 1.2e-7.
+    fn erfc(x: f64) -> f64 {
+        let z = x.abs();
+        let t = 1.0 / (1.0 + 0.5 * z);
+        let poly = -z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 + t * (-0.18628806
+            + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+        let value = t * poly.exp();
+        if x >= 0.0 { value } else { 2.0 - value }
+    }
+
+    fn log_probit(x: f64) -> f64 {
+        (0.5 * erfc(-x / std::f64::consts::SQRT_2)).ln()
+    }
+
+    #[test]
+    fn probit_posterior_matches_numerical_integration() {
+        let data = [(1.0, 1.0), (2.0, 1.0), (-0.5, 1.0), (1.5, -1.0)];
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("w", 1);
+        graph.add_gaussian_factor(vec!(String::from("w")),
+                                  GaussianPotential::from_moments(&[0.0], &Matrix::from_diagonal(&[1.0])));
+        for &(x, y) in data.iter() {
+            graph.add_continuous_factor(vec!(String::from("w")),
+                                        ContinuousPotential::new(move |v| log_probit(y * x * v[0][0])));
+        }
+
+        let grid: Vec<f64> = (0..16001).map(|i| -8.0 + 1e-3 * i as f64).collect();
+        let log_posterior: Vec<f64> = grid.iter()
+            .map(|&w| -0.5 * w * w + data.iter().map(|&(x, y)| log_probit(y * x * w)).sum::<f64>())
+            .collect();
+        let log_z = log_sum_exp(&log_posterior);
+        let mean: f64 = grid.iter().zip(log_posterior.iter()).map(|(w, l)| w * (l - log_z).exp()).sum();
+        let variance: f64 = grid.iter().zip(log_posterior.iter())
+            .map(|(w, l)| (w - mean) * (w - mean) * (l - log_z).exp())
+            .sum();
+
+        let result = ExpectationPropagation::new(&graph).run().unwrap();
+        assert!(result.converged);
+        assert!((result.means["w"][0] - mean).abs() < 0.02);
+        assert!((result.covariances["w"][(0, 0)] - variance).abs() < 0.02);
+
+        let damped = ExpectationPropagation::new(&graph).with_damping(0.5).with_power(0.5).run().unwrap();
+        assert!(damped.converged);
+        assert!((damped.means["w"][0] - mean).abs() < 0.05);
+    }
+
+    fn make_sum_probit(dimension: usize) -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("w", dimension);
+        graph.add_gaussian_factor(vec!(String::from("w")), GaussianPotential::from_moments(
+            &vec![0.0; dimension], &Matrix::from_diagonal(&vec![1.0; dimension])));
+        graph.add_continuous_factor(vec!(String::from("w")),
+                                    ContinuousPotential::new(|v| log_probit(v[0].iter().sum())));
+        graph
+    }
+
+    #[test]
+    fn high_dimensional_factor_is_projected() {
+        // The sum of the components has prior N(0, d), so its posterior mean is d sqrt(2 / pi) / sqrt(1 + d),
+        // shared equally between the components.
+        let graph = make_sum_probit(6);
+        let result = ExpectationPropagation::new(&graph).with_quadrature_points(6).run().unwrap();
+
+        let expected = (2.0 / std::f64::consts::PI).sqrt() / 7f64.sqrt();
+        assert!(result.converged);
+        for mean in result.means["w"].iter() {
+            assert!((mean - expected).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn refuses_factors_with_too_many_points() {
+        let graph = make_sum_probit(6);
+
+        let error = ExpectationPropagation::new(&graph).run().unwrap_err();
+        assert_eq!(error.num_points, 20u128.pow(6));
+        assert_eq!(error.limit, 1 << 22);
+
+        let error = ExpectationPropagation::new(&graph).with_quadrature_points(6).with_max_points(1000).run();
+        assert_eq!(error.unwrap_err().num_points, 6u128.pow(6));
+    }
+
+    fn agree(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 3 } else { 1 }
+    }
+
+    fn prefer(vals: &[u32]) -> i32 {
+        vals[0] as i32 + 1
+    }
+
+    #[test]
+    fn categorical_projection_is_exact_on_trees() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_discrete_var(name, vec![0, 1, 2]);
+        }
+        graph.add_factor::<i32>(vec!(String::from("a"), String::from("b")), agree);
+        graph.add_factor::<i32>(vec!(String::from("b"), String::from("c")), agree);
+        graph.add_factor::<i32>(vec!(String::from("c")), prefer);
+
+        let result = ExpectationPropagation::new(&graph).run().unwrap();
+        let exact = Enumeration::new(&graph).run().unwrap();
+
+        assert!(result.converged);
+        for (name, marginal) in exact.marginals.iter() {
+            for (q, p) in result.marginals[name].iter().zip(marginal.iter()) {
+                assert!((q - p).abs() < 1e-6);
+            }
+        }
+    }
+}
@@ -11,20 +11,51 @@ use *;
 
 type PotentialFunc = fn(&[u32]) -> i32;
 
+/// Enum representing the kinds of potential a factor can hold.
+#[derive(Clone, Debug)]
+pub enum Potential {
+    /// Potential over discrete variables computed by a function of their values.
+    Discrete(PotentialFunc),
+
+    /// Gaussian potential in information form over the stacked components of continuous variables.
+    Gaussian(GaussianPotential),
+
+    /// Least-squares potential `exp(-rho(|r(x)|))` over continuous variables, given by a residual function.
+    Nonlinear(NonlinearPotential),
+
+    /// Conditional linear Gaussian potential over continuous variables, indexed by discrete variables.
+    Hybrid(HybridPotential),
+
+    /// Arbitrary potential over continuous variables, given by a function computing its log.
+    Continuous(ContinuousPotential),
+
+    /// Tabulated log-potential over discrete variables, whose variables are the factor's in the same order.
+    Table(Table),
+
+    /// Log-linear potential over discrete variables, weighted by parameters of the graph.
+    LogLinear(LogLinearPotential),
+}
+
 /// Struct representing a factor over several variables.
+#[derive(Clone)]
 pub struct Factor {
     id: u32,
     variables: Vec<String>,
-    func: PotentialFunc,
+    potential: Potential,
 }
 
 impl Factor {
     /// Create a new Factor with associated variables.
     pub fn new(id: u32, variables: Vec<String>, func: PotentialFunc) -> Factor {
+        Factor::with_potential(id, variables, Potential::Discrete(func))
+    }
+
+    /// Create a new Factor with associated variables and any kind of potential.
+    pub fn with_potential(id: u32, variables: Vec<String>, potential: Potential) -> Factor {
         Factor {
             id,
             variables,
-            func
+            potential
         }
     }
 
@@ -32,6 +63,35 @@ impl Factor {
     pub fn get_variables(&self) -> &Vec<String> {
         &self.variables
     }
+
+    /// Function to get the potential held by this factor.
+    pub fn get_potential(&self) -> &Potential {
+        &self.potential
+    }
+
+    /// Evaluate the potential for the given values of this factor's discrete variables.
+    pub fn potential(&self, values: &[u32]) -> i32 {
+        match self.potential {
+            Potential::Discrete(func) => func(values),
+            _ => panic!("Factor {} does not have a discrete potential", self.get_name())
+        }
+    }
+
+    /// Evaluate the natural log of the potential for the given values of this factor's variables.
+    pub fn log_potential(&self, values: &[u32]) -> f64 {
+        match self.potential {
+            Potential::Table(ref table) => return table.log_value(values),
+            Potential::LogLinear(ref potential) => return potential.log_value(values),
+            _ => {}
+        }
+
+        let value = self.potential(values);
+        if value < 0 {
+            panic!("Factor {} has negative potential {} for values {:?}", self.get_name(), value, values);
+        }
+
+        f64::from(value).ln()
+    }
 }
 
 impl std::fmt::Debug for Factor {
@@ -47,7 +107,7 @@ impl FactorGraphItem for Factor {
     }
 
     fn get_id(&self) -> u32 {
-        self.id.clone()
+        self.id
     }
 
     fn is_factor(&self) -> bool {
@@ -0,0 +1,105 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with Gaussian potentials in information form
+
+use linalg::Matrix;
+
+/// Struct representing the Gaussian potential `exp(-x^T P x / 2 + h^T x)` in information form.
+///
+/// Here `P` is the precision matrix and `h` the information (or potential) vector, over the stacked
+/// components of the variables the potential is attached to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaussianPotential {
+    precision: Matrix,
+    information: Vec<f64>,
+}
+
+impl GaussianPotential {
+    /// Create a new GaussianPotential from its precision matrix and information vector.
+    pub fn new(precision: Matrix, information: Vec<f64>) -> GaussianPotential {
+        if precision.get_rows() != precision.get_cols() || precision.get_rows() != information.len() {
+            panic!("A Gaussian potential over {} components needs a square precision matrix of that size, \
+                    got {}x{}", information.len(), precision.get_rows(), precision.get_cols());
+        }
+
+        GaussianPotential {
+            precision,
+            information,
+        }
+    }
+
+    /// Create a GaussianPotential from a mean and a covariance matrix.
+    pub fn from_moments(mean: &[f64], covariance: &Matrix) -> GaussianPotential {
+        let precision = match covariance.inverse() {
+            Some(x) => x,
+            None => panic!("Covariance matrix is singular")
+        };
+        let information = precision.mul_vec(mean);
+        GaussianPotential::new(precision, information)
+    }
+
+    /// Create a GaussianPotential over zero components, which has no effect when combined.
+    pub fn empty() -> GaussianPotential {
+        GaussianPotential::new(Matrix::zeros(0, 0), vec!())
+    }
+
+    /// Function to get the precision matrix.
+    pub fn get_precision(&self) -> &Matrix {
+        &self.precision
+    }
+
+    /// Function to get the information vector.
+    pub fn get_information(&self) -> &Vec<f64> {
+        &self.information
+    }
+
+    /// Get the number of components the potential is defined over.
+    pub fn dimension(&self) -> usize {
+        self.information.len()
+    }
+
+    /// Get the mean, or None if the precision matrix is singular.
+    pub fn mean(&self) -> Option<Vec<f64>> {
+        self.precision.solve_vec(&self.information)
+    }
+
+    /// Get the covariance matrix, or None if the precision matrix is singular.
+    pub fn covariance(&self) -> Option<Matrix> {
+        self.precision.inverse()
+    }
+
+    /// Add another potential over the same components, multiplying the two densities.
+    pub fn combine(&self, other: &GaussianPotential) -> GaussianPotential {
+        GaussianPotential::new(&self.precision + &other.precision,
+                               self.information.iter().zip(other.information.iter()).map(|(a, b)| a + b).collect())
+    }
+
+    /// Integrate out every component not in `keep`, returning the potential over the kept components.
+    ///
+    /// Returns None if the precision of the integrated components is singular.
+    pub fn marginalize(&self, keep: &[usize]) -> Option<GaussianPotential> {
+        let rest: Vec<usize> = (0..self.dimension()).filter(|i| !keep.contains(i)).collect();
+        if rest.is_empty() {
+            return Some(GaussianPotential::new(self.precision.select(keep, keep),
+                                               keep.iter().map(|&i| self.information[i]).collect()));
+        }
+
+        let p_kk = self.precision.select(keep, keep);
+        let p_kr = self.precision.select(keep, &rest);
+        let p_rr = self.precision.select(&rest, &rest);
+        let h_k: Vec<f64> = keep.iter().map(|&i| self.information[i]).collect();
+        let h_r: Vec<f64> = rest.iter().map(|&i| self.information[i]).collect();
+
+        // Schur complement: P_kk - P_kr P_rr^-1 P_rk and h_k - P_kr P_rr^-1 h_r.
+        let solved = p_rr.solve(&p_kr.transpose())?;
+        let shift = p_rr.solve_vec(&h_r)?;
+        let precision = &p_kk - &(&p_kr * &solved);
+        let information = h_k.iter().zip(p_kr.mul_vec(&shift).iter()).map(|(h, c)| h - c).collect();
+        Some(GaussianPotential::new(precision, information))
+    }
+}
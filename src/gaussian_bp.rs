@@ -0,0 +1,411 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with Gaussian belief propagation over continuous factor graphs
+
+use *;
+use gaussian::GaussianPotential;
+use linalg::Matrix;
+
+/// Get the Gaussian potential of a factor, panicking if it holds any other kind.
+fn gaussian_potential(factor: &Factor) -> &GaussianPotential {
+    match *factor.get_potential() {
+        Potential::Gaussian(ref potential) => potential,
+        _ => panic!("Factor {} does not have a Gaussian potential", factor.get_name())
+    }
+}
+
+/// Assemble the product of every Gaussian factor into one potential over all continuous variables.
+///
+/// Returns the variables with the offset of their first component, in the order of
+/// `FactorGraph::get_variable_names`, alongside the joint potential.
+pub(crate) fn joint_potential(graph: &FactorGraph) -> (Vec<(String, usize)>, GaussianPotential) {
+    let mut offsets = vec!();
+    let mut size = 0;
+    for name in graph.get_variable_names() {
+        let dimension = graph.dimension(&name);
+        if dimension > 0 {
+            offsets.push((name, size));
+            size += dimension;
+        }
+    }
+
+    let mut precision = Matrix::zeros(size, size);
+    let mut information = vec![0.0; size];
+    for factor in graph.get_factors() {
+        let potential = gaussian_potential(factor);
+        let indices: Vec<usize> = factor.get_variables().iter()
+            .flat_map(|var| {
+                let offset = offsets.iter().find(|entry| &entry.0 == var).unwrap().1;
+                offset..offset + graph.dimension(var)
+            })
+            .collect();
+
+        for (i, &row) in indices.iter().enumerate() {
+            information[row] += potential.get_information()[i];
+            for (j, &col) in indices.iter().enumerate() {
+                precision[(row, col)] += potential.get_precision()[(i, j)];
+            }
+        }
+    }
+
+    (offsets, GaussianPotential::new(precision, information))
+}
+
+/// Struct holding diagnostics on whether Gaussian belief propagation can be trusted on a graph.
+///
+/// Writing the normalized joint precision as `I - R`, the graph is walk-summable when the spectral
+/// radius of `|R|` is below one. Walk-summable models are positive definite, and on them Gaussian BP
+/// converges to the exact means even when the graph has cycles.
+#[derive(Clone, Copy, Debug)]
+pub struct WalkSummability {
+    /// Spectral radius of the absolute normalized partial correlation matrix `|R|`.
+    pub spectral_radius: f64,
+
+    /// Whether the spectral radius is below one.
+    pub walk_summable: bool,
+
+    /// Whether the joint precision matrix is positive definite, so that the model is a proper Gaussian.
+    pub positive_definite: bool,
+}
+
+impl WalkSummability {
+    /// Compute the walk-summability diagnostics for the Gaussian factors of the graph.
+    pub fn new(graph: &FactorGraph) -> WalkSummability {
+        let (_, joint) = joint_potential(graph);
+        let precision = joint.get_precision();
+        let n = precision.get_rows();
+        let positive_definite = precision.cholesky().is_some();
+
+        let diagonal = precision.diagonal();
+        if diagonal.iter().any(|&d| d <= 0.0) {
+            return WalkSummability {
+                spectral_radius: f64::INFINITY,
+                walk_summable: false,
+                positive_definite,
+            };
+        }
+
+        let mut abs_r = Matrix::zeros(n, n);
+        for i in 0..n {
+            for j in 0..n {
+                if i != j {
+                    abs_r[(i, j)] = precision[(i, j)].abs() / (diagonal[i] * diagonal[j]).sqrt();
+                }
+            }
+        }
+
+        // Power iteration from a positive vector converges to the Perron root of the non-negative |R|.
+        let mut x = vec![1.0; n];
+        let mut spectral_radius = 0.0;
+        for _ in 0..1000 {
+            let y = abs_r.mul_vec(&x);
+            let norm = y.iter().map(|v| v * v).sum::<f64>().sqrt();
+            let previous_norm = x.iter().map(|v| v * v).sum::<f64>().sqrt();
+            let estimate = norm / previous_norm;
+            if norm == 0.0 {
+                spectral_radius = 0.0;
+                break;
+            }
+
+            x = y.iter().map(|v| v / norm).collect();
+            let change = (estimate - spectral_radius).abs();
+            spectral_radius = estimate;
+            if change < 1e-12 {
+                break;
+            }
+        }
+
+        WalkSummability {
+            spectral_radius,
+            walk_summable: spectral_radius < 1.0,
+            positive_definite,
+        }
+    }
+}
+
+/// Struct holding the beliefs computed by Gaussian belief propagation.
+#[derive(Clone, Debug)]
+pub struct GaussianBpResult {
+    /// Mean of the belief over each variable.
+    pub means: Values,
+
+    /// Covariance of the belief over each variable.
+    pub covariances: HashMap<String, Matrix>,
+
+    /// Number of message passing iterations that were run.
+    pub iterations: usize,
+
+    /// Whether the messages converged before the iteration limit.
+    pub converged: bool,
+}
+
+impl GaussianBpResult {
+    /// Get the variance of each component of a variable's belief.
+    pub fn variance(&self, name: &str) -> Option<Vec<f64>> {
+        self.covariances.get(name).map(|covariance| covariance.diagonal())
+    }
+}
+
+/// Struct running Gaussian belief propagation with parallel message updates in information form.
+///
+/// On graphs without cycles the means and covariances are exact. On loopy graphs the means are exact
+/// whenever the messages converge, which is guaranteed for walk-summable models (see `WalkSummability`),
+/// while the covariances are generally overconfident.
+#[derive(Debug)]
+pub struct GaussianBeliefPropagation<'a> {
+    graph: &'a FactorGraph,
+    max_iterations: usize,
+    tolerance: f64,
+    damping: f64,
+}
+
+impl<'a> GaussianBeliefPropagation<'a> {
+    /// Create a new GaussianBeliefPropagation for the graph, running at most 100 iterations without damping.
+    pub fn new(graph: &'a FactorGraph) -> GaussianBeliefPropagation<'a> {
+        GaussianBeliefPropagation {
+            graph,
+            max_iterations: 100,
+            tolerance: 1e-10,
+            damping: 0.0,
+        }
+    }
+
+    /// Set the maximum number of message passing iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> GaussianBeliefPropagation<'a> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Stop once no message entry changes by more than `tolerance` in an iteration.
+    pub fn with_tolerance(mut self, tolerance: f64) -> GaussianBeliefPropagation<'a> {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Keep a fraction `damping` of each old message, in information form, to help loopy graphs converge.
+    pub fn with_damping(mut self, damping: f64) -> GaussianBeliefPropagation<'a> {
+        if !(0.0..1.0).contains(&damping) {
+            panic!("Damping must be in [0, 1), got {}", damping);
+        }
+
+        self.damping = damping;
+        self
+    }
+
+    /// Pass messages until convergence and compute the beliefs.
+    ///
+    /// Variables whose belief has a singular precision, such as those without any factors, are left out
+    /// of the result.
+    pub fn run(&self) -> GaussianBpResult {
+        let factors = self.graph.get_factors();
+        let potentials: Vec<&GaussianPotential> = factors.iter().map(gaussian_potential).collect();
+        let dimensions: Vec<Vec<usize>> = factors.iter()
+            .map(|f| f.get_variables().iter().map(|var| self.graph.dimension(var)).collect())
+            .collect();
+
+        // Messages are indexed by factor and position within the factor's scope.
+        let mut to_var: Vec<Vec<GaussianPotential>> = dimensions.iter()
+            .map(|dims| dims.iter().map(|&d| GaussianPotential::new(Matrix::zeros(d, d), vec![0.0; d])).collect())
+            .collect();
+
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.max_iterations && !converged {
+            iterations += 1;
+
+            let mut max_change: f64 = 0.0;
+            let mut new_to_var = to_var.clone();
+            for (f, messages) in new_to_var.iter_mut().enumerate() {
+                for (pos, message) in messages.iter_mut().enumerate() {
+                    let mut updated = self.factor_message(&potentials, &dimensions, &to_var, f, pos);
+                    if self.damping > 0.0 {
+                        updated = GaussianPotential::new(
+                            &updated.get_precision().scaled(1.0 - self.damping)
+                                + &to_var[f][pos].get_precision().scaled(self.damping),
+                            updated.get_information().iter().zip(to_var[f][pos].get_information().iter())
+                                .map(|(new, old)| (1.0 - self.damping) * new + self.damping * old)
+                                .collect());
+                    }
+
+                    let old = &to_var[f][pos];
+                    for (a, b) in updated.get_precision().get_data().iter().zip(old.get_precision().get_data().iter())
+                        .chain(updated.get_information().iter().zip(old.get_information().iter())) {
+                        max_change = max_change.max((a - b).abs());
+                    }
+                    *message = updated;
+                }
+            }
+
+            to_var = new_to_var;
+            converged = max_change < self.tolerance;
+        }
+
+        let mut means = Values::new();
+        let mut covariances = HashMap::new();
+        for name in self.graph.get_variable_names() {
+            let dimension = self.graph.dimension(&name);
+            if dimension == 0 {
+                continue;
+            }
+
+            let belief = self.variable_belief(&to_var, &name, dimension, None);
+            if let (Some(mean), Some(covariance)) = (belief.mean(), belief.covariance()) {
+                means.insert(name.clone(), mean);
+                covariances.insert(name, covariance);
+            }
+        }
+
+        GaussianBpResult {
+            means,
+            covariances,
+            iterations,
+            converged,
+        }
+    }
+
+    /// Combine the messages into a variable from every factor except the one at `skip`.
+    fn variable_belief(&self, to_var: &[Vec<GaussianPotential>], name: &str, dimension: usize,
+                       skip: Option<(usize, usize)>) -> GaussianPotential {
+        let mut belief = GaussianPotential::new(Matrix::zeros(dimension, dimension), vec![0.0; dimension]);
+        for (f, factor) in self.graph.get_factors().iter().enumerate() {
+            for (pos, var) in factor.get_variables().iter().enumerate() {
+                if var == name && skip != Some((f, pos)) {
+                    belief = belief.combine(&to_var[f][pos]);
+                }
+            }
+        }
+        belief
+    }
+
+    /// Compute the message from factor `f` to the variable at position `pos` of its scope.
+    ///
+    /// If the other variables cannot be integrated out yet, the message carries no information.
+    fn factor_message(&self, potentials: &[&GaussianPotential], dimensions: &[Vec<usize>],
+                      to_var: &[Vec<GaussianPotential>], f: usize, pos: usize) -> GaussianPotential {
+        let variables = self.graph.get_factors()[f].get_variables();
+        let mut precision = potentials[f].get_precision().clone();
+        let mut information = potentials[f].get_information().clone();
+        let mut keep = vec!();
+
+        let mut offset = 0;
+        for (other_pos, (var, &dimension)) in variables.iter().zip(dimensions[f].iter()).enumerate() {
+            if other_pos == pos {
+                keep.extend(offset..offset + dimension);
+            } else {
+                let incoming = self.variable_belief(to_var, var, dimension, Some((f, other_pos)));
+                precision.add_block(offset, offset, incoming.get_precision());
+                for (i, h) in incoming.get_information().iter().enumerate() {
+                    information[offset + i] += h;
+                }
+            }
+            offset += dimension;
+        }
+
+        let dimension = dimensions[f][pos];
+        GaussianPotential::new(precision, information).marginalize(&keep)
+            .unwrap_or_else(|| GaussianPotential::new(Matrix::zeros(dimension, dimension), vec![0.0; dimension]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::names;
+
+    /// Add the factor `exp(-w (x - y)^2 / 2)` between two scalar variables.
+    fn add_coupling(graph: &mut FactorGraph, x: &str, y: &str, w: f64) {
+        let precision = Matrix::from_rows(&[vec!(w, -w), vec!(-w, w)]);
+        graph.add_gaussian_factor(names(&[x, y]), GaussianPotential::new(precision, vec!(0.0, 0.0)));
+    }
+
+    fn add_prior(graph: &mut FactorGraph, x: &str, mean: f64, precision: f64) {
+        graph.add_gaussian_factor(names(&[x]),
+                                  GaussianPotential::new(Matrix::from_diagonal(&[precision]), vec!(precision * mean)));
+    }
+
+    fn exact_moments(graph: &FactorGraph) -> (Vec<(String, usize)>, Vec<f64>, Matrix) {
+        let (offsets, joint) = joint_potential(graph);
+        (offsets, joint.mean().unwrap(), joint.covariance().unwrap())
+    }
+
+    #[test]
+    fn beliefs_are_exact_on_trees() {
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("pose", 2);
+        graph.add_gaussian_var("a", 1);
+        graph.add_gaussian_var("b", 1);
+
+        let prior = Matrix::from_rows(&[vec!(2.0, 0.5), vec!(0.5, 1.0)]);
+        graph.add_gaussian_factor(names(&["pose"]), GaussianPotential::new(prior, vec!(1.0, -1.0)));
+
+        // Each scalar observes one component of the pose with a bias.
+        let coupling = Matrix::from_rows(&[vec!(1.0, 0.0, -1.0), vec!(0.0, 0.0, 0.0), vec!(-1.0, 0.0, 1.0)]);
+        graph.add_gaussian_factor(names(&["pose", "a"]), GaussianPotential::new(coupling, vec!(0.3, 0.0, -0.3)));
+        let coupling = Matrix::from_rows(&[vec!(0.0, 0.0, 0.0), vec!(0.0, 3.0, -3.0), vec!(0.0, -3.0, 3.0)]);
+        graph.add_gaussian_factor(names(&["pose", "b"]), GaussianPotential::new(coupling, vec!(0.0, 0.0, 0.0)));
+        add_prior(&mut graph, "b", 2.0, 0.5);
+
+        let result = GaussianBeliefPropagation::new(&graph).run();
+        let (offsets, mean, covariance) = exact_moments(&graph);
+
+        assert!(result.converged);
+        for (name, offset) in offsets {
+            let dimension = graph.dimension(&name);
+            for i in 0..dimension {
+                assert!((result.means[&name][i] - mean[offset + i]).abs() < 1e-9);
+                for j in 0..dimension {
+                    assert!((result.covariances[&name][(i, j)] - covariance[(offset + i, offset + j)]).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn means_are_exact_on_walk_summable_loops() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d"] {
+            graph.add_gaussian_var(name, 1);
+        }
+        for &(x, y) in [("a", "b"), ("b", "c"), ("c", "d"), ("d", "a"), ("a", "c")].iter() {
+            add_coupling(&mut graph, x, y, 0.5);
+        }
+        add_prior(&mut graph, "a", 1.0, 1.0);
+        add_prior(&mut graph, "c", -2.0, 0.5);
+        add_prior(&mut graph, "d", 0.5, 2.0);
+
+        let diagnostics = WalkSummability::new(&graph);
+        assert!(diagnostics.walk_summable && diagnostics.positive_definite);
+
+        let result = GaussianBeliefPropagation::new(&graph).with_max_iterations(500).run();
+        let (offsets, mean, covariance) = exact_moments(&graph);
+
+        assert!(result.converged);
+        for (name, offset) in offsets {
+            assert!((result.means[&name][0] - mean[offset]).abs() < 1e-8);
+            assert!(result.variance(&name).unwrap()[0] < covariance[(offset, offset)]);
+        }
+    }
+
+    #[test]
+    fn walk_summability_is_stricter_than_positive_definiteness() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_gaussian_var(name, 1);
+            add_prior(&mut graph, name, 0.0, 1.0);
+        }
+        for &(x, y) in [("a", "b"), ("b", "c"), ("c", "a")].iter() {
+            let precision = Matrix::from_rows(&[vec!(0.0, 0.6), vec!(0.6, 0.0)]);
+            graph.add_gaussian_factor(names(&[x, y]), GaussianPotential::new(precision, vec!(0.0, 0.0)));
+        }
+
+        let diagnostics = WalkSummability::new(&graph);
+        assert!((diagnostics.spectral_radius - 1.2).abs() < 1e-9);
+        assert!(!diagnostics.walk_summable);
+        assert!(diagnostics.positive_definite);
+    }
+}
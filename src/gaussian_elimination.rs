@@ -0,0 +1,300 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with exact elimination of Gaussian factors into a Gaussian Bayes net
+
+use std::error::Error;
+use std::fmt;
+
+use *;
+
+/// Error returned when a variable's precision is singular, so the linear system has no unique solution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndeterminateSystem {
+    /// Variable that could not be eliminated.
+    pub variable: String,
+}
+
+impl fmt::Display for IndeterminateSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "variable {} is not fully constrained by its factors", self.variable)
+    }
+}
+
+impl Error for IndeterminateSystem {}
+
+/// Struct representing a Gaussian potential in information form together with the variables it is over.
+#[derive(Clone, Debug)]
+pub struct GaussianFactor {
+    variables: Vec<String>,
+    dimensions: Vec<usize>,
+    potential: GaussianPotential,
+}
+
+impl GaussianFactor {
+    /// Create a new GaussianFactor over the stacked components of variables with the given dimensions.
+    pub fn new(variables: Vec<String>, dimensions: Vec<usize>, potential: GaussianPotential) -> GaussianFactor {
+        if variables.len() != dimensions.len() || dimensions.iter().sum::<usize>() != potential.dimension() {
+            panic!("Gaussian factor over {:?} with dimensions {:?} cannot have a potential over {} components",
+                   variables, dimensions, potential.dimension());
+        }
+
+        GaussianFactor {
+            variables,
+            dimensions,
+            potential,
+        }
+    }
+
+    /// Function to get the variables of the factor.
+    pub fn get_variables(&self) -> &Vec<String> {
+        &self.variables
+    }
+
+    /// Function to get the number of components of each variable.
+    pub fn get_dimensions(&self) -> &Vec<usize> {
+        &self.dimensions
+    }
+
+    /// Function to get the potential.
+    pub fn get_potential(&self) -> &GaussianPotential {
+        &self.potential
+    }
+}
+
+/// Struct representing the Gaussian conditional `R x + sum_j S_j x_j = d` of a variable given its parents.
+///
+/// `R` is upper triangular, so stacking the conditionals in elimination order gives the square-root
+/// information matrix of the joint.
+#[derive(Clone, Debug)]
+pub struct GaussianConditional {
+    variable: String,
+    parents: Vec<String>,
+    r: Matrix,
+    s: Vec<Matrix>,
+    d: Vec<f64>,
+}
+
+impl GaussianConditional {
+    /// Eliminate a variable from the factors that contain it.
+    ///
+    /// Returns the conditional of the variable given the other variables of the factors, and the factor
+    /// over those other variables that remains, if there are any.
+    pub fn eliminate(factors: &[GaussianFactor], variable: &str)
+        -> Result<(GaussianConditional, Option<GaussianFactor>), IndeterminateSystem> {
+        let indeterminate = || IndeterminateSystem { variable: String::from(variable) };
+
+        let frontal = factors.iter()
+            .filter_map(|f| f.variables.iter().position(|v| v == variable).map(|pos| f.dimensions[pos]))
+            .next()
+            .ok_or_else(indeterminate)?;
+
+        let mut variables = vec!(String::from(variable));
+        let mut dimensions = vec!(frontal);
+        for factor in factors.iter() {
+            for (var, &dimension) in factor.variables.iter().zip(factor.dimensions.iter()) {
+                if !variables.contains(var) {
+                    variables.push(var.clone());
+                    dimensions.push(dimension);
+                }
+            }
+        }
+
+        let size: usize = dimensions.iter().sum();
+        let offsets: Vec<usize> = (0..dimensions.len()).map(|i| dimensions[..i].iter().sum()).collect();
+        let mut precision = Matrix::zeros(size, size);
+        let mut information = vec![0.0; size];
+        for factor in factors.iter() {
+            let indices: Vec<usize> = factor.variables.iter()
+                .flat_map(|v| {
+                    let pos = variables.iter().position(|x| x == v).unwrap();
+                    offsets[pos]..offsets[pos] + dimensions[pos]
+                })
+                .collect();
+            for (i, &row) in indices.iter().enumerate() {
+                information[row] += factor.potential.get_information()[i];
+                for (j, &col) in indices.iter().enumerate() {
+                    precision[(row, col)] += factor.potential.get_precision()[(i, j)];
+                }
+            }
+        }
+
+        // With L L^T the precision of the frontal variable, R = L^T, S = L^-1 P_fs and d = L^-1 h_f, and
+        // the separator keeps the Schur complement P_ss - S^T S and h_s - S^T d.
+        let rest = precision.get_rows() - frontal;
+        let l = precision.block(0, 0, frontal, frontal).cholesky().ok_or_else(indeterminate)?;
+        let s = l.solve(&precision.block(0, frontal, frontal, rest)).ok_or_else(indeterminate)?;
+        let d = l.solve_vec(&information[..frontal]).ok_or_else(indeterminate)?;
+
+        let mut parent_blocks = vec!();
+        let mut offset = 0;
+        for &dimension in dimensions[1..].iter() {
+            parent_blocks.push(s.block(0, offset, frontal, dimension));
+            offset += dimension;
+        }
+
+        let remaining = if rest > 0 {
+            let st = s.transpose();
+            let precision = &precision.block(frontal, frontal, rest, rest) - &(&st * &s);
+            let information = information[frontal..].iter().zip(st.mul_vec(&d).iter()).map(|(h, c)| h - c).collect();
+            Some(GaussianFactor::new(variables[1..].to_vec(), dimensions[1..].to_vec(),
+                                     GaussianPotential::new(precision, information)))
+        } else {
+            None
+        };
+
+        let conditional = GaussianConditional {
+            variable: String::from(variable),
+            parents: variables[1..].to_vec(),
+            r: l.transpose(),
+            s: parent_blocks,
+            d,
+        };
+        Ok((conditional, remaining))
+    }
+
+    /// Function to get the variable this conditional is over.
+    pub fn get_variable(&self) -> &String {
+        &self.variable
+    }
+
+    /// Function to get the variables this conditional depends on.
+    pub fn get_parents(&self) -> &Vec<String> {
+        &self.parents
+    }
+
+    /// Function to get the upper triangular matrix `R`.
+    pub fn get_r(&self) -> &Matrix {
+        &self.r
+    }
+
+    /// Function to get the matrix `S_j` for each parent.
+    pub fn get_s(&self) -> &Vec<Matrix> {
+        &self.s
+    }
+
+    /// Function to get the right hand side `d`.
+    pub fn get_d(&self) -> &Vec<f64> {
+        &self.d
+    }
+
+    /// Solve for the variable given values for all of its parents.
+    pub fn solve(&self, values: &Values) -> Vec<f64> {
+        let mut rhs = self.d.clone();
+        for (parent, s) in self.parents.iter().zip(self.s.iter()) {
+            let value = match values.get(parent) {
+                Some(x) => x,
+                None => panic!("No value for {}, a parent of {}", parent, self.variable)
+            };
+            for (r, c) in rhs.iter_mut().zip(s.mul_vec(value).iter()) {
+                *r -= c;
+            }
+        }
+
+        self.r.solve_vec(&rhs).unwrap()
+    }
+}
+
+/// Struct representing a Gaussian Bayes net of conditionals, in the order their variables were eliminated.
+#[derive(Clone, Debug)]
+pub struct GaussianBayesNet {
+    conditionals: Vec<GaussianConditional>,
+}
+
+impl GaussianBayesNet {
+    /// Eliminate every variable of the factors in the given order.
+    ///
+    /// Every variable of the factors must appear in the order.
+    pub fn eliminate(factors: Vec<GaussianFactor>, order: &[String]) -> Result<GaussianBayesNet, IndeterminateSystem> {
+        let mut factors = factors;
+        let mut conditionals = vec!();
+        for var in order {
+            let (involved, rest): (Vec<GaussianFactor>, Vec<GaussianFactor>) =
+                factors.into_iter().partition(|f| f.variables.contains(var));
+            factors = rest;
+
+            let (conditional, remaining) = GaussianConditional::eliminate(&involved, var)?;
+            conditionals.push(conditional);
+            if let Some(factor) = remaining {
+                factors.push(factor);
+            }
+        }
+
+        if let Some(factor) = factors.first() {
+            panic!("Variables {:?} are missing from the elimination order", factor.variables);
+        }
+
+        Ok(GaussianBayesNet {
+            conditionals,
+        })
+    }
+
+    /// Create a GaussianBayesNet from conditionals that are already in elimination order.
+    pub(crate) fn from_conditionals(conditionals: Vec<GaussianConditional>) -> GaussianBayesNet {
+        GaussianBayesNet {
+            conditionals,
+        }
+    }
+
+    /// Function to get the conditionals in elimination order.
+    pub fn get_conditionals(&self) -> &Vec<GaussianConditional> {
+        &self.conditionals
+    }
+
+    /// Find the most probable value of every variable by back-substitution.
+    pub fn solve(&self) -> Values {
+        let mut values = Values::new();
+        for conditional in self.conditionals.iter().rev() {
+            let value = conditional.solve(&values);
+            values.insert(conditional.variable.clone(), value);
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factor(variables: &[&str], dimensions: Vec<usize>, precision: &[Vec<f64>], information: Vec<f64>)
+        -> GaussianFactor {
+        GaussianFactor::new(variables.iter().map(|v| String::from(*v)).collect(), dimensions,
+                            GaussianPotential::new(Matrix::from_rows(precision), information))
+    }
+
+    #[test]
+    fn elimination_matches_dense_solve() {
+        let factors = vec!(
+            factor(&["x"], vec!(2), &[vec!(2.0, 0.3), vec!(0.3, 1.0)], vec!(1.0, 0.5)),
+            factor(&["x", "y"], vec!(2, 1),
+                   &[vec!(1.0, 0.0, -1.0), vec!(0.0, 0.5, 0.2), vec!(-1.0, 0.2, 1.5)], vec!(0.0, 0.1, -0.4)),
+            factor(&["y", "z"], vec!(1, 1), &[vec!(1.0, -1.0), vec!(-1.0, 1.0)], vec!(0.2, -0.2)),
+            factor(&["z"], vec!(1), &[vec!(0.5)], vec!(1.0)),
+        );
+
+        // The same system with components stacked as x, y, z.
+        let dense = GaussianPotential::new(
+            Matrix::from_rows(&[vec!(3.0, 0.3, -1.0, 0.0), vec!(0.3, 1.5, 0.2, 0.0),
+                                vec!(-1.0, 0.2, 2.5, -1.0), vec!(0.0, 0.0, -1.0, 1.5)]),
+            vec!(1.0, 0.6, -0.2, 0.8));
+        let expected = dense.mean().unwrap();
+
+        let order: Vec<String> = ["y", "x", "z"].iter().map(|v| String::from(*v)).collect();
+        let bayes_net = GaussianBayesNet::eliminate(factors.clone(), &order).unwrap();
+        let values = bayes_net.solve();
+
+        let stacked: Vec<f64> = ["x", "y", "z"].iter().flat_map(|v| values[*v].clone()).collect();
+        for (x, e) in stacked.iter().zip(expected.iter()) {
+            assert!((x - e).abs() < 1e-12);
+        }
+        assert_eq!(bayes_net.get_conditionals()[0].get_parents(), &vec!(String::from("x"), String::from("z")));
+
+        let unconstrained = factor(&["w"], vec!(1), &[vec!(0.0)], vec!(0.0));
+        let result = GaussianBayesNet::eliminate(vec!(unconstrained), &[String::from("w")]);
+        assert_eq!(result.unwrap_err().variable, "w");
+    }
+}
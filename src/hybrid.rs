@@ -0,0 +1,536 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with hybrid discrete-continuous models built from conditional linear Gaussian factors
+
+use std::f64::consts::PI;
+
+use *;
+use table::log_sum_exp;
+
+/// Compute the log-determinant of a symmetric positive definite matrix, or None if it is not one.
+fn log_determinant(matrix: &Matrix) -> Option<f64> {
+    matrix.cholesky().map(|l| 2.0 * l.diagonal().iter().map(|d| d.ln()).sum::<f64>())
+}
+
+/// Struct representing the Gaussian function `exp(g + h^T x - x^T K x / 2)` in canonical form.
+///
+/// Unlike a `GaussianPotential`, it keeps the log scale `g`, which hybrid inference needs to weigh
+/// the continuous parts of different discrete configurations against each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanonicalForm {
+    log_scale: f64,
+    potential: GaussianPotential,
+}
+
+impl CanonicalForm {
+    /// Create a new CanonicalForm from its log scale and its precision and information.
+    pub fn new(log_scale: f64, potential: GaussianPotential) -> CanonicalForm {
+        CanonicalForm {
+            log_scale,
+            potential,
+        }
+    }
+
+    /// Create the CanonicalForm of a Gaussian with the given mean and covariance, scaled to have total
+    /// mass `exp(log_mass)`.
+    pub fn from_moments(log_mass: f64, mean: &[f64], covariance: &Matrix) -> CanonicalForm {
+        let potential = GaussianPotential::from_moments(mean, covariance);
+        let log_det = match log_determinant(covariance) {
+            Some(x) => x,
+            None => panic!("Covariance matrix is not positive definite")
+        };
+        let quadratic: f64 = mean.iter().zip(potential.get_information().iter()).map(|(m, h)| m * h).sum();
+        let log_scale = log_mass - 0.5 * (mean.len() as f64 * (2.0 * PI).ln() + log_det + quadratic);
+
+        CanonicalForm::new(log_scale, potential)
+    }
+
+    /// Create the normalized density of a Gaussian with the given mean and covariance.
+    pub fn density(mean: &[f64], covariance: &Matrix) -> CanonicalForm {
+        CanonicalForm::from_moments(0.0, mean, covariance)
+    }
+
+    /// Create the conditional density `p(y | x) = N(y; A x + b, noise)`, over `x` followed by `y`.
+    pub fn linear_gaussian(a: &Matrix, b: &[f64], noise: &Matrix) -> CanonicalForm {
+        let noise_precision = match noise.inverse() {
+            Some(x) => x,
+            None => panic!("Noise covariance matrix is singular")
+        };
+        let log_det = match log_determinant(noise) {
+            Some(x) => x,
+            None => panic!("Noise covariance matrix is not positive definite")
+        };
+
+        // The density is exp(-(y - A x - b)^T Q^-1 (y - A x - b) / 2) / sqrt(|2 pi Q|).
+        let (cols, rows) = (a.get_cols(), a.get_rows());
+        let qa = &noise_precision * a;
+        let mut precision = Matrix::zeros(cols + rows, cols + rows);
+        precision.add_block(0, 0, &(&a.transpose() * &qa));
+        precision.add_block(0, cols, &qa.transpose().scaled(-1.0));
+        precision.add_block(cols, 0, &qa.scaled(-1.0));
+        precision.add_block(cols, cols, &noise_precision);
+
+        let qb = noise_precision.mul_vec(b);
+        let mut information: Vec<f64> = qa.transpose().mul_vec(b).iter().map(|v| -v).collect();
+        information.extend(qb.iter());
+
+        let quadratic: f64 = b.iter().zip(qb.iter()).map(|(x, y)| x * y).sum();
+        let log_scale = -0.5 * (rows as f64 * (2.0 * PI).ln() + log_det + quadratic);
+        CanonicalForm::new(log_scale, GaussianPotential::new(precision, information))
+    }
+
+    /// Function to get the log scale `g`.
+    pub fn get_log_scale(&self) -> f64 {
+        self.log_scale
+    }
+
+    /// Function to get the precision and information.
+    pub fn get_potential(&self) -> &GaussianPotential {
+        &self.potential
+    }
+
+    /// Get the log of the integral over every component, or None if the precision is not positive definite.
+    pub fn log_integral(&self) -> Option<f64> {
+        let log_det = log_determinant(self.potential.get_precision())?;
+        let mean = self.potential.mean()?;
+        let quadratic: f64 = mean.iter().zip(self.potential.get_information().iter()).map(|(m, h)| m * h).sum();
+        Some(self.log_scale + 0.5 * (mean.len() as f64 * (2.0 * PI).ln() - log_det + quadratic))
+    }
+}
+
+/// Collapse a Gaussian mixture into the single Gaussian with the same total mass, mean and covariance.
+///
+/// Each component is given as its log mass, mean and covariance.
+pub fn collapse_mixture(components: &[(f64, Vec<f64>, Matrix)]) -> (f64, Vec<f64>, Matrix) {
+    let log_masses: Vec<f64> = components.iter().map(|c| c.0).collect();
+    let log_mass = log_sum_exp(&log_masses);
+    let dimension = components[0].1.len();
+
+    let mut mean = vec![0.0; dimension];
+    for &(w, ref m, _) in components.iter() {
+        for (total, x) in mean.iter_mut().zip(m.iter()) {
+            *total += (w - log_mass).exp() * x;
+        }
+    }
+
+    let mut covariance = Matrix::zeros(dimension, dimension);
+    for &(w, ref m, ref c) in components.iter() {
+        let p = (w - log_mass).exp();
+        for i in 0..dimension {
+            for j in 0..dimension {
+                covariance[(i, j)] += p * (c[(i, j)] + (m[i] - mean[i]) * (m[j] - mean[j]));
+            }
+        }
+    }
+    (log_mass, mean, covariance)
+}
+
+/// Struct representing a conditional linear Gaussian potential, a canonical form over a factor's continuous
+/// variables for every configuration of its discrete variables.
+///
+/// Components are indexed like a `Table` over the factor's discrete variables in the factor's order, and
+/// each is over the stacked components of the factor's continuous variables in the factor's order.
+#[derive(Clone, Debug)]
+pub struct HybridPotential {
+    components: Vec<CanonicalForm>,
+}
+
+impl HybridPotential {
+    /// Create a new HybridPotential from its component for each discrete configuration.
+    pub fn new(components: Vec<CanonicalForm>) -> HybridPotential {
+        if components.is_empty() {
+            panic!("A hybrid potential needs at least one component");
+        }
+        if components.iter().any(|c| c.potential.dimension() != components[0].potential.dimension()) {
+            panic!("Every component of a hybrid potential must be over the same number of components");
+        }
+
+        HybridPotential {
+            components,
+        }
+    }
+
+    /// Function to get the component for each discrete configuration.
+    pub fn get_components(&self) -> &Vec<CanonicalForm> {
+        &self.components
+    }
+
+    /// Get the number of continuous components each canonical form is over.
+    pub fn dimension(&self) -> usize {
+        self.components[0].potential.dimension()
+    }
+}
+
+/// Struct holding the results of inference on a hybrid graph.
+#[derive(Clone, Debug)]
+pub struct HybridResult {
+    /// Log partition function, integrating over the continuous and summing over the discrete variables.
+    pub log_partition: f64,
+
+    /// Marginal distribution of every discrete variable.
+    pub marginals: Marginals,
+
+    /// Mean of every continuous variable.
+    pub means: Values,
+
+    /// Covariance of every continuous variable.
+    pub covariances: HashMap<String, Matrix>,
+
+    /// Number of Gaussian components in the posterior mixture over the continuous variables.
+    pub num_components: usize,
+}
+
+/// Struct representing one Gaussian component of the posterior, for a set of discrete configurations.
+#[derive(Clone)]
+struct Hypothesis {
+    values: Vec<Option<u32>>,
+    beliefs: Vec<Vec<f64>>,
+    form: CanonicalForm,
+}
+
+impl Hypothesis {
+    /// Multiply a canonical form over the continuous components at `indices` into the hypothesis.
+    fn absorb(&mut self, form: &CanonicalForm, indices: &[usize]) {
+        let mut precision = self.form.potential.get_precision().clone();
+        let mut information = self.form.potential.get_information().clone();
+        for (i, &row) in indices.iter().enumerate() {
+            information[row] += form.potential.get_information()[i];
+            for (j, &col) in indices.iter().enumerate() {
+                precision[(row, col)] += form.potential.get_precision()[(i, j)];
+            }
+        }
+        self.form = CanonicalForm::new(self.form.log_scale + form.log_scale, GaussianPotential::new(precision, information));
+    }
+
+    /// Get the log mass, mean and covariance over the continuous components in `covered`.
+    fn moments(&self, covered: &[usize]) -> Option<(f64, Vec<f64>, Matrix)> {
+        let potential = self.form.potential.get_precision().select(covered, covered);
+        let information = covered.iter().map(|&i| self.form.potential.get_information()[i]).collect();
+        let form = CanonicalForm::new(self.form.log_scale, GaussianPotential::new(potential, information));
+        Some((form.log_integral()?, form.potential.mean()?, form.potential.covariance()?))
+    }
+}
+
+/// Struct running inference on graphs mixing discrete variables with Gaussian variables.
+///
+/// Factors may be discrete, Gaussian, or conditional linear Gaussian (see `FactorGraph::add_hybrid_factor`).
+/// The discrete variables are assigned one at a time, in the order they were added to the graph, keeping a
+/// Gaussian component for every configuration. This is exact, but the discrete variables are not eliminated
+/// along their structure, so the cost is exponential in their number even when they only form a chain; exact
+/// inference is only practical for a few discrete variables. With `with_collapse`, after each discrete variable
+/// the components that agree on every assigned variable still needed by a later factor are collapsed into one
+/// Gaussian with the same moments, as in generalized pseudo-Bayesian filtering of switching models, so the
+/// number of components only depends on the assigned variables that later factors still need. Collapsed
+/// components are only merged once their Gaussians are proper, so ordering the discrete variables in time gives the
+/// best results.
+#[derive(Debug)]
+pub struct HybridInference<'a> {
+    graph: &'a FactorGraph,
+    collapse: bool,
+}
+
+impl<'a> HybridInference<'a> {
+    /// Create a new HybridInference for the graph, doing exact inference over every discrete configuration.
+    pub fn new(graph: &'a FactorGraph) -> HybridInference<'a> {
+        HybridInference {
+            graph,
+            collapse: false,
+        }
+    }
+
+    /// Collapse components that later factors cannot tell apart into a single Gaussian.
+    pub fn with_collapse(mut self) -> HybridInference<'a> {
+        self.collapse = true;
+        self
+    }
+
+    /// Run inference.
+    ///
+    /// Returns an error if the continuous variables are not fully determined for some discrete configuration.
+    pub fn run(&self) -> Result<HybridResult, IndeterminateSystem> {
+        let names = self.graph.get_variable_names();
+        let discrete: Vec<String> = names.iter().filter(|v| self.graph.dimension(v) == 0).cloned().collect();
+        let mut offsets: HashMap<String, usize> = HashMap::new();
+        let mut size = 0;
+        for name in names.iter().filter(|v| self.graph.dimension(v) > 0) {
+            offsets.insert(name.clone(), size);
+            size += self.graph.dimension(name);
+        }
+
+        // Each factor is multiplied in once its last discrete variable is assigned.
+        let position = |var: &String| discrete.iter().position(|v| v == var);
+        let ready: Vec<Option<usize>> = self.graph.get_factors().iter()
+            .map(|f| f.get_variables().iter().filter_map(&position).max())
+            .collect();
+
+        let empty = CanonicalForm::new(0.0, GaussianPotential::new(Matrix::zeros(size, size), vec![0.0; size]));
+        let mut hypotheses = vec!(Hypothesis {
+            values: vec![None; discrete.len()],
+            beliefs: vec![vec!(); discrete.len()],
+            form: empty,
+        });
+        let mut covered = vec![false; size];
+        self.absorb_ready(&mut hypotheses, &ready, None, &offsets, &mut covered);
+
+        for (k, var) in discrete.iter().enumerate() {
+            let card = self.graph.domain_size(var);
+            let mut extended = Vec::with_capacity(hypotheses.len() * card);
+            for hypothesis in hypotheses.iter() {
+                for val in 0..card {
+                    let mut next = hypothesis.clone();
+                    next.values[k] = Some(val as u32);
+                    next.beliefs[k] = (0..card).map(|v| if v == val { 1.0 } else { 0.0 }).collect();
+                    extended.push(next);
+                }
+            }
+            hypotheses = extended;
+            self.absorb_ready(&mut hypotheses, &ready, Some(k), &offsets, &mut covered);
+
+            if self.collapse {
+                let frontier: Vec<usize> = (0..=k)
+                    .filter(|&j| self.graph.get_factors().iter().zip(ready.iter())
+                        .any(|(f, r)| r.is_some_and(|r| r > k) && f.get_variables().contains(&discrete[j])))
+                    .collect();
+                hypotheses = self.merge(hypotheses, &frontier, &covered);
+            }
+        }
+
+        let all: Vec<usize> = (0..size).collect();
+        let mut components = vec!();
+        for hypothesis in hypotheses.iter() {
+            match hypothesis.moments(&all) {
+                Some(moments) => components.push(moments),
+                None => {
+                    let continuous: Vec<String> = names.iter().filter(|v| offsets.contains_key(*v)).cloned().collect();
+                    let dimensions = continuous.iter().map(|v| self.graph.dimension(v)).collect();
+                    let factor = GaussianFactor::new(continuous.clone(), dimensions, hypothesis.form.potential.clone());
+                    let error = GaussianBayesNet::eliminate(vec!(factor), &continuous).err();
+                    return Err(error.unwrap_or(IndeterminateSystem { variable: continuous[0].clone() }));
+                }
+            }
+        }
+
+        let (log_partition, mean, covariance) = collapse_mixture(&components);
+        let mut marginals = Marginals::new();
+        for (k, var) in discrete.iter().enumerate() {
+            let mut marginal = vec![0.0; self.graph.domain_size(var)];
+            for (hypothesis, component) in hypotheses.iter().zip(components.iter()) {
+                let p = (component.0 - log_partition).exp();
+                for (total, b) in marginal.iter_mut().zip(hypothesis.beliefs[k].iter()) {
+                    *total += p * b;
+                }
+            }
+            marginals.insert(var.clone(), marginal);
+        }
+
+        let mut means = Values::new();
+        let mut covariances = HashMap::new();
+        for (var, &offset) in offsets.iter() {
+            let dimension = self.graph.dimension(var);
+            means.insert(var.clone(), mean[offset..offset + dimension].to_vec());
+            covariances.insert(var.clone(), covariance.block(offset, offset, dimension, dimension));
+        }
+
+        Ok(HybridResult {
+            log_partition,
+            marginals,
+            means,
+            covariances,
+            num_components: hypotheses.len(),
+        })
+    }
+
+    /// Multiply every factor that becomes ready at step `step` into each hypothesis.
+    fn absorb_ready(&self, hypotheses: &mut [Hypothesis], ready: &[Option<usize>], step: Option<usize>,
+                    offsets: &HashMap<String, usize>, covered: &mut [bool]) {
+        let discrete: Vec<String> = self.graph.get_variable_names().into_iter()
+            .filter(|v| self.graph.dimension(v) == 0)
+            .collect();
+
+        for (factor, &r) in self.graph.get_factors().iter().zip(ready.iter()) {
+            if r != step {
+                continue;
+            }
+
+            let mut indices = vec!();
+            let mut config = vec!();
+            let mut cards = vec!();
+            for var in factor.get_variables() {
+                match offsets.get(var) {
+                    Some(&offset) => indices.extend(offset..offset + self.graph.dimension(var)),
+                    None => {
+                        config.push(discrete.iter().position(|v| v == var).unwrap());
+                        cards.push(self.graph.domain_size(var));
+                    }
+                }
+            }
+            for &i in indices.iter() {
+                covered[i] = true;
+            }
+
+            for hypothesis in hypotheses.iter_mut() {
+                let values: Vec<u32> = config.iter()
+                    .map(|&j| hypothesis.values[j].expect("Discrete variable was collapsed before its last factor"))
+                    .collect();
+                match *factor.get_potential() {
+                    Potential::Discrete(_) | Potential::Table(_) | Potential::LogLinear(_) => {
+                        let log_potential = factor.log_potential(&values);
+                        hypothesis.form.log_scale += log_potential;
+                    },
+                    Potential::Gaussian(ref potential) => {
+                        hypothesis.absorb(&CanonicalForm::new(0.0, potential.clone()), &indices);
+                    },
+                    Potential::Hybrid(ref potential) => {
+                        let index = values.iter().zip(cards.iter()).fold(0, |index, (&v, card)| index * card + v as usize);
+                        hypothesis.absorb(&potential.components[index], &indices);
+                    },
+                    _ => panic!("Factor {} cannot be used in hybrid inference", factor.get_name())
+                }
+            }
+        }
+    }
+
+    /// Collapse the hypotheses that agree on the discrete variables at `frontier` into one each.
+    fn merge(&self, hypotheses: Vec<Hypothesis>, frontier: &[usize], covered: &[bool]) -> Vec<Hypothesis> {
+        let covered: Vec<usize> = (0..covered.len()).filter(|&i| covered[i]).collect();
+        let mut groups: Vec<(Vec<Option<u32>>, Vec<Hypothesis>)> = vec!();
+        for hypothesis in hypotheses {
+            let key: Vec<Option<u32>> = frontier.iter().map(|&j| hypothesis.values[j]).collect();
+            match groups.iter().position(|g| g.0 == key) {
+                Some(g) => groups[g].1.push(hypothesis),
+                None => groups.push((key, vec!(hypothesis))),
+            }
+        }
+
+        let mut merged = vec!();
+        for (_, group) in groups {
+            let moments: Option<Vec<(f64, Vec<f64>, Matrix)>> = group.iter().map(|h| h.moments(&covered)).collect();
+            let moments = match moments {
+                Some(ref m) if group.len() > 1 => m.clone(),
+                _ => {
+                    merged.extend(group);
+                    continue;
+                }
+            };
+
+            let (log_mass, mean, covariance) = collapse_mixture(&moments);
+            let mut hypothesis = group[0].clone();
+            for (j, value) in hypothesis.values.iter_mut().enumerate() {
+                if group.iter().any(|h| h.values[j] != *value) {
+                    *value = None;
+                }
+            }
+            for (j, belief) in hypothesis.beliefs.iter_mut().enumerate() {
+                for (v, b) in belief.iter_mut().enumerate() {
+                    *b = group.iter().zip(moments.iter())
+                        .map(|(h, m)| (m.0 - log_mass).exp() * h.beliefs[j][v])
+                        .sum();
+                }
+            }
+
+            let size = hypothesis.form.potential.dimension();
+            hypothesis.form = CanonicalForm::new(0.0, GaussianPotential::new(Matrix::zeros(size, size), vec![0.0; size]));
+            hypothesis.absorb(&CanonicalForm::from_moments(log_mass, &mean, &covariance), &covered);
+            merged.push(hypothesis);
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::names;
+
+    fn prior(vals: &[u32]) -> i32 {
+        if vals[0] == 0 { 3 } else { 7 }
+    }
+
+    fn sticky(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 9 } else { 1 }
+    }
+
+    fn normal_log_density(x: f64, mean: f64, variance: f64) -> f64 {
+        -0.5 * ((2.0 * PI * variance).ln() + (x - mean) * (x - mean) / variance)
+    }
+
+    #[test]
+    fn exact_inference_matches_closed_form() {
+        let mut graph = FactorGraph::new();
+        graph.add_discrete_var("s", vec!["calm", "stormy"]);
+        graph.add_gaussian_var("x", 1);
+        graph.add_factor::<i32>(names(&["s"]), prior);
+
+        let (means, variances) = ([0.0, 3.0], [1.0, 4.0]);
+        let components = (0..2).map(|s| CanonicalForm::density(&[means[s]], &Matrix::from_diagonal(&[variances[s]])))
+            .collect();
+        graph.add_hybrid_factor(names(&["s", "x"]), HybridPotential::new(components));
+
+        // A noisy observation of x = 1.0 with variance 0.5.
+        graph.add_gaussian_factor(names(&["x"]),
+                                  GaussianPotential::from_moments(&[1.0], &Matrix::from_diagonal(&[0.5])));
+
+        let result = HybridInference::new(&graph).run().unwrap();
+
+        let log_weights: Vec<f64> = (0..2)
+            .map(|s| [3f64, 7.0][s].ln() + normal_log_density(1.0, means[s], variances[s] + 0.5))
+            .collect();
+        let log_total = log_sum_exp(&log_weights);
+        let posterior: Vec<f64> = log_weights.iter().map(|w| (w - log_total).exp()).collect();
+        for (p, e) in result.marginals["s"].iter().zip(posterior.iter()) {
+            assert!((p - e).abs() < 1e-12);
+        }
+
+        // Each configuration combines its prior with the observation.
+        let expected_mean: f64 = (0..2)
+            .map(|s| posterior[s] * (means[s] / variances[s] + 2.0) / (1.0 / variances[s] + 2.0))
+            .sum();
+        assert!((result.means["x"][0] - expected_mean).abs() < 1e-12);
+        assert_eq!(result.num_components, 2);
+    }
+
+    #[test]
+    fn collapse_approximates_switching_models() {
+        let mut graph = FactorGraph::new();
+        let observations = [0.1, 0.3, 2.9, 3.1];
+        for (t, y) in observations.iter().enumerate() {
+            let (s, x) = (format!("s{}", t), format!("x{}", t));
+            graph.add_discrete_var(&s, vec![0, 1]);
+            graph.add_gaussian_var(&x, 1);
+            if t == 0 {
+                graph.add_factor::<i32>(vec!(s.clone()), prior);
+                graph.add_gaussian_factor(vec!(x.clone()),
+                                          GaussianPotential::from_moments(&[0.0], &Matrix::from_diagonal(&[1.0])));
+            } else {
+                let (previous_s, previous_x) = (format!("s{}", t - 1), format!("x{}", t - 1));
+                graph.add_factor::<i32>(vec!(previous_s, s.clone()), sticky);
+
+                // Regime 0 stays put, regime 1 drifts towards 3.
+                let components = [(1.0, 0.0), (0.0, 3.0)].iter()
+                    .map(|&(a, b)| CanonicalForm::linear_gaussian(&Matrix::from_diagonal(&[a]), &[b],
+                                                                  &Matrix::from_diagonal(&[0.2])))
+                    .collect();
+                graph.add_hybrid_factor(vec!(s.clone(), previous_x, x.clone()), HybridPotential::new(components));
+            }
+            graph.add_gaussian_factor(vec!(x),
+                                      GaussianPotential::from_moments(&[*y], &Matrix::from_diagonal(&[0.1])));
+        }
+
+        let exact = HybridInference::new(&graph).run().unwrap();
+        let collapsed = HybridInference::new(&graph).with_collapse().run().unwrap();
+
+        assert_eq!(exact.num_components, 16);
+        assert_eq!(collapsed.num_components, 1);
+        for t in 0..observations.len() {
+            let s = format!("s{}", t);
+            assert!((exact.marginals[&s][1] - collapsed.marginals[&s][1]).abs() < 0.05);
+        }
+        assert!(exact.marginals["s3"][1] > 0.9);
+        assert!((exact.means["x3"][0] - collapsed.means["x3"][0]).abs() < 0.05);
+    }
+}
@@ -0,0 +1,324 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with incremental smoothing of nonlinear factor graphs that grow over time
+
+use std::collections::HashSet;
+
+use *;
+use elimination::min_fill_order;
+use nonlinear::nonlinear_potential;
+
+/// Struct describing the work done by one `IncrementalSmoother::update`.
+#[derive(Clone, Debug)]
+pub struct IncrementalUpdate {
+    /// Variables whose linearization point moved, in the order of `FactorGraph::get_variable_names`.
+    pub relinearized: Vec<String>,
+
+    /// Variables whose conditionals were recomputed, in their new elimination order.
+    pub eliminated: Vec<String>,
+}
+
+/// Struct keeping the solution of a growing nonlinear factor graph up to date, in the style of iSAM2.
+///
+/// The smoother keeps a linearization point, the Gaussian Bayes net of the linearized problem and, for every
+/// eliminated variable, the factor its elimination passed on to its separator. Since a `FactorGraph` only
+/// ever grows, each update picks up the factors added since the last one. Only the variables those factors
+/// touch, those whose update exceeds the relinearization threshold, and their ancestors in the elimination
+/// tree are eliminated again; the rest of the Bayes net is kept and enters through the cached factors.
+#[derive(Debug)]
+pub struct IncrementalSmoother {
+    linearization_point: Values,
+    delta: Values,
+    order: Vec<String>,
+    conditionals: Vec<GaussianConditional>,
+    separators: HashMap<String, GaussianFactor>,
+    linear: Vec<GaussianFactor>,
+    relinearize_threshold: f64,
+}
+
+impl Default for IncrementalSmoother {
+    fn default() -> IncrementalSmoother {
+        IncrementalSmoother::new()
+    }
+}
+
+impl IncrementalSmoother {
+    /// Create a new IncrementalSmoother that has seen no factors, relinearizing variables whose update
+    /// exceeds 0.1 in any component.
+    pub fn new() -> IncrementalSmoother {
+        IncrementalSmoother {
+            linearization_point: Values::new(),
+            delta: Values::new(),
+            order: vec!(),
+            conditionals: vec!(),
+            separators: HashMap::new(),
+            linear: vec!(),
+            relinearize_threshold: 0.1,
+        }
+    }
+
+    /// Relinearize a variable once its update exceeds `threshold` in any component.
+    pub fn with_relinearize_threshold(mut self, threshold: f64) -> IncrementalSmoother {
+        if threshold < 0.0 {
+            panic!("Relinearization threshold must be non-negative, got {}", threshold);
+        }
+
+        self.relinearize_threshold = threshold;
+        self
+    }
+
+    /// Get the current estimate of every variable, the linearization point plus the solved update.
+    pub fn estimate(&self) -> Values {
+        let mut estimate = self.linearization_point.clone();
+        for (var, step) in self.delta.iter() {
+            for (x, dx) in estimate.get_mut(var).unwrap().iter_mut().zip(step.iter()) {
+                *x += dx;
+            }
+        }
+        estimate
+    }
+
+    /// Get the Gaussian Bayes net of the problem linearized at the current linearization point.
+    pub fn get_bayes_net(&self) -> GaussianBayesNet {
+        GaussianBayesNet::from_conditionals(self.conditionals.clone())
+    }
+
+    /// Incorporate the factors added to the graph since the last update, with initial values for the
+    /// variables that first appear in them, and re-solve.
+    ///
+    /// The graph must be the one passed to every earlier update, grown only by adding variables and factors.
+    /// On error the smoother is left as it was before the update.
+    pub fn update(&mut self, graph: &FactorGraph, new_values: Values) -> Result<IncrementalUpdate, IndeterminateSystem> {
+        let factors = graph.get_factors();
+        if factors.len() < self.linear.len() {
+            panic!("The graph has fewer factors than the smoother has already seen");
+        }
+
+        let mut linearization_point = self.linearization_point.clone();
+        for (var, value) in new_values {
+            if value.len() != graph.dimension(&var) {
+                panic!("Variable {} has {} components, got a value with {}", var, graph.dimension(&var), value.len());
+            }
+            linearization_point.entry(var).or_insert(value);
+        }
+
+        // Move the linearization point of variables whose update has grown too large.
+        let relinearized: Vec<String> = graph.get_variable_names().into_iter()
+            .filter(|var| self.delta.get(var)
+                .is_some_and(|step| step.iter().any(|dx| dx.abs() > self.relinearize_threshold)))
+            .collect();
+        for var in relinearized.iter() {
+            for (x, dx) in linearization_point.get_mut(var).unwrap().iter_mut().zip(self.delta[var].iter()) {
+                *x += dx;
+            }
+        }
+
+        let mut linear = self.linear.clone();
+        let mut touched: HashSet<String> = HashSet::new();
+        let mut refreshed: Vec<usize> = vec!();
+        for (index, factor) in factors.iter().enumerate() {
+            let is_new = index >= self.linear.len();
+            if is_new || factor.get_variables().iter().any(|v| relinearized.contains(v)) {
+                let values: Vec<&[f64]> = factor.get_variables().iter()
+                    .map(|var| match linearization_point.get(var) {
+                        Some(value) => value.as_slice(),
+                        None => panic!("No value for the variable {}", var)
+                    })
+                    .collect();
+                let dimensions = factor.get_variables().iter().map(|var| graph.dimension(var)).collect();
+                let factor_linear = GaussianFactor::new(factor.get_variables().clone(), dimensions,
+                                                        nonlinear_potential(factor).linearize(&values));
+                if is_new {
+                    linear.push(factor_linear);
+                } else {
+                    linear[index] = factor_linear;
+                }
+
+                touched.extend(factor.get_variables().iter().cloned());
+                refreshed.push(index);
+            }
+        }
+
+        // Every ancestor of a touched variable in the elimination tree is affected as well.
+        let positions: HashMap<&String, usize> = self.order.iter().enumerate().map(|(i, v)| (v, i)).collect();
+        let mut affected = touched;
+        for (i, conditional) in self.conditionals.iter().enumerate() {
+            if affected.contains(&self.order[i]) {
+                if let Some(parent) = conditional.get_parents().iter().min_by_key(|p| positions[p]) {
+                    affected.insert(parent.clone());
+                }
+            }
+        }
+
+        // Re-eliminate the affected variables from the factors whose first variable in the old order is
+        // affected, together with the cached factors of the subtrees that hang off the affected part.
+        let first_affected = |factor: &GaussianFactor| -> bool {
+            factor.get_variables().iter()
+                .min_by_key(|v| positions.get(v).cloned().unwrap_or(usize::MAX))
+                .is_some_and(|v| affected.contains(v))
+        };
+        let mut remaining: Vec<GaussianFactor> = linear.iter().enumerate()
+            .filter(|&(index, f)| refreshed.contains(&index) || first_affected(f))
+            .map(|(_, f)| f.clone())
+            .collect();
+        for (var, separator) in self.separators.iter() {
+            if !affected.contains(var) && first_affected(separator) {
+                remaining.push(separator.clone());
+            }
+        }
+
+        let affected_order: Vec<String> = graph.get_variable_names().into_iter()
+            .filter(|var| affected.contains(var))
+            .collect();
+        let scopes: Vec<Vec<String>> = remaining.iter().map(|f| f.get_variables().clone()).collect();
+        let eliminated = min_fill_order(&affected_order, &scopes);
+
+        let mut order: Vec<String> = self.order.iter().filter(|v| !affected.contains(*v)).cloned().collect();
+        let mut conditionals: Vec<GaussianConditional> = self.conditionals.iter()
+            .filter(|c| !affected.contains(c.get_variable()))
+            .cloned()
+            .collect();
+        let mut separators = self.separators.clone();
+        for var in eliminated.iter() {
+            let (involved, rest): (Vec<GaussianFactor>, Vec<GaussianFactor>) =
+                remaining.into_iter().partition(|f| f.get_variables().contains(var));
+            remaining = rest;
+
+            let (conditional, separator) = GaussianConditional::eliminate(&involved, var)?;
+            match separator {
+                Some(separator) => {
+                    separators.insert(var.clone(), separator.clone());
+                    remaining.push(separator);
+                },
+                None => {
+                    separators.remove(var);
+                }
+            }
+            order.push(var.clone());
+            conditionals.push(conditional);
+        }
+
+        let delta = GaussianBayesNet::from_conditionals(conditionals.clone()).solve();
+
+        self.linearization_point = linearization_point;
+        self.delta = delta;
+        self.order = order;
+        self.conditionals = conditionals;
+        self.separators = separators;
+        self.linear = linear;
+        Ok(IncrementalUpdate {
+            relinearized,
+            eliminated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Residual of a measured difference between two scalar positions.
+    #[derive(Debug)]
+    struct Odometry {
+        measured: f64,
+    }
+
+    impl ResidualFunction for Odometry {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            vec!(values[1][0] - values[0][0] - self.measured)
+        }
+    }
+
+    /// Residual of a measured value of a scalar.
+    #[derive(Debug)]
+    struct Prior {
+        measured: f64,
+    }
+
+    impl ResidualFunction for Prior {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            vec!(values[0][0] - self.measured)
+        }
+    }
+
+    /// Residual of a measured square of a scalar.
+    #[derive(Debug)]
+    struct Square {
+        measured: f64,
+    }
+
+    impl ResidualFunction for Square {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            vec!(values[0][0] * values[0][0] - self.measured)
+        }
+    }
+
+    fn single(name: &str, value: f64) -> Values {
+        let mut values = Values::new();
+        values.insert(String::from(name), vec!(value));
+        values
+    }
+
+    #[test]
+    fn incremental_updates_match_batch_solutions() {
+        let mut graph = FactorGraph::new();
+        let mut smoother = IncrementalSmoother::new();
+        graph.add_gaussian_var("x0", 1);
+        graph.add_nonlinear_factor(vec!(String::from("x0")),
+                                   NonlinearPotential::new(Square { measured: 0.0 }).with_sigmas(vec!(0.1)));
+        smoother.update(&graph, single("x0", 0.0)).unwrap_err();
+
+        // The failed update was discarded, so the value of x0 is given again with the prior that anchors it.
+        graph.add_nonlinear_factor(vec!(String::from("x0")), NonlinearPotential::new(Prior { measured: 0.0 }));
+        smoother.update(&graph, single("x0", 0.0)).unwrap();
+
+        for i in 1..6 {
+            let (previous, name) = (format!("x{}", i - 1), format!("x{}", i));
+            graph.add_gaussian_var(&name, 1);
+            graph.add_nonlinear_factor(vec!(previous, name.clone()),
+                                       NonlinearPotential::new(Odometry { measured: 1.0 }).with_sigmas(vec!(0.1)));
+            let update = smoother.update(&graph, single(&name, i as f64)).unwrap();
+
+            // Extending the chain only re-eliminates the previous pose and the new one.
+            assert_eq!(update.eliminated.len(), 2);
+        }
+
+        // Closing the loop re-eliminates the path between the two ends.
+        graph.add_nonlinear_factor(vec!(String::from("x0"), String::from("x5")),
+                                   NonlinearPotential::new(Odometry { measured: 4.5 }).with_sigmas(vec!(0.1)));
+        smoother.update(&graph, Values::new()).unwrap();
+
+        let initial = smoother.estimate();
+        let batch = NonlinearOptimizer::new(&graph, initial.clone())
+            .with_method(OptimizationMethod::GaussNewton)
+            .optimize()
+            .unwrap();
+        for (name, value) in batch.values.iter() {
+            assert!((initial[name][0] - value[0]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn repeated_updates_relinearize_towards_the_optimum() {
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("x", 1);
+        graph.add_nonlinear_factor(vec!(String::from("x")), NonlinearPotential::new(Square { measured: 4.0 }));
+
+        let mut smoother = IncrementalSmoother::new().with_relinearize_threshold(1e-9);
+        smoother.update(&graph, single("x", 3.0)).unwrap();
+        for _ in 0..10 {
+            let update = smoother.update(&graph, Values::new()).unwrap();
+            if update.relinearized.is_empty() {
+                break;
+            }
+            assert_eq!(update.eliminated, vec!(String::from("x")));
+        }
+
+        assert!((smoother.estimate()["x"][0] - 2.0).abs() < 1e-9);
+    }
+}
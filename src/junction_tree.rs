@@ -0,0 +1,253 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with exact inference on a calibrated junction tree
+
+use *;
+use elimination::{elimination_cliques, evidence_tables, min_fill_order};
+
+/// Struct representing a calibrated junction tree over the unobserved variables of a factor graph.
+#[derive(Debug)]
+pub struct JunctionTree {
+    cliques: Vec<Vec<String>>,
+    neighbours: Vec<Vec<usize>>,
+    beliefs: Vec<Table>,
+    log_partition: f64,
+}
+
+impl JunctionTree {
+    /// Find the maximal cliques that a junction tree for the graph would have, without calibrating it.
+    pub fn cliques_for(graph: &FactorGraph, evidence: &Assignment) -> Vec<Vec<String>> {
+        let free: Vec<String> = graph.get_variable_names().into_iter()
+            .filter(|var| !evidence.contains_key(var))
+            .collect();
+        let scopes: Vec<Vec<String>> = graph.get_factors().iter()
+            .map(|f| f.get_variables().iter().filter(|v| free.contains(v)).cloned().collect())
+            .collect();
+
+        let order = min_fill_order(&free, &scopes);
+        let all_cliques = elimination_cliques(&order, &scopes);
+
+        // Elimination cliques contained in another clique are redundant; of equal cliques, only the first is kept.
+        let mut cliques: Vec<Vec<String>> = vec!();
+        for (i, clique) in all_cliques.iter().enumerate() {
+            let redundant = all_cliques.iter().enumerate().any(|(j, other)| {
+                j != i && clique.iter().all(|v| other.contains(v)) && (other.len() > clique.len() || j < i)
+            });
+            if !redundant {
+                cliques.push(clique.clone());
+            }
+        }
+        cliques
+    }
+
+    /// Build the junction tree for the graph with the evidence applied and calibrate it by message passing.
+    pub fn new(graph: &FactorGraph, evidence: &Assignment) -> JunctionTree {
+        let cliques = JunctionTree::cliques_for(graph, evidence);
+
+        // Connect the cliques with a maximum spanning tree on separator sizes, which has the running
+        // intersection property for elimination cliques.
+        let mut edges = vec!();
+        for i in 0..cliques.len() {
+            for j in i + 1..cliques.len() {
+                let separator = cliques[i].iter().filter(|v| cliques[j].contains(v)).count();
+                edges.push((separator, i, j));
+            }
+        }
+        edges.sort_by_key(|&(separator, i, j)| (std::cmp::Reverse(separator), i, j));
+
+        let mut component: Vec<usize> = (0..cliques.len()).collect();
+        let mut neighbours = vec![vec!(); cliques.len()];
+        for (_, i, j) in edges {
+            let (ci, cj) = (component[i], component[j]);
+            if ci != cj {
+                for c in component.iter_mut() {
+                    if *c == cj {
+                        *c = ci;
+                    }
+                }
+                neighbours[i].push(j);
+                neighbours[j].push(i);
+            }
+        }
+
+        let mut potentials: Vec<Table> = cliques.iter()
+            .map(|clique| {
+                let cardinalities = clique.iter().map(|var| graph.domain_size(var)).collect();
+                Table::uniform(clique.clone(), cardinalities)
+            })
+            .collect();
+
+        let mut log_constant = 0.0;
+        for table in evidence_tables(graph, evidence) {
+            if table.get_variables().is_empty() {
+                log_constant += table.get_log_values()[0];
+                continue;
+            }
+
+            let home = cliques.iter()
+                .position(|clique| table.get_variables().iter().all(|v| clique.contains(v)))
+                .unwrap();
+            potentials[home] = potentials[home].product(&table);
+        }
+
+        let mut tree = JunctionTree {
+            cliques,
+            neighbours,
+            beliefs: potentials,
+            log_partition: log_constant,
+        };
+        tree.calibrate();
+        tree
+    }
+
+    /// Run the collect and distribute passes of Shafer-Shenoy message passing from every component's root.
+    fn calibrate(&mut self) {
+        let n = self.cliques.len();
+        let mut visited = vec![false; n];
+        let mut schedule = vec!();
+        let mut roots = vec!();
+
+        for root in 0..n {
+            if visited[root] {
+                continue;
+            }
+            roots.push(root);
+            visited[root] = true;
+
+            // Breadth-first order, so every clique appears after its parent.
+            let start = schedule.len();
+            schedule.push((root, None));
+            let mut k = start;
+            while k < schedule.len() {
+                let (node, _) = schedule[k];
+                for &child in self.neighbours[node].iter() {
+                    if !visited[child] {
+                        visited[child] = true;
+                        schedule.push((child, Some(node)));
+                    }
+                }
+                k += 1;
+            }
+        }
+
+        // messages[i][j] holds the message from clique i to clique j once it has been sent.
+        let mut messages: Vec<Vec<Option<Table>>> = vec![vec![None; n]; n];
+        for &(node, parent) in schedule.iter().rev() {
+            if let Some(parent) = parent {
+                messages[node][parent] = Some(self.message(node, parent, &messages));
+            }
+        }
+        for &(node, parent) in schedule.iter() {
+            if let Some(parent) = parent {
+                messages[parent][node] = Some(self.message(parent, node, &messages));
+            }
+        }
+
+        for node in 0..n {
+            for incoming in messages.iter() {
+                if let Some(ref m) = incoming[node] {
+                    self.beliefs[node] = self.beliefs[node].product(m);
+                }
+            }
+        }
+
+        for root in roots {
+            self.log_partition += self.beliefs[root].log_partition();
+        }
+    }
+
+    /// Compute the message from one clique to a neighbour given the messages sent so far.
+    fn message(&self, from: usize, to: usize, messages: &[Vec<Option<Table>>]) -> Table {
+        let mut table = self.beliefs[from].clone();
+        for (other, outgoing) in messages.iter().enumerate() {
+            if other != to {
+                if let Some(ref m) = outgoing[from] {
+                    table = table.product(m);
+                }
+            }
+        }
+
+        for var in self.cliques[from].iter().filter(|v| !self.cliques[to].contains(v)) {
+            table = table.sum_out(var);
+        }
+        table
+    }
+
+    /// Function to get the cliques of the tree.
+    pub fn get_cliques(&self) -> &Vec<Vec<String>> {
+        &self.cliques
+    }
+
+    /// Function to get the calibrated, unnormalized belief of each clique.
+    pub fn get_beliefs(&self) -> &Vec<Table> {
+        &self.beliefs
+    }
+
+    /// Get the log partition function, summing only over assignments consistent with the evidence.
+    pub fn log_partition(&self) -> f64 {
+        self.log_partition
+    }
+
+    /// Get the normalized joint distribution over `variables` if a single clique contains them all.
+    pub fn marginal(&self, variables: &[String]) -> Option<Table> {
+        let home = self.cliques.iter().position(|clique| variables.iter().all(|v| clique.contains(v)))?;
+
+        let mut table = self.beliefs[home].clone();
+        for var in self.cliques[home].iter().filter(|v| !variables.contains(v)) {
+            table = table.sum_out(var);
+        }
+        Some(table.reordered(variables).normalized())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agree(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 2 } else { 1 }
+    }
+
+    #[test]
+    fn log_partition_matches_enumeration() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d", "e"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+        for &(x, y) in [("a", "b"), ("b", "c"), ("c", "a"), ("c", "d")].iter() {
+            graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), agree);
+        }
+
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("d"), 1);
+        let tree = JunctionTree::new(&graph, &evidence);
+        let exact = Enumeration::new(&graph).with_evidence(evidence).run().unwrap();
+
+        assert!((tree.log_partition() - exact.log_partition).abs() < 1e-12);
+        let e = tree.marginal(&[String::from("e")]).unwrap();
+        assert_eq!(e.probabilities(), vec!(0.5, 0.5));
+    }
+
+    #[test]
+    fn cliques_are_maximal() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+        for &(x, y) in [("a", "b"), ("b", "c"), ("c", "d")].iter() {
+            graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), agree);
+        }
+
+        let cliques = JunctionTree::cliques_for(&graph, &Assignment::new());
+        assert_eq!(cliques.len(), 3);
+        for (i, clique) in cliques.iter().enumerate() {
+            assert_eq!(clique.len(), 2);
+            assert!(cliques.iter().enumerate().all(|(j, other)| i == j || !clique.iter().all(|v| other.contains(v))));
+        }
+    }
+}
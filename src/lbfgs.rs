@@ -0,0 +1,295 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with limited-memory quasi-Newton minimization, with orthant-wise steps for L1 regularization
+
+use std::collections::VecDeque;
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+fn norm(a: &[f64]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+/// Struct holding the result of a minimization.
+#[derive(Clone, Debug)]
+pub struct LbfgsResult {
+    /// Minimizing parameters.
+    pub parameters: Vec<f64>,
+
+    /// Objective, including the L1 penalty, at the start and after every iteration.
+    pub objectives: Vec<f64>,
+
+    /// Norm of the gradient, or of the pseudo-gradient with an L1 penalty, at the start and after every iteration.
+    pub gradient_norms: Vec<f64>,
+
+    /// Number of iterations that were run.
+    pub iterations: usize,
+
+    /// Whether the gradient norm fell below the tolerance before the iteration limit.
+    pub converged: bool,
+}
+
+/// Struct minimizing a smooth function, plus an optional L1 penalty, by L-BFGS.
+///
+/// The inverse Hessian is approximated from the last few steps and gradient changes, and steps are chosen by
+/// backtracking until the Armijo condition holds. With an L1 penalty this is OWL-QN: the gradient is replaced by
+/// the pseudo-gradient of the penalized objective, and every step stays within the orthant of the current point,
+/// so parameters can land exactly on zero.
+#[derive(Clone, Copy, Debug)]
+pub struct Lbfgs {
+    memory: usize,
+    max_iterations: usize,
+    tolerance: f64,
+    l1: f64,
+    unpenalized: usize,
+}
+
+impl Default for Lbfgs {
+    fn default() -> Lbfgs {
+        Lbfgs::new()
+    }
+}
+
+impl Lbfgs {
+    /// Create a new Lbfgs remembering 10 steps, running at most 100 iterations without an L1 penalty.
+    pub fn new() -> Lbfgs {
+        Lbfgs {
+            memory: 10,
+            max_iterations: 100,
+            tolerance: 1e-6,
+            l1: 0.0,
+            unpenalized: 0,
+        }
+    }
+
+    /// Set the number of past steps the inverse Hessian approximation is built from.
+    pub fn with_memory(mut self, memory: usize) -> Lbfgs {
+        if memory == 0 {
+            panic!("L-BFGS needs to remember at least one step");
+        }
+
+        self.memory = memory;
+        self
+    }
+
+    /// Set the maximum number of iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Lbfgs {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Set the gradient norm, relative to the parameter norm when that is above one, under which to stop.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Lbfgs {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Add the penalty `l1 * sum_i |x_i|` to the objective.
+    pub fn with_l1(mut self, l1: f64) -> Lbfgs {
+        if l1 < 0.0 {
+            panic!("The L1 penalty must be non-negative, got {}", l1);
+        }
+
+        self.l1 = l1;
+        self
+    }
+
+    /// Leave the first `count` components, such as intercepts, out of the L1 penalty.
+    pub fn with_unpenalized(mut self, count: usize) -> Lbfgs {
+        self.unpenalized = count;
+        self
+    }
+
+    /// Get the L1 penalty on the component at `index`.
+    fn l1_at(&self, index: usize) -> f64 {
+        if index < self.unpenalized { 0.0 } else { self.l1 }
+    }
+
+    /// Minimize the function, which gives its value and gradient at a point, starting from `initial`.
+    pub fn minimize<F: FnMut(&[f64]) -> (f64, Vec<f64>)>(&self, initial: &[f64], mut function: F) -> LbfgsResult {
+        let penalty = |x: &[f64]| x.iter().enumerate().map(|(i, v)| self.l1_at(i) * v.abs()).sum::<f64>();
+
+        let mut x = initial.to_vec();
+        let (value, mut gradient) = function(&x);
+        let mut objective = value + penalty(&x);
+        let mut history: VecDeque<(Vec<f64>, Vec<f64>, f64)> = VecDeque::with_capacity(self.memory);
+
+        let mut pseudo = self.pseudo_gradient(&x, &gradient);
+        let mut result = LbfgsResult {
+            parameters: vec!(),
+            objectives: vec!(objective),
+            gradient_norms: vec!(norm(&pseudo)),
+            iterations: 0,
+            converged: false,
+        };
+
+        while result.iterations < self.max_iterations {
+            if norm(&pseudo) <= self.tolerance * norm(&x).max(1.0) {
+                break;
+            }
+            result.iterations += 1;
+
+            let mut direction = two_loop(&history, &pseudo);
+            if self.l1 > 0.0 {
+                for (d, p) in direction.iter_mut().zip(pseudo.iter()).skip(self.unpenalized) {
+                    if *d * p >= 0.0 {
+                        *d = 0.0;
+                    }
+                }
+            }
+            if dot(&direction, &pseudo) >= 0.0 {
+                history.clear();
+                direction = pseudo.iter().map(|p| -p).collect();
+            }
+
+            // Steps may not cross from the orthant of x, or for zero components that of the descent direction.
+            let orthant: Vec<f64> = x.iter().zip(pseudo.iter())
+                .map(|(&xi, &p)| if xi != 0.0 { xi.signum() } else if p != 0.0 { -p.signum() } else { 0.0 })
+                .collect();
+
+            let mut step = if history.is_empty() { (1.0 / norm(&pseudo)).min(1.0) } else { 1.0 };
+            let mut accepted = None;
+            for _ in 0..60 {
+                let mut candidate: Vec<f64> = x.iter().zip(direction.iter()).map(|(xi, d)| xi + step * d).collect();
+                if self.l1 > 0.0 {
+                    for (c, o) in candidate.iter_mut().zip(orthant.iter()).skip(self.unpenalized) {
+                        if *c * o <= 0.0 {
+                            *c = 0.0;
+                        }
+                    }
+                }
+
+                let (value, candidate_gradient) = function(&candidate);
+                let candidate_objective = value + penalty(&candidate);
+                let moved: Vec<f64> = candidate.iter().zip(x.iter()).map(|(c, xi)| c - xi).collect();
+                if candidate_objective <= objective + 1e-4 * dot(&pseudo, &moved) {
+                    accepted = Some((candidate, candidate_gradient, candidate_objective));
+                    break;
+                }
+                step *= 0.5;
+            }
+
+            let (candidate, candidate_gradient, candidate_objective) = match accepted {
+                Some(x) => x,
+                None => break
+            };
+
+            let s: Vec<f64> = candidate.iter().zip(x.iter()).map(|(a, b)| a - b).collect();
+            let y: Vec<f64> = candidate_gradient.iter().zip(gradient.iter()).map(|(a, b)| a - b).collect();
+            let curvature = dot(&s, &y);
+            if curvature > 1e-10 {
+                if history.len() == self.memory {
+                    history.pop_front();
+                }
+                history.push_back((s, y, 1.0 / curvature));
+            }
+
+            let stalled = (objective - candidate_objective).abs() <= f64::EPSILON * objective.abs().max(1.0);
+            x = candidate;
+            gradient = candidate_gradient;
+            objective = candidate_objective;
+            pseudo = self.pseudo_gradient(&x, &gradient);
+            result.objectives.push(objective);
+            result.gradient_norms.push(norm(&pseudo));
+
+            if stalled {
+                break;
+            }
+        }
+
+        result.converged = norm(&pseudo) <= self.tolerance * norm(&x).max(1.0);
+        result.parameters = x;
+        result
+    }
+
+    /// Get the gradient of the penalized objective, taking at zero components the one-sided derivative that
+    /// descends, or zero if neither does.
+    fn pseudo_gradient(&self, x: &[f64], gradient: &[f64]) -> Vec<f64> {
+        if self.l1 == 0.0 {
+            return gradient.to_vec();
+        }
+
+        x.iter().zip(gradient.iter()).enumerate()
+            .map(|(i, (&xi, &g))| {
+                let l1 = self.l1_at(i);
+                if xi > 0.0 {
+                    g + l1
+                } else if xi < 0.0 {
+                    g - l1
+                } else if g + l1 < 0.0 {
+                    g + l1
+                } else if g - l1 > 0.0 {
+                    g - l1
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+}
+
+/// Multiply the negated gradient by the inverse Hessian approximation from the remembered steps.
+fn two_loop(history: &VecDeque<(Vec<f64>, Vec<f64>, f64)>, gradient: &[f64]) -> Vec<f64> {
+    let mut q: Vec<f64> = gradient.iter().map(|g| -g).collect();
+    let mut alphas = Vec::with_capacity(history.len());
+    for &(ref s, ref y, rho) in history.iter().rev() {
+        let alpha = rho * dot(s, &q);
+        for (qi, yi) in q.iter_mut().zip(y.iter()) {
+            *qi -= alpha * yi;
+        }
+        alphas.push(alpha);
+    }
+
+    if let Some((s, y, _)) = history.back() {
+        let gamma = dot(s, y) / dot(y, y);
+        for qi in q.iter_mut() {
+            *qi *= gamma;
+        }
+    }
+
+    for (&(ref s, ref y, rho), alpha) in history.iter().zip(alphas.iter().rev()) {
+        let beta = rho * dot(y, &q);
+        for (qi, si) in q.iter_mut().zip(s.iter()) {
+            *qi += (alpha - beta) * si;
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rosenbrock(x: &[f64]) -> (f64, Vec<f64>) {
+        let (a, b) = (1.0 - x[0], x[1] - x[0] * x[0]);
+        (a * a + 100.0 * b * b, vec!(-2.0 * a - 400.0 * x[0] * b, 200.0 * b))
+    }
+
+    #[test]
+    fn minimizes_rosenbrock() {
+        let result = Lbfgs::new().with_max_iterations(200).minimize(&[-1.2, 1.0], rosenbrock);
+        assert!(result.converged);
+        assert!((result.parameters[0] - 1.0).abs() < 1e-5);
+        assert!((result.parameters[1] - 1.0).abs() < 1e-5);
+        assert!(result.objectives.windows(2).all(|pair| pair[1] <= pair[0]));
+    }
+
+    #[test]
+    fn l1_penalty_gives_exact_zeros() {
+        // Minimizing (x - 3)^2 / 2 + (y - 0.5)^2 / 2 + |x| + |y| gives x = 2 and y = 0.
+        let quadratic = |x: &[f64]| {
+            (0.5 * (x[0] - 3.0).powi(2) + 0.5 * (x[1] - 0.5).powi(2), vec!(x[0] - 3.0, x[1] - 0.5))
+        };
+        let result = Lbfgs::new().with_l1(1.0).minimize(&[0.0, 0.0], quadratic);
+        assert!(result.converged);
+        assert!((result.parameters[0] - 2.0).abs() < 1e-6);
+        assert_eq!(result.parameters[1], 0.0);
+    }
+}
@@ -12,20 +12,99 @@ unused_import_braces, unused_qualifications)]
 extern crate dot;
 
 mod render;
+mod rng;
+mod model;
 pub mod variable;
 pub mod factor;
 pub mod tree;
+pub mod table;
+pub mod elimination;
+pub mod minibucket;
+pub mod mean_field;
+pub mod sampling;
+pub mod enumeration;
+pub mod junction_tree;
+pub mod belief_propagation;
+pub mod bethe;
+pub mod linalg;
+pub mod gaussian;
+pub mod gaussian_bp;
+pub mod gaussian_elimination;
+pub mod nonlinear;
+pub mod optimizer;
+pub mod covariance;
+pub mod incremental;
+pub mod hybrid;
+pub mod particle_bp;
+pub mod expectation_propagation;
+pub mod discretization;
+pub mod log_linear;
+pub mod lbfgs;
+pub mod mle;
+pub mod pseudo_likelihood;
+pub mod expectation_maximization;
+pub mod crf;
+pub mod max_margin;
+pub mod contrastive_divergence;
+pub mod chow_liu;
+pub mod neighbourhood_selection;
+pub mod dirichlet;
+#[cfg(test)]
+mod test_support;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::io::Write;
+use std::rc::Rc;
 
-pub use variable::{Variable, DiscreteVariable};
-pub use factor::Factor;
+pub use variable::{Variable, DiscreteVariable, GaussianVariable};
+pub use factor::{Factor, Potential};
 pub use tree::{SpanningTree, TreeNode};
+pub use table::Table;
+pub use minibucket::MiniBucket;
+pub use mean_field::MeanField;
+pub use sampling::{SamplingEstimate, GibbsSampler, ImportanceSampler, AnnealedImportanceSampler};
+pub use enumeration::{Enumeration, ExactResult, EnumerationError, StateSpaceTooLarge};
+pub use elimination::VariableElimination;
+pub use junction_tree::JunctionTree;
+pub use belief_propagation::{BeliefPropagation, BeliefPropagationResult};
+pub use bethe::BetheFreeEnergy;
+pub use linalg::Matrix;
+pub use gaussian::GaussianPotential;
+pub use gaussian_bp::{GaussianBeliefPropagation, GaussianBpResult, WalkSummability};
+pub use gaussian_elimination::{GaussianFactor, GaussianConditional, GaussianBayesNet, IndeterminateSystem};
+pub use nonlinear::{ResidualFunction, NonlinearPotential, RobustKernel};
+pub use optimizer::{NonlinearOptimizer, OptimizationMethod, OptimizationResult};
+pub use covariance::CovarianceRecovery;
+pub use incremental::{IncrementalSmoother, IncrementalUpdate};
+pub use hybrid::{CanonicalForm, HybridPotential, HybridInference, HybridResult};
+pub use particle_bp::{ContinuousPotential, ParticleBeliefPropagation, ParticleBpResult};
+pub use expectation_propagation::{ExpectationPropagation, ExpectationPropagationResult, TooManyQuadraturePoints};
+pub use discretization::{BinningStrategy, BinLabel, Binning, Discretizer};
+pub use log_linear::LogLinearPotential;
+pub use lbfgs::{Lbfgs, LbfgsResult};
+pub use mle::{MaximumLikelihood, InferenceMethod, TrainingResult};
+pub use pseudo_likelihood::PseudoLikelihood;
+pub use expectation_maximization::{ExpectationMaximization, EmResult};
+pub use crf::{ConditionalRandomField, ConditionalLikelihood};
+pub use max_margin::{StructuredPerceptron, PerceptronResult, StructuredSvm, SsvmResult};
+pub use contrastive_divergence::{ContrastiveDivergence, CdResult};
+pub use chow_liu::ChowLiu;
+pub use neighbourhood_selection::{NeighbourhoodSelection, Symmetrization};
+pub use dirichlet::{DirichletPrior, BayesianEstimation, BayesianResult};
 
 type PotentialFunc = fn(&[u32]) -> i32;
 
+/// Values of discrete variables, keyed by variable name.
+pub type Assignment = HashMap<String, u32>;
+
+/// Distribution over the values of each discrete variable, keyed by variable name.
+pub type Marginals = HashMap<String, Vec<f64>>;
+
+/// Values of continuous variables, keyed by variable name.
+pub type Values = HashMap<String, Vec<f64>>;
+
 /// Trait representing a generic item stored in the factor graph.
 pub trait FactorGraphItem : std::fmt::Debug {
     /// Get the name of this item.
@@ -44,11 +123,18 @@ pub trait FactorGraphItem : std::fmt::Debug {
 /// Struct representing the full factor graph.
 #[derive(Debug)]
 pub struct FactorGraph {
-    variables: HashMap<String, Box<Variable>>,
+    variables: HashMap<String, Box<dyn Variable>>,
     factors: Vec<Factor>,
     next_id: u32,
     all_names: Vec<String>,
     is_factor: Vec<bool>,
+    parameters: Rc<RefCell<Vec<f64>>>,
+}
+
+impl Default for FactorGraph {
+    fn default() -> FactorGraph {
+        FactorGraph::new()
+    }
 }
 
 impl FactorGraph {
@@ -60,6 +146,7 @@ impl FactorGraph {
             next_id: 0,
             all_names: vec!(),
             is_factor: vec!(),
+            parameters: Rc::new(RefCell::new(vec!())),
         }
     }
 
@@ -74,25 +161,259 @@ impl FactorGraph {
         self.next_id += 1;
     }
 
+    /// Add a new continuous variable with the specified name and number of components to the factor graph.
+    pub fn add_gaussian_var(&mut self, name: &str, dimension: usize) {
+        let new_var = GaussianVariable::new(self.next_id, name, dimension);
+
+        self.variables.insert(String::from(name),
+                              Box::new(new_var));
+        self.all_names.insert(self.next_id as usize, String::from(name));
+        self.is_factor.insert(self.next_id as usize, false);
+        self.next_id += 1;
+    }
+
     /// Add a new factor with the specified variables to the factor graph.
     pub fn add_factor<T: std::fmt::Debug + 'static>(&mut self, variables: Vec<String>, func: PotentialFunc) {
+        for var in variables.iter() {
+            if self.dimension(var) > 0 {
+                panic!("The variable {} is continuous and cannot be in a discrete factor.", var);
+            }
+        }
+
+        self.push_factor(variables, Potential::Discrete(func));
+    }
+
+    /// Add a new factor over the table's discrete variables, with the table's log-potentials.
+    pub fn add_table_factor(&mut self, table: Table) {
+        for (var, &card) in table.get_variables().iter().zip(table.get_cardinalities().iter()) {
+            if self.dimension(var) > 0 {
+                panic!("The variable {} is continuous and cannot be in a discrete factor.", var);
+            }
+            if self.domain_size(var) != card {
+                panic!("The variable {} has {} values, but the table gives it {}", var, self.domain_size(var), card);
+            }
+        }
+
+        self.push_factor(table.get_variables().clone(), Potential::Table(table));
+    }
+
+    /// Replace the table of the table factor at `index` among the factors of the graph.
+    pub fn set_table(&mut self, index: usize, table: Table) {
+        let factor = match self.factors.get(index) {
+            Some(factor) => factor,
+            None => panic!("The graph has no factor {}", index)
+        };
+        match *factor.get_potential() {
+            Potential::Table(ref old) if old.get_variables() == table.get_variables()
+                && old.get_cardinalities() == table.get_cardinalities() => {},
+            _ => panic!("Factor {} does not hold a table over {:?}", factor.get_name(), table.get_variables())
+        }
+
+        let factor = Factor::with_potential(factor.get_id(), table.get_variables().clone(), Potential::Table(table));
+        for var in factor.get_variables().iter() {
+            self.variables.get_mut(var).unwrap().replace_factor(factor.clone());
+        }
+        self.factors[index] = factor;
+    }
+
+    /// Add a new log-linear factor over the specified discrete variables, reading its weights from the
+    /// graph's parameters.
+    pub fn add_log_linear_factor(&mut self, variables: Vec<String>, potential: LogLinearPotential) {
+        for var in variables.iter() {
+            if self.dimension(var) > 0 {
+                panic!("The variable {} is continuous and cannot be in a discrete factor.", var);
+            }
+        }
+        if let Some(&weight) = potential.get_weights().iter().find(|&&w| w >= self.num_parameters()) {
+            panic!("Weight {} is out of range for the {} parameters of the graph", weight, self.num_parameters());
+        }
+
+        self.push_factor(variables, Potential::LogLinear(potential.with_parameters(self.parameters.clone())));
+    }
+
+    /// Add a new parameter to the graph with the given initial value, returning its index.
+    pub fn add_parameter(&mut self, initial: f64) -> usize {
+        let mut parameters = self.parameters.borrow_mut();
+        parameters.push(initial);
+        parameters.len() - 1
+    }
+
+    /// Get the number of parameters the log-linear factors of the graph are weighted by.
+    pub fn num_parameters(&self) -> usize {
+        self.parameters.borrow().len()
+    }
+
+    /// Get the current value of every parameter of the graph.
+    pub fn get_parameters(&self) -> Vec<f64> {
+        self.parameters.borrow().clone()
+    }
+
+    /// Set every parameter of the graph, which changes every log-linear factor weighted by them.
+    pub fn set_parameters(&mut self, parameters: &[f64]) {
+        if parameters.len() != self.num_parameters() {
+            panic!("The graph has {} parameters, got {}", self.num_parameters(), parameters.len());
+        }
+
+        self.parameters.borrow_mut().copy_from_slice(parameters);
+    }
+
+    /// Add a new Gaussian factor over the stacked components of the specified continuous variables.
+    pub fn add_gaussian_factor(&mut self, variables: Vec<String>, potential: GaussianPotential) {
+        let dimension = self.continuous_dimension(&variables);
+        if dimension != potential.dimension() {
+            panic!("Gaussian factor over {:?} needs {} components, got {}", variables, dimension, potential.dimension());
+        }
+
+        self.push_factor(variables, Potential::Gaussian(potential));
+    }
+
+    /// Add a new factor whose residual is minimized in least squares over the specified continuous variables.
+    pub fn add_nonlinear_factor(&mut self, variables: Vec<String>, potential: NonlinearPotential) {
+        self.continuous_dimension(&variables);
+        self.push_factor(variables, Potential::Nonlinear(potential));
+    }
+
+    /// Add a new factor with an arbitrary log-potential over the specified continuous variables.
+    pub fn add_continuous_factor(&mut self, variables: Vec<String>, potential: ContinuousPotential) {
+        self.continuous_dimension(&variables);
+        self.push_factor(variables, Potential::Continuous(potential));
+    }
+
+    /// Add a new conditional linear Gaussian factor, with a Gaussian over the factor's continuous variables
+    /// for every configuration of its discrete variables.
+    pub fn add_hybrid_factor(&mut self, variables: Vec<String>, potential: HybridPotential) {
+        let mut configurations = 1;
+        let mut dimension = 0;
+        for var in variables.iter() {
+            match self.dimension(var) {
+                0 => configurations *= self.domain_size(var),
+                d => dimension += d
+            }
+        }
+
+        if configurations != potential.get_components().len() || dimension != potential.dimension() {
+            panic!("Hybrid factor over {:?} needs {} components over {} continuous components, got {} over {}",
+                   variables, configurations, dimension, potential.get_components().len(), potential.dimension());
+        }
+
+        self.push_factor(variables, Potential::Hybrid(potential));
+    }
+
+    /// Add a switchable constraint, with a new scalar switch variable that the optimizer can drive to zero
+    /// to reject the factor as an outlier.
+    ///
+    /// The switch is held near one by a prior with standard deviation `switch_sigma`, and should start
+    /// at one in the initial values.
+    pub fn add_switchable_factor(&mut self, variables: Vec<String>, potential: NonlinearPotential,
+                                 switch: &str, switch_sigma: f64) {
+        self.add_gaussian_var(switch, 1);
+
+        let mut switched = variables;
+        switched.push(String::from(switch));
+        self.add_nonlinear_factor(switched, potential.switchable());
+        self.add_nonlinear_factor(vec!(String::from(switch)), NonlinearPotential::switch_prior(switch_sigma));
+    }
+
+    /// Get the total number of components of the variables, panicking if any of them is discrete.
+    fn continuous_dimension(&self, variables: &[String]) -> usize {
+        let mut dimension = 0;
+        for var in variables.iter() {
+            match self.dimension(var) {
+                0 => panic!("The variable {} is discrete and cannot be in a continuous factor.", var),
+                d => dimension += d
+            }
+        }
+        dimension
+    }
+
+    /// Attach a factor with the given potential to its variables and record it in the graph.
+    fn push_factor(&mut self, variables: Vec<String>, potential: Potential) {
+        let factor = Factor::with_potential(self.next_id, variables.clone(), potential);
         for var in variables.iter() {
             match self.variables.get_mut(var) {
                 Some(var_obj) => {
-                    var_obj.add_factor(Factor::new(self.next_id,variables.clone(), func));
+                    var_obj.add_factor(factor.clone());
                 },
                 None => panic!("The variable {} was not found in the factor graph.", var)
             }
         }
 
-        self.factors.push(Factor::new(self.next_id, variables.clone(), func));
+        self.factors.push(factor);
 
-        self.all_names.insert(self.next_id as usize, String::from(format!("factor<{:?}>", variables.clone())));
+        self.all_names.insert(self.next_id as usize, format!("factor<{:?}>", variables));
         self.is_factor.insert(self.next_id as usize, true);
 
         self.next_id += 1;
     }
 
+    /// Get the variable with the specified name, if it is in the factor graph.
+    pub fn get_variable(&self, name: &str) -> Option<&dyn Variable> {
+        self.variables.get(name).map(|var| var.as_ref())
+    }
+
+    /// Get the names of all variables in the factor graph, in the order they were added.
+    pub fn get_variable_names(&self) -> Vec<String> {
+        self.all_names.iter()
+            .zip(self.is_factor.iter())
+            .filter(|&(_, is_factor)| !is_factor)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Get all factors in the factor graph, in the order they were added.
+    pub fn get_factors(&self) -> &Vec<Factor> {
+        &self.factors
+    }
+
+    /// Get the number of values the named discrete variable can take.
+    pub(crate) fn domain_size(&self, name: &str) -> usize {
+        match self.variables.get(name) {
+            Some(var) if var.get_dimension() > 0 => panic!("The variable {} is continuous, not discrete.", name),
+            Some(var) => var.get_domain().len(),
+            None => panic!("The variable {} was not found in the factor graph.", name)
+        }
+    }
+
+    /// Get the number of real components of the named variable, which is zero for discrete variables.
+    pub(crate) fn dimension(&self, name: &str) -> usize {
+        match self.variables.get(name) {
+            Some(var) => var.get_dimension(),
+            None => panic!("The variable {} was not found in the factor graph.", name)
+        }
+    }
+
+    /// Compute the joint distribution of the query variables conditioned on the evidence.
+    ///
+    /// The result is a normalized table over the query variables, in the order given. Queries that fit
+    /// inside a single clique of the junction tree are read off the calibrated tree, and the rest fall back
+    /// to variable elimination. Observed query variables get all their mass on the observed value.
+    pub fn query(&self, variables: &[&str], evidence: &Assignment) -> Table {
+        let query: Vec<String> = variables.iter().map(|v| String::from(*v)).collect();
+        let free: Vec<String> = query.iter().filter(|v| !evidence.contains_key(*v)).cloned().collect();
+
+        let fits_clique = JunctionTree::cliques_for(self, evidence).iter()
+            .any(|clique| free.iter().all(|v| clique.contains(v)));
+        let free_table = if free.is_empty() {
+            Table::constant(0.0)
+        } else if fits_clique {
+            JunctionTree::new(self, evidence).marginal(&free).unwrap()
+        } else {
+            VariableElimination::new(self).query(&free, evidence)
+        };
+
+        let cardinalities = query.iter().map(|v| self.domain_size(v)).collect();
+        let mut result = Table::uniform(query.clone(), cardinalities).product(&free_table);
+        for var in query.iter() {
+            if let Some(&observed) = evidence.get(var) {
+                let indicator = (0..self.domain_size(var) as u32)
+                    .map(|val| if val == observed { 0.0 } else { f64::NEG_INFINITY })
+                    .collect();
+                result = result.product(&Table::new(vec!(var.clone()), vec!(self.domain_size(var)), indicator));
+            }
+        }
+        result
+    }
+
     /// Render this graph to a Graphviz file
     pub fn render_to<W: Write>(&self, output: &mut W) {
         match dot::render(self, output) {
@@ -113,7 +434,7 @@ impl FactorGraph {
                                                   self.variables.values().len());
 
         let mut var_iteration = true;
-        let mut var_queue: VecDeque<&Box<Variable>> = VecDeque::new();
+        let mut var_queue: VecDeque<&Box<dyn Variable>> = VecDeque::new();
         let mut factor_queue: VecDeque<&Factor> = VecDeque::new();
         var_queue.push_back(root);
 
@@ -189,6 +510,62 @@ mod tests {
         graph.add_factor::<i32>(vec!(String::from("second")), dummy_func);
     }
 
+    fn agree(args: &[u32]) -> i32 {
+        if args[0] == args[1] { 3 } else { 1 }
+    }
+
+    fn make_cycle() -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d"] {
+            graph.add_discrete_var(name, vec![0, 1, 2]);
+        }
+        for &(x, y) in [("a", "b"), ("b", "c"), ("c", "d"), ("d", "a")].iter() {
+            graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), agree);
+        }
+        graph.add_factor::<i32>(vec!(String::from("a")), dummy_func);
+        graph
+    }
+
+    #[test]
+    fn query_matches_enumeration() {
+        let graph = make_cycle();
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("c"), 2);
+
+        // (a, b) share a junction tree clique, while (b, d) is only reachable by variable elimination.
+        for query in [["a", "b"], ["b", "d"]].iter() {
+            let table = graph.query(query, &evidence);
+            assert_eq!(table.get_variables(), &vec!(String::from(query[0]), String::from(query[1])));
+
+            for index in 0..table.size() {
+                let assignment = table.assignment_at(index);
+                let mut joint_evidence = evidence.clone();
+                joint_evidence.insert(String::from(query[0]), assignment[0]);
+                joint_evidence.insert(String::from(query[1]), assignment[1]);
+
+                let conditional = Enumeration::new(&graph).with_evidence(evidence.clone()).run().unwrap();
+                let joint = Enumeration::new(&graph).with_evidence(joint_evidence).run().unwrap();
+                let expected = (joint.log_partition - conditional.log_partition).exp();
+                assert!((table.get_log_values()[index].exp() - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn query_includes_observed_variables() {
+        let graph = make_cycle();
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("c"), 1);
+
+        let table = graph.query(&["c", "a"], &evidence);
+        let probs = table.probabilities();
+        let a_marginal = graph.query(&["a"], &evidence).probabilities();
+        for a in 0..3 {
+            assert_eq!(probs[a], 0.0);
+            assert!((probs[3 + a] - a_marginal[a]).abs() < 1e-12);
+        }
+    }
+
     #[test]
     fn factor_is_added_to_var() {
         let mut graph = FactorGraph::new();
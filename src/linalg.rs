@@ -0,0 +1,332 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with the small dense linear algebra needed by the continuous models
+
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+
+/// Struct representing a dense matrix stored in row-major order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    /// Create a new Matrix from its entries in row-major order.
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Matrix {
+        if data.len() != rows * cols {
+            panic!("A {}x{} matrix needs {} entries, got {}", rows, cols, rows * cols, data.len());
+        }
+
+        Matrix {
+            rows,
+            cols,
+            data,
+        }
+    }
+
+    /// Create a Matrix of zeros.
+    pub fn zeros(rows: usize, cols: usize) -> Matrix {
+        Matrix::new(rows, cols, vec![0.0; rows * cols])
+    }
+
+    /// Create an identity Matrix.
+    pub fn identity(size: usize) -> Matrix {
+        Matrix::from_diagonal(&vec![1.0; size])
+    }
+
+    /// Create a square Matrix with the given diagonal.
+    pub fn from_diagonal(diagonal: &[f64]) -> Matrix {
+        let mut result = Matrix::zeros(diagonal.len(), diagonal.len());
+        for (i, d) in diagonal.iter().enumerate() {
+            result[(i, i)] = *d;
+        }
+        result
+    }
+
+    /// Create a Matrix from a list of rows.
+    pub fn from_rows(rows: &[Vec<f64>]) -> Matrix {
+        let cols = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != cols) {
+            panic!("Every row of a matrix must have the same length");
+        }
+
+        Matrix::new(rows.len(), cols, rows.iter().flat_map(|row| row.iter().cloned()).collect())
+    }
+
+    /// Create a single-column Matrix from a vector.
+    pub fn column(values: &[f64]) -> Matrix {
+        Matrix::new(values.len(), 1, values.to_vec())
+    }
+
+    /// Function to get the number of rows.
+    pub fn get_rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Function to get the number of columns.
+    pub fn get_cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Function to get the entries in row-major order.
+    pub fn get_data(&self) -> &Vec<f64> {
+        &self.data
+    }
+
+    /// Get the entries along the diagonal.
+    pub fn diagonal(&self) -> Vec<f64> {
+        (0..self.rows.min(self.cols)).map(|i| self[(i, i)]).collect()
+    }
+
+    /// Get the transpose of this matrix.
+    pub fn transpose(&self) -> Matrix {
+        let mut result = Matrix::zeros(self.cols, self.rows);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                result[(j, i)] = self[(i, j)];
+            }
+        }
+        result
+    }
+
+    /// Get a copy of this matrix with every entry multiplied by `factor`.
+    pub fn scaled(&self, factor: f64) -> Matrix {
+        Matrix::new(self.rows, self.cols, self.data.iter().map(|v| v * factor).collect())
+    }
+
+    /// Multiply this matrix by a vector.
+    pub fn mul_vec(&self, v: &[f64]) -> Vec<f64> {
+        if v.len() != self.cols {
+            panic!("Cannot multiply a {}x{} matrix by a vector of length {}", self.rows, self.cols, v.len());
+        }
+
+        (0..self.rows)
+            .map(|i| self.data[i * self.cols..(i + 1) * self.cols].iter().zip(v.iter()).map(|(a, b)| a * b).sum())
+            .collect()
+    }
+
+    /// Get the submatrix with the given rows and columns.
+    pub fn select(&self, rows: &[usize], cols: &[usize]) -> Matrix {
+        let mut result = Matrix::zeros(rows.len(), cols.len());
+        for (i, &r) in rows.iter().enumerate() {
+            for (j, &c) in cols.iter().enumerate() {
+                result[(i, j)] = self[(r, c)];
+            }
+        }
+        result
+    }
+
+    /// Get the contiguous block with its top-left corner at `(row, col)`.
+    pub fn block(&self, row: usize, col: usize, rows: usize, cols: usize) -> Matrix {
+        let row_indices: Vec<usize> = (row..row + rows).collect();
+        let col_indices: Vec<usize> = (col..col + cols).collect();
+        self.select(&row_indices, &col_indices)
+    }
+
+    /// Add `other` into the block with its top-left corner at `(row, col)`.
+    pub fn add_block(&mut self, row: usize, col: usize, other: &Matrix) {
+        for i in 0..other.rows {
+            for j in 0..other.cols {
+                self[(row + i, col + j)] += other[(i, j)];
+            }
+        }
+    }
+
+    /// Compute the lower-triangular Cholesky factor `L` with `L * L^T` equal to this matrix.
+    ///
+    /// Returns None if the matrix is not symmetric positive definite, treating pivots that lose all but a
+    /// `1e-12` fraction of their diagonal entry as zero.
+    pub fn cholesky(&self) -> Option<Matrix> {
+        if self.rows != self.cols {
+            return None;
+        }
+
+        let n = self.rows;
+        let mut l = Matrix::zeros(n, n);
+        for j in 0..n {
+            let mut diag = self[(j, j)];
+            for k in 0..j {
+                diag -= l[(j, k)] * l[(j, k)];
+            }
+            if diag <= 1e-12 * self[(j, j)].abs() || !diag.is_finite() {
+                return None;
+            }
+            l[(j, j)] = diag.sqrt();
+
+            for i in j + 1..n {
+                let mut value = self[(i, j)];
+                for k in 0..j {
+                    value -= l[(i, k)] * l[(j, k)];
+                }
+                l[(i, j)] = value / l[(j, j)];
+            }
+        }
+        Some(l)
+    }
+
+    /// Solve `self * X = rhs` by LU decomposition with partial pivoting.
+    ///
+    /// Returns None if the matrix is singular.
+    pub fn solve(&self, rhs: &Matrix) -> Option<Matrix> {
+        if self.rows != self.cols || rhs.rows != self.rows {
+            panic!("Cannot solve a {}x{} system with a {}x{} right hand side",
+                   self.rows, self.cols, rhs.rows, rhs.cols);
+        }
+
+        let n = self.rows;
+        let scale = self.data.iter().fold(0.0f64, |acc, v| acc.max(v.abs()));
+        let mut a = self.clone();
+        let mut x = rhs.clone();
+
+        for col in 0..n {
+            let pivot = (col..n).max_by(|&i, &j| a[(i, col)].abs().partial_cmp(&a[(j, col)].abs()).unwrap())?;
+            if a[(pivot, col)].abs() <= 1e-13 * scale || scale == 0.0 {
+                return None;
+            }
+
+            if pivot != col {
+                for k in 0..n {
+                    a.data.swap(pivot * n + k, col * n + k);
+                }
+                for k in 0..x.cols {
+                    x.data.swap(pivot * x.cols + k, col * x.cols + k);
+                }
+            }
+
+            for row in col + 1..n {
+                let ratio = a[(row, col)] / a[(col, col)];
+                if ratio != 0.0 {
+                    for k in col..n {
+                        a[(row, k)] -= ratio * a[(col, k)];
+                    }
+                    for k in 0..x.cols {
+                        x[(row, k)] -= ratio * x[(col, k)];
+                    }
+                }
+            }
+        }
+
+        for row in (0..n).rev() {
+            for k in 0..x.cols {
+                let mut value = x[(row, k)];
+                for j in row + 1..n {
+                    value -= a[(row, j)] * x[(j, k)];
+                }
+                x[(row, k)] = value / a[(row, row)];
+            }
+        }
+        Some(x)
+    }
+
+    /// Solve `self * x = rhs` for a vector right hand side.
+    pub fn solve_vec(&self, rhs: &[f64]) -> Option<Vec<f64>> {
+        self.solve(&Matrix::column(rhs)).map(|x| x.data)
+    }
+
+    /// Compute the inverse of this matrix, or None if it is singular.
+    pub fn inverse(&self) -> Option<Matrix> {
+        self.solve(&Matrix::identity(self.rows))
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (row, col): (usize, usize)) -> &f64 {
+        &self.data[row * self.cols + col]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut f64 {
+        &mut self.data[row * self.cols + col]
+    }
+}
+
+impl<'a> Add for &'a Matrix {
+    type Output = Matrix;
+
+    fn add(self, other: &'a Matrix) -> Matrix {
+        if (self.rows, self.cols) != (other.rows, other.cols) {
+            panic!("Cannot add a {}x{} matrix to a {}x{} matrix", other.rows, other.cols, self.rows, self.cols);
+        }
+
+        Matrix::new(self.rows, self.cols, self.data.iter().zip(other.data.iter()).map(|(a, b)| a + b).collect())
+    }
+}
+
+impl<'a> Sub for &'a Matrix {
+    type Output = Matrix;
+
+    fn sub(self, other: &'a Matrix) -> Matrix {
+        self + &other.scaled(-1.0)
+    }
+}
+
+impl<'a> Mul for &'a Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: &'a Matrix) -> Matrix {
+        if self.cols != other.rows {
+            panic!("Cannot multiply a {}x{} matrix by a {}x{} matrix", self.rows, self.cols, other.rows, other.cols);
+        }
+
+        let mut result = Matrix::zeros(self.rows, other.cols);
+        for i in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self[(i, k)];
+                if a != 0.0 {
+                    for j in 0..other.cols {
+                        result[(i, j)] += a * other[(k, j)];
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cholesky_and_solve_agree() {
+        let a = Matrix::from_rows(&[vec!(4.0, 2.0, 0.6), vec!(2.0, 5.0, 1.0), vec!(0.6, 1.0, 3.0)]);
+        let l = a.cholesky().unwrap();
+        let reconstructed = &l * &l.transpose();
+        for (x, y) in reconstructed.get_data().iter().zip(a.get_data().iter()) {
+            assert!((x - y).abs() < 1e-12);
+        }
+
+        let b = vec!(1.0, -2.0, 0.5);
+        let x = a.solve_vec(&b).unwrap();
+        for (lhs, rhs) in a.mul_vec(&x).iter().zip(b.iter()) {
+            assert!((lhs - rhs).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn singular_matrices_are_detected() {
+        let a = Matrix::from_rows(&[vec!(1.0, 2.0), vec!(2.0, 4.0)]);
+
+        assert!(a.cholesky().is_none());
+        assert!(a.inverse().is_none());
+    }
+
+    #[test]
+    fn round_off_does_not_hide_rank_deficiency() {
+        // The columns are parallel, but round-off leaves the last pivot of the normal matrix slightly positive.
+        let jacobian = Matrix::from_rows(&[vec!(0.1, 0.3), vec!(0.2, 0.6)]);
+        let normal = &jacobian.transpose() * &jacobian;
+
+        assert!(normal.cholesky().is_none());
+    }
+}
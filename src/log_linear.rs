@@ -0,0 +1,122 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with log-linear potentials over discrete variables, whose weights are parameters of the graph
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type FeatureFunc = dyn Fn(&[u32]) -> f64;
+
+/// Struct representing a log-linear potential `exp(sum_k theta[w_k] f_k(x))` over discrete variables.
+///
+/// Each feature function `f_k` is paired with the index `w_k` of its weight in the parameter vector of the
+/// graph the factor is added to, so a weight can be shared by features of many factors. The potential
+/// reads the graph's current parameters, which `FactorGraph::set_parameters` changes for every factor at once.
+#[derive(Clone)]
+pub struct LogLinearPotential {
+    features: Vec<(usize, Rc<FeatureFunc>)>,
+    parameters: Rc<RefCell<Vec<f64>>>,
+}
+
+impl Default for LogLinearPotential {
+    fn default() -> LogLinearPotential {
+        LogLinearPotential::new()
+    }
+}
+
+impl LogLinearPotential {
+    /// Create a new LogLinearPotential without features, which is constant.
+    pub fn new() -> LogLinearPotential {
+        LogLinearPotential {
+            features: vec!(),
+            parameters: Rc::new(RefCell::new(vec!())),
+        }
+    }
+
+    /// Add a feature function of the factor's values, weighted by the graph parameter at index `weight`.
+    pub fn with_feature<F: Fn(&[u32]) -> f64 + 'static>(mut self, weight: usize, feature: F) -> LogLinearPotential {
+        self.features.push((weight, Rc::new(feature)));
+        self
+    }
+
+    /// Get the index of the weight of every feature, in the order they were added.
+    pub fn get_weights(&self) -> Vec<usize> {
+        self.features.iter().map(|f| f.0).collect()
+    }
+
+    /// Evaluate every feature, giving the index of its weight and its value.
+    pub fn features(&self, values: &[u32]) -> Vec<(usize, f64)> {
+        self.features.iter().map(|&(weight, ref feature)| (weight, feature(values))).collect()
+    }
+
+    /// Evaluate the log-potential under the current parameters of the graph.
+    pub fn log_value(&self, values: &[u32]) -> f64 {
+        let parameters = self.parameters.borrow();
+        self.features.iter().map(|&(weight, ref feature)| parameters[weight] * feature(values)).sum()
+    }
+
+    /// Read the weights from the given parameter vector, shared with the graph.
+    pub(crate) fn with_parameters(mut self, parameters: Rc<RefCell<Vec<f64>>>) -> LogLinearPotential {
+        self.parameters = parameters;
+        self
+    }
+}
+
+impl std::fmt::Debug for LogLinearPotential {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "LogLinearPotential {{ weights: {:?}, <feature_funcs> }}", self.get_weights())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use *;
+
+    fn agreement(vals: &[u32]) -> f64 {
+        if vals[0] == vals[1] { 1.0 } else { -1.0 }
+    }
+
+    #[test]
+    fn shared_weights_change_every_factor() {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+        let coupling = graph.add_parameter(0.0);
+        let field = graph.add_parameter(0.0);
+        for pair in &[("a", "b"), ("b", "c")] {
+            graph.add_log_linear_factor(vec!(String::from(pair.0), String::from(pair.1)),
+                                        LogLinearPotential::new().with_feature(coupling, agreement));
+        }
+        graph.add_log_linear_factor(vec!(String::from("a")),
+                                    LogLinearPotential::new().with_feature(field, |v| v[0] as f64));
+
+        let uniform = Enumeration::new(&graph).run().unwrap();
+        assert!((uniform.marginals["c"][1] - 0.5).abs() < 1e-12);
+
+        graph.set_parameters(&[2.0, 3.0]);
+        assert_eq!(graph.get_parameters(), vec!(2.0, 3.0));
+        for factor in graph.get_factors().iter().take(2) {
+            assert!((factor.log_potential(&[1, 1]) - 2.0).abs() < 1e-12);
+            assert!((factor.log_potential(&[0, 1]) + 2.0).abs() < 1e-12);
+        }
+
+        // The field on a and the coupling through b both favour c = 1.
+        let coupled = Enumeration::new(&graph).run().unwrap();
+        assert!(coupled.marginals["c"][1] > 0.9);
+    }
+
+    #[test]
+    #[should_panic]
+    fn setting_parameters_checks_the_length() {
+        let mut graph = FactorGraph::new();
+        graph.add_parameter(1.0);
+        graph.set_parameters(&[1.0, 2.0]);
+    }
+}
@@ -15,14 +15,16 @@ use std::env;
 
 use getopts::Options;
 
-use factor_graph::FactorGraph;
+use factor_graph::{FactorGraph, LogLinearPotential};
 
-fn dummy_func(args: &[u32]) -> i32 {
-    args.len() as i32
+fn agreement(args: &[u32]) -> f64 {
+    if args[0] == args[1] { 1.0 } else { -1.0 }
 }
 
 fn make_ising_model(x_dim: u32, y_dim: u32) -> FactorGraph {
     let mut graph = FactorGraph::new();
+    let coupling = graph.add_parameter(0.5);
+    let edge = || LogLinearPotential::new().with_feature(coupling, agreement);
 
     for i in 0..x_dim {
         for j in 0..y_dim {
@@ -30,27 +32,17 @@ fn make_ising_model(x_dim: u32, y_dim: u32) -> FactorGraph {
         }
     }
 
-    // Add factors between adjacent nodes
+    // Add factors between adjacent nodes, all sharing one coupling weight
     for i in 0..x_dim {
         for j in 0..y_dim {
             if i > 0 {
-                graph.add_factor::<i32>(vec!(String::from(format!("({},{})", i - 1, j)),
-                                             String::from(format!("({},{})", i, j))), dummy_func);
+                graph.add_log_linear_factor(vec!(format!("({},{})", i - 1, j),
+                                                 format!("({},{})", i, j)), edge());
             }
 
             if j > 0 {
-                graph.add_factor::<i32>(vec!(String::from(format!("({},{})", i, j - 1)),
-                                             String::from(format!("({},{})", i, j))), dummy_func);
-            }
-
-            if j < y_dim - 1 {
-                graph.add_factor::<i32>(vec!(String::from(format!("({},{})", i, j + 1)),
-                                             String::from(format!("({},{})", i, j))), dummy_func);
-            }
-
-            if i < x_dim - 1 {
-                graph.add_factor::<i32>(vec!(String::from(format!("({},{})", i + 1, j)),
-                                             String::from(format!("({},{})", i, j))), dummy_func);
+                graph.add_log_linear_factor(vec!(format!("({},{})", i, j - 1),
+                                                 format!("({},{})", i, j)), edge());
             }
         }
     }
@@ -76,7 +68,7 @@ fn main() {
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
-        Err(f) => { panic!(f.to_string()) }
+        Err(f) => { panic!("{}", f) }
     };
 
     if matches.opt_present("h") {
@@ -0,0 +1,497 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with max-margin learning of conditional random fields, which only needs MAP inference
+
+use std::slice;
+
+use *;
+use mle::{FeatureTables, complete_states};
+use model::Model;
+use rng::Rng;
+
+type MapOracle = dyn Fn(&FactorGraph, &Assignment) -> Assignment;
+
+/// Get the default MAP oracle, max-product variable elimination.
+fn default_oracle() -> Box<MapOracle> {
+    Box::new(|graph: &FactorGraph, evidence: &Assignment| VariableElimination::new(graph).map(evidence).0)
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+/// Struct holding one labelled input, with its graph and a copy adding the Hamming loss to every label.
+#[derive(Debug)]
+struct Example {
+    graph: FactorGraph,
+    augmented: FactorGraph,
+    model: Model,
+    features: FeatureTables,
+    state: Vec<u32>,
+}
+
+impl Example {
+    fn new<X>(crf: &ConditionalRandomField<X>, input: &X, labels: &Assignment) -> Example {
+        let graph = crf.instance(input);
+        let model = Model::new(&graph);
+        let features = FeatureTables::new(&graph, &model);
+        let state = complete_states(&model, slice::from_ref(labels)).remove(0);
+
+        let mut augmented = crf.instance(input);
+        for ((var, &card), &label) in model.names.iter().zip(model.cardinalities.iter()).zip(state.iter()) {
+            let loss = (0..card as u32).map(|val| if val == label { 0.0 } else { 1.0 }).collect();
+            augmented.add_table_factor(Table::new(vec!(var.clone()), vec!(card), loss));
+        }
+
+        Example { graph, augmented, model, features, state }
+    }
+
+    /// Get the features of the log-linear factors at a state.
+    fn joint_features(&self, state: &[u32], num_parameters: usize) -> Vec<f64> {
+        let mut features = vec![0.0; num_parameters];
+        self.features.add_state(&self.model, state, 1.0, &mut features);
+        features
+    }
+
+    /// Get the log-potential of the factors that are not log-linear at a state.
+    fn fixed(&self, state: &[u32]) -> f64 {
+        (0..self.model.tables.len())
+            .filter(|f| !self.features.factors.contains(f))
+            .map(|f| self.model.factor_log_potential(f, state))
+            .sum()
+    }
+
+    /// Count the labels of a state that differ from the true labelling.
+    fn loss(&self, state: &[u32]) -> f64 {
+        state.iter().zip(self.state.iter()).filter(|&(a, b)| a != b).count() as f64
+    }
+
+    /// Find the highest scoring state under the given parameters with the oracle, with or without the loss added.
+    fn decode(&mut self, oracle: &MapOracle, parameters: &[f64], loss_augmented: bool) -> Vec<u32> {
+        let graph = if loss_augmented { &mut self.augmented } else { &mut self.graph };
+        graph.set_parameters(parameters);
+        let assignment = oracle(graph, &Assignment::new());
+        self.model.clamped(&assignment).into_iter().zip(self.model.names.iter())
+            .map(|(val, var)| match val {
+                Some(x) => x,
+                None => panic!("The MAP oracle did not label the variable {}", var)
+            })
+            .collect()
+    }
+}
+
+fn examples<X>(crf: &ConditionalRandomField<X>, data: &[(X, Assignment)]) -> Vec<Example> {
+    if data.is_empty() {
+        panic!("Max-margin learning needs at least one labelled input");
+    }
+
+    data.iter().map(|(input, labels)| Example::new(crf, input, labels)).collect()
+}
+
+/// Struct holding the result of training with the structured perceptron.
+#[derive(Clone, Debug)]
+pub struct PerceptronResult {
+    /// Learned parameters, which the field is also left with.
+    pub parameters: Vec<f64>,
+
+    /// Number of inputs whose MAP labelling was wrong in every epoch.
+    pub mistakes: Vec<usize>,
+}
+
+/// Struct fitting the parameters of a conditional random field with the structured perceptron.
+///
+/// Every epoch visits the labelled inputs in a random order, and whenever the MAP labelling under the current
+/// weights is wrong, adds the features of the true labelling and subtracts those of the predicted one. The MAP
+/// labelling is found by max-product variable elimination unless another oracle is set. Averaging the weights
+/// over every step, the default, makes the result much less sensitive to the last few updates.
+pub struct StructuredPerceptron<'a, X: 'a> {
+    crf: &'a mut ConditionalRandomField<X>,
+    data: &'a [(X, Assignment)],
+    epochs: usize,
+    averaged: bool,
+    seed: u64,
+    oracle: Box<MapOracle>,
+}
+
+impl<'a, X> StructuredPerceptron<'a, X> {
+    /// Create a new StructuredPerceptron, running 10 averaged epochs from the field's current parameters.
+    pub fn new(crf: &'a mut ConditionalRandomField<X>, data: &'a [(X, Assignment)]) -> StructuredPerceptron<'a, X> {
+        StructuredPerceptron {
+            crf,
+            data,
+            epochs: 10,
+            averaged: true,
+            seed: 0,
+            oracle: default_oracle(),
+        }
+    }
+
+    /// Set the maximum number of passes over the data.
+    pub fn with_epochs(mut self, epochs: usize) -> StructuredPerceptron<'a, X> {
+        self.epochs = epochs;
+        self
+    }
+
+    /// Set whether to return the average of the weights over every step rather than the last weights.
+    pub fn with_averaging(mut self, averaged: bool) -> StructuredPerceptron<'a, X> {
+        self.averaged = averaged;
+        self
+    }
+
+    /// Set the seed of the random number generator shuffling the data.
+    pub fn with_seed(mut self, seed: u64) -> StructuredPerceptron<'a, X> {
+        self.seed = seed;
+        self
+    }
+
+    /// Set the oracle giving the MAP labelling of a graph under evidence, which must label every variable.
+    pub fn with_oracle<F: Fn(&FactorGraph, &Assignment) -> Assignment + 'static>(mut self, oracle: F)
+                                                                                -> StructuredPerceptron<'a, X> {
+        self.oracle = Box::new(oracle);
+        self
+    }
+
+    /// Train until an epoch makes no mistakes or the epoch limit, leaving the field with the learned parameters.
+    pub fn run(self) -> PerceptronResult {
+        let mut examples = examples(self.crf, self.data);
+        let mut rng = Rng::new(self.seed);
+        let mut weights = self.crf.get_parameters();
+        let mut sum = vec![0.0; weights.len()];
+        let mut steps = 0;
+
+        let mut mistakes = vec!();
+        let mut order: Vec<usize> = (0..examples.len()).collect();
+        for _ in 0..self.epochs {
+            for i in (1..order.len()).rev() {
+                order.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+            }
+
+            let mut wrong = 0;
+            for &i in order.iter() {
+                let example = &mut examples[i];
+                let predicted = example.decode(&*self.oracle, &weights, false);
+                if predicted != example.state {
+                    wrong += 1;
+                    let truth = example.joint_features(&example.state, weights.len());
+                    let guess = example.joint_features(&predicted, weights.len());
+                    for (w, (t, g)) in weights.iter_mut().zip(truth.iter().zip(guess.iter())) {
+                        *w += t - g;
+                    }
+                }
+
+                for (s, w) in sum.iter_mut().zip(weights.iter()) {
+                    *s += w;
+                }
+                steps += 1;
+            }
+
+            mistakes.push(wrong);
+            if wrong == 0 {
+                break;
+            }
+        }
+
+        let parameters = if self.averaged && steps > 0 { sum.iter().map(|s| s / steps as f64).collect() } else { weights };
+        self.crf.set_parameters(&parameters);
+        PerceptronResult { parameters, mistakes }
+    }
+}
+
+impl<'a, X> std::fmt::Debug for StructuredPerceptron<'a, X> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "StructuredPerceptron {{ crf: {:?}, epochs: {}, averaged: {}, seed: {}, <oracle> }}", self.crf,
+               self.epochs, self.averaged, self.seed)
+    }
+}
+
+/// Struct holding the result of training a structured SVM.
+#[derive(Clone, Debug)]
+pub struct SsvmResult {
+    /// Learned parameters, which the field is also left with.
+    pub parameters: Vec<f64>,
+
+    /// Primal objective `|w|^2 / 2 + C * slack` at the weights of every iteration, with the exact slack found by
+    /// loss-augmented inference.
+    pub objectives: Vec<f64>,
+
+    /// Number of constraints in the working set when training stopped.
+    pub num_constraints: usize,
+
+    /// Number of iterations that were run.
+    pub iterations: usize,
+
+    /// Whether the most violated constraint was within the tolerance before the iteration limit.
+    pub converged: bool,
+}
+
+/// Struct fitting the parameters of a conditional random field as a structured SVM by the cutting-plane method.
+///
+/// This minimizes `|w|^2 / 2 + C * slack` such that, averaged over the labelled inputs, the score of the true
+/// labelling beats that of any other labelling by its Hamming loss, less the slack. That is the one-slack
+/// formulation: each iteration finds the most violated constraint by loss-augmented MAP inference, with
+/// max-product variable elimination unless another oracle is set, adds it to a working set, and solves the dual
+/// of the problem restricted to the working set. Training stops once no constraint is violated by more than the
+/// tolerance.
+pub struct StructuredSvm<'a, X: 'a> {
+    crf: &'a mut ConditionalRandomField<X>,
+    data: &'a [(X, Assignment)],
+    c: f64,
+    tolerance: f64,
+    max_iterations: usize,
+    oracle: Box<MapOracle>,
+}
+
+impl<'a, X> StructuredSvm<'a, X> {
+    /// Create a new StructuredSvm with `C = 1`, a tolerance of 1e-3 and at most 100 iterations.
+    ///
+    /// The weights always come from the dual solution, so training starts from zero weights whatever the field's
+    /// current parameters are.
+    pub fn new(crf: &'a mut ConditionalRandomField<X>, data: &'a [(X, Assignment)]) -> StructuredSvm<'a, X> {
+        StructuredSvm {
+            crf,
+            data,
+            c: 1.0,
+            tolerance: 1e-3,
+            max_iterations: 100,
+            oracle: default_oracle(),
+        }
+    }
+
+    /// Set the weight `C` of the slack against the norm of the weights.
+    pub fn with_c(mut self, c: f64) -> StructuredSvm<'a, X> {
+        if c <= 0.0 {
+            panic!("The slack weight must be positive, got {}", c);
+        }
+
+        self.c = c;
+        self
+    }
+
+    /// Set how far the most violated constraint may exceed the slack when training stops.
+    pub fn with_tolerance(mut self, tolerance: f64) -> StructuredSvm<'a, X> {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set the maximum number of cutting-plane iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> StructuredSvm<'a, X> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Set the oracle giving the MAP labelling of a graph under evidence, which must label every variable.
+    ///
+    /// The oracle is given graphs with the Hamming loss added as unary tables, so it must handle table factors.
+    pub fn with_oracle<F: Fn(&FactorGraph, &Assignment) -> Assignment + 'static>(mut self, oracle: F)
+                                                                                -> StructuredSvm<'a, X> {
+        self.oracle = Box::new(oracle);
+        self
+    }
+
+    /// Train until no constraint is violated, leaving the field with the learned parameters.
+    pub fn run(self) -> SsvmResult {
+        let mut examples = examples(self.crf, self.data);
+        let num_parameters = self.crf.get_parameters().len();
+        let scale = 1.0 / examples.len() as f64;
+
+        // The working set starts with the constraint of zero slack, whose dual variable takes up the unused mass.
+        let mut constraints: Vec<(Vec<f64>, f64)> = vec!((vec![0.0; num_parameters], 0.0));
+        let mut alphas = vec!(self.c);
+        let mut weights = vec![0.0; num_parameters];
+
+        let mut result = SsvmResult {
+            parameters: vec!(),
+            objectives: vec!(),
+            num_constraints: 0,
+            iterations: 0,
+            converged: false,
+        };
+        while result.iterations < self.max_iterations {
+            result.iterations += 1;
+
+            let mut difference = vec![0.0; num_parameters];
+            let mut margin = 0.0;
+            for example in examples.iter_mut() {
+                let violator = example.decode(&*self.oracle, &weights, true);
+                let truth = example.joint_features(&example.state, num_parameters);
+                let guess = example.joint_features(&violator, num_parameters);
+                for (d, (t, g)) in difference.iter_mut().zip(truth.iter().zip(guess.iter())) {
+                    *d += scale * (t - g);
+                }
+                margin += scale * (example.loss(&violator) + example.fixed(&violator) - example.fixed(&example.state));
+            }
+
+            let slack = constraints.iter().map(|(a, b)| b - dot(a, &weights)).fold(0.0, f64::max);
+            let violation = margin - dot(&difference, &weights);
+            result.objectives.push(0.5 * dot(&weights, &weights) + self.c * violation.max(0.0));
+            if violation <= slack + self.tolerance {
+                result.converged = true;
+                break;
+            }
+
+            constraints.push((difference, margin));
+            alphas.push(0.0);
+            weights = solve_dual(&constraints, &mut alphas);
+        }
+
+        result.num_constraints = constraints.len() - 1;
+        self.crf.set_parameters(&weights);
+        result.parameters = weights;
+        result
+    }
+}
+
+impl<'a, X> std::fmt::Debug for StructuredSvm<'a, X> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "StructuredSvm {{ crf: {:?}, c: {}, tolerance: {}, max_iterations: {}, <oracle> }}", self.crf,
+               self.c, self.tolerance, self.max_iterations)
+    }
+}
+
+/// Maximize `sum_j alpha_j b_j - |sum_j alpha_j a_j|^2 / 2` over non-negative `alpha` keeping its sum, by moving
+/// mass between pairs of constraints, and give the weights `sum_j alpha_j a_j`.
+fn solve_dual(constraints: &[(Vec<f64>, f64)], alphas: &mut [f64]) -> Vec<f64> {
+    let weights_of = |alphas: &[f64]| {
+        let mut weights = vec![0.0; constraints[0].0.len()];
+        for ((a, _), alpha) in constraints.iter().zip(alphas.iter()) {
+            for (w, x) in weights.iter_mut().zip(a.iter()) {
+                *w += alpha * x;
+            }
+        }
+        weights
+    };
+
+    let mut weights = weights_of(alphas);
+    for _ in 0..1000 {
+        let mut improvement: f64 = 0.0;
+        for j in 0..constraints.len() {
+            for k in 0..constraints.len() {
+                if j == k || alphas[k] == 0.0 {
+                    continue;
+                }
+
+                let (ref a_j, b_j) = constraints[j];
+                let (ref a_k, b_k) = constraints[k];
+                let direction: Vec<f64> = a_j.iter().zip(a_k.iter()).map(|(x, y)| x - y).collect();
+                let ascent = (b_j - b_k) - dot(&direction, &weights);
+                let curvature = dot(&direction, &direction);
+                if ascent <= 0.0 {
+                    continue;
+                }
+
+                // Move mass from k to j, as far as the quadratic allows.
+                let step = if curvature > 0.0 { (ascent / curvature).min(alphas[k]) } else { alphas[k] };
+                alphas[j] += step;
+                alphas[k] -= step;
+                for (w, d) in weights.iter_mut().zip(direction.iter()) {
+                    *w += step * d;
+                }
+                improvement = improvement.max(step * ascent - 0.5 * step * step * curvature);
+            }
+        }
+
+        if improvement < 1e-14 {
+            break;
+        }
+    }
+    weights_of(alphas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use test_support::make_tagger;
+
+    /// Label sequences of six random observations with the MAP labelling of the tagger.
+    fn make_data(num_samples: usize) -> Vec<(Vec<f64>, Assignment)> {
+        let mut crf = make_tagger();
+        crf.set_parameters(&[2.0, -0.5, 1.0]);
+        let mut rng = Rng::new(5);
+        (0..num_samples)
+            .map(|_| {
+                let observations: Vec<f64> = (0..6).map(|_| 2.0 * rng.next_gaussian()).collect();
+                let labels = crf.predict(&observations);
+                (observations, labels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn perceptron_separates_the_training_data() {
+        let data = make_data(40);
+        let mut crf = make_tagger();
+        let result = StructuredPerceptron::new(&mut crf, &data).with_averaging(false).with_epochs(50).run();
+
+        assert_eq!(result.mistakes[result.mistakes.len() - 1], 0);
+        assert!(result.mistakes[0] > 0);
+        for (input, labels) in data.iter() {
+            assert_eq!(&crf.predict(input), labels);
+        }
+    }
+
+    #[test]
+    fn svm_satisfies_the_margin_constraints() {
+        let data = make_data(40);
+        let mut crf = make_tagger();
+        let result = StructuredSvm::new(&mut crf, &data).with_c(10.0).with_tolerance(1e-4).run();
+        assert!(result.converged);
+        assert!(result.num_constraints > 0);
+
+        // Every labelling scores below the true one by about its Hamming loss, less the optimal slack.
+        let mut examples = examples(&crf, &data);
+        let mut violation = 0.0;
+        for example in examples.iter_mut() {
+            let violator = example.decode(&*default_oracle(), &result.parameters, true);
+            let truth = example.joint_features(&example.state, 3);
+            let guess = example.joint_features(&violator, 3);
+            let margin = dot(&result.parameters, &truth) - dot(&result.parameters, &guess);
+            violation += (example.loss(&violator) - margin) / data.len() as f64;
+        }
+        let objective = 0.5 * dot(&result.parameters, &result.parameters) + 10.0 * violation.max(0.0);
+        assert!((objective - result.objectives[result.objectives.len() - 1]).abs() < 1e-9);
+        assert!(result.objectives.iter().all(|&o| o >= objective - 10.0 * 1e-4 - 1e-9));
+
+        let correct = data.iter().filter(|(input, labels)| &crf.predict(input) == labels).count();
+        assert!(correct as f64 >= 0.9 * data.len() as f64);
+    }
+
+    #[test]
+    fn training_uses_the_given_oracle() {
+        let data = make_data(20);
+        let mut crf = make_tagger();
+        let expected = StructuredSvm::new(&mut crf, &data).run();
+
+        // Enumeration finds the same labellings as variable elimination, so training takes the same steps.
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut crf = make_tagger();
+        let result = StructuredSvm::new(&mut crf, &data)
+            .with_oracle(move |graph: &FactorGraph, evidence: &Assignment| {
+                counter.set(counter.get() + 1);
+                Enumeration::new(graph).with_evidence(evidence.clone()).run().unwrap().map_assignment
+            })
+            .run();
+        assert_eq!(calls.get(), 20 * result.iterations);
+        assert_eq!(result.iterations, expected.iterations);
+        for (w, v) in result.parameters.iter().zip(expected.parameters.iter()) {
+            assert!((w - v).abs() < 1e-9);
+        }
+
+        let mut crf = make_tagger();
+        let expected = StructuredPerceptron::new(&mut crf, &data).run();
+        let mut crf = make_tagger();
+        let perceptron = StructuredPerceptron::new(&mut crf, &data)
+            .with_oracle(|graph: &FactorGraph, evidence: &Assignment| {
+                Enumeration::new(graph).with_evidence(evidence.clone()).run().unwrap().map_assignment
+            })
+            .run();
+        assert_eq!(perceptron.mistakes, expected.mistakes);
+    }
+}
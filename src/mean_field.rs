@@ -0,0 +1,164 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with naive mean-field inference
+
+use *;
+use model::Model;
+use table::log_sum_exp;
+
+/// Struct running naive mean-field coordinate ascent over the discrete variables of a factor graph.
+#[derive(Debug)]
+pub struct MeanField<'a> {
+    graph: &'a FactorGraph,
+    evidence: Assignment,
+    max_iterations: usize,
+    tolerance: f64,
+}
+
+impl<'a> MeanField<'a> {
+    /// Create a new MeanField for the graph, running at most 100 sweeps.
+    pub fn new(graph: &'a FactorGraph) -> MeanField<'a> {
+        MeanField {
+            graph,
+            evidence: Assignment::new(),
+            max_iterations: 100,
+            tolerance: 1e-6,
+        }
+    }
+
+    /// Clamp the given variables to observed values.
+    pub fn with_evidence(mut self, evidence: Assignment) -> MeanField<'a> {
+        self.evidence = evidence;
+        self
+    }
+
+    /// Set the maximum number of sweeps over the variables.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> MeanField<'a> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Stop once no marginal changes by more than `tolerance` during a sweep.
+    pub fn with_tolerance(mut self, tolerance: f64) -> MeanField<'a> {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Run mean-field until convergence, returning the fully factorized approximate marginals.
+    pub fn run(&self) -> Marginals {
+        let model = Model::new(self.graph);
+        let clamped = model.clamped(&self.evidence);
+
+        let mut q: Vec<Vec<f64>> = model.cardinalities.iter().zip(clamped.iter())
+            .map(|(&card, clamp)| match *clamp {
+                Some(val) => (0..card).map(|v| if v == val as usize { 1.0 } else { 0.0 }).collect(),
+                None => vec![1.0 / card as f64; card],
+            })
+            .collect();
+
+        for _ in 0..self.max_iterations {
+            let mut max_change: f64 = 0.0;
+
+            for var in (0..q.len()).filter(|&v| clamped[v].is_none()) {
+                let mut expected = vec![0.0; model.cardinalities[var]];
+
+                for &f in model.var_factors[var].iter() {
+                    let table = &model.tables[f];
+                    let scope = &model.scopes[f];
+                    for index in 0..table.size() {
+                        let assignment = table.assignment_at(index);
+                        let mut weight = 1.0;
+                        let mut own_val = None;
+                        for (pos, &other) in scope.iter().enumerate() {
+                            if other == var {
+                                // A factor may list the variable more than once; only consistent entries count.
+                                if own_val.is_some_and(|v| v != assignment[pos]) {
+                                    weight = 0.0;
+                                }
+                                own_val = Some(assignment[pos]);
+                            } else {
+                                weight *= q[other][assignment[pos] as usize];
+                            }
+                        }
+
+                        if weight > 0.0 {
+                            expected[own_val.unwrap() as usize] += weight * table.get_log_values()[index];
+                        }
+                    }
+                }
+
+                let log_z = log_sum_exp(&expected);
+                for (val, e) in expected.iter().enumerate() {
+                    let new_prob = (e - log_z).exp();
+                    max_change = max_change.max((new_prob - q[var][val]).abs());
+                    q[var][val] = new_prob;
+                }
+            }
+
+            if max_change < self.tolerance {
+                break;
+            }
+        }
+
+        model.names.iter().cloned().zip(q).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coupling(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 3 } else { 2 }
+    }
+
+    fn field(vals: &[u32]) -> i32 {
+        vals[0] as i32 + 1
+    }
+
+    fn make_chain(couple: bool) -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+            graph.add_factor::<i32>(vec!(String::from(*name)), field);
+        }
+        if couple {
+            for &(x, y) in [("a", "b"), ("b", "c")].iter() {
+                graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), coupling);
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn independent_variables_are_exact() {
+        let graph = make_chain(false);
+        let marginals = MeanField::new(&graph).run();
+        let exact = Enumeration::new(&graph).run().unwrap();
+
+        for (var, probs) in exact.marginals.iter() {
+            for (p, q) in probs.iter().zip(marginals[var].iter()) {
+                assert!((p - q).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn weak_coupling_is_close_to_exact() {
+        let graph = make_chain(true);
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("c"), 0);
+        let marginals = MeanField::new(&graph).with_evidence(evidence.clone()).with_tolerance(1e-10).run();
+        let exact = Enumeration::new(&graph).with_evidence(evidence).run().unwrap();
+
+        assert_eq!(marginals["c"], vec!(1.0, 0.0));
+        for var in ["a", "b"].iter() {
+            assert!((marginals[*var][1] - exact.marginals[*var][1]).abs() < 0.02);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with mini-bucket elimination bounds
+
+use *;
+use elimination::min_fill_order;
+
+/// How each mini-bucket eliminates its variable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// Sum in the first mini-bucket and maximize in the rest.
+    Sum,
+    /// Power sums with uniform Hölder weights across the mini-buckets.
+    WeightedSum,
+    /// Maximize in every mini-bucket.
+    Max,
+}
+
+/// Struct computing mini-bucket elimination bounds over the discrete variables of a factor graph.
+///
+/// Each bucket is split into mini-buckets whose combined scope has at most `i_bound` variables, so a
+/// larger i-bound gives tighter bounds at the cost of larger intermediate tables.
+#[derive(Debug)]
+pub struct MiniBucket<'a> {
+    graph: &'a FactorGraph,
+    i_bound: usize,
+    order: Vec<String>,
+}
+
+impl<'a> MiniBucket<'a> {
+    /// Create a new MiniBucket for the graph, eliminating variables in min-fill order.
+    pub fn new(graph: &'a FactorGraph, i_bound: usize) -> MiniBucket<'a> {
+        if i_bound == 0 {
+            panic!("The i-bound of mini-bucket elimination must be at least 1");
+        }
+
+        let scopes: Vec<Vec<String>> = graph.get_factors().iter().map(|f| f.get_variables().clone()).collect();
+        let order = min_fill_order(&graph.get_variable_names(), &scopes);
+
+        MiniBucket {
+            graph,
+            i_bound,
+            order,
+        }
+    }
+
+    /// Eliminate variables in the specified order instead.
+    pub fn with_order(mut self, order: Vec<String>) -> MiniBucket<'a> {
+        let mut sorted = order.clone();
+        let mut expected = self.graph.get_variable_names();
+        sorted.sort();
+        expected.sort();
+        if sorted != expected {
+            panic!("Elimination order must contain every variable of the factor graph exactly once");
+        }
+
+        self.order = order;
+        self
+    }
+
+    /// Get an upper bound on the log partition function.
+    pub fn log_partition_bound(&self) -> f64 {
+        self.eliminate(Mode::Sum)
+    }
+
+    /// Get an upper bound on the log partition function using weighted mini-buckets.
+    ///
+    /// Every bucket divides its variable's elimination evenly between its mini-buckets, which usually
+    /// gives a tighter bound than summing in a single mini-bucket.
+    pub fn weighted_log_partition_bound(&self) -> f64 {
+        self.eliminate(Mode::WeightedSum)
+    }
+
+    /// Get an upper bound on the log of the largest unnormalized probability of any assignment.
+    pub fn map_bound(&self) -> f64 {
+        self.eliminate(Mode::Max)
+    }
+
+    /// Split a bucket into mini-buckets whose scopes have at most `i_bound` variables.
+    fn partition(&self, mut bucket: Vec<Table>) -> Vec<Vec<Table>> {
+        bucket.sort_by_key(|t| std::cmp::Reverse(t.get_variables().len()));
+
+        let mut mini_buckets: Vec<(Vec<String>, Vec<Table>)> = vec!();
+        for table in bucket {
+            let fits = mini_buckets.iter().position(|(scope, _)| {
+                let added = table.get_variables().iter().filter(|v| !scope.contains(v)).count();
+                scope.len() + added <= self.i_bound
+            });
+
+            match fits {
+                Some(i) => {
+                    for var in table.get_variables() {
+                        if !mini_buckets[i].0.contains(var) {
+                            mini_buckets[i].0.push(var.clone());
+                        }
+                    }
+                    mini_buckets[i].1.push(table);
+                },
+                None => mini_buckets.push((table.get_variables().clone(), vec!(table)))
+            }
+        }
+
+        mini_buckets.into_iter().map(|(_, tables)| tables).collect()
+    }
+
+    /// Run mini-bucket elimination with the given elimination mode.
+    fn eliminate(&self, mode: Mode) -> f64 {
+        let mut pool: Vec<Table> = self.graph.get_factors().iter()
+            .map(|factor| Table::from_factor(self.graph, factor))
+            .collect();
+        let mut log_bound = 0.0;
+
+        for var in self.order.iter() {
+            let (bucket, rest): (Vec<Table>, Vec<Table>) = pool.into_iter().partition(|t| t.contains(var));
+            pool = rest;
+
+            if bucket.is_empty() {
+                if mode != Mode::Max {
+                    log_bound += (self.graph.domain_size(var) as f64).ln();
+                }
+                continue;
+            }
+
+            let mini_buckets = self.partition(bucket);
+            let weight = 1.0 / mini_buckets.len() as f64;
+            for (i, tables) in mini_buckets.into_iter().enumerate() {
+                let joint = tables.iter().skip(1).fold(tables[0].clone(), |acc, t| acc.product(t));
+                let message = match mode {
+                    Mode::Sum if i == 0 => joint.sum_out(var),
+                    Mode::WeightedSum => joint.power_sum_out(var, weight),
+                    _ => joint.max_out(var),
+                };
+
+                if message.get_variables().is_empty() {
+                    log_bound += message.get_log_values()[0];
+                } else {
+                    pool.push(message);
+                }
+            }
+        }
+
+        log_bound + pool.iter().map(|t| t.get_log_values()[0]).sum::<f64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coupling(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 4 } else { 1 }
+    }
+
+    fn field(vals: &[u32]) -> i32 {
+        vals[0] as i32 + 1
+    }
+
+    fn make_loop() -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c", "d"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+
+        let edges = [("a", "b"), ("b", "c"), ("c", "d"), ("d", "a"), ("a", "c")];
+        for &(x, y) in edges.iter() {
+            graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), coupling);
+        }
+        graph.add_factor::<i32>(vec!(String::from("b")), field);
+        graph
+    }
+
+    fn exact(graph: &FactorGraph) -> (f64, f64) {
+        let result = Enumeration::new(graph).run().unwrap();
+        (result.log_partition, result.map_log_potential)
+    }
+
+    #[test]
+    fn bounds_are_exact_with_large_i_bound() {
+        let graph = make_loop();
+        let (log_z, log_map) = exact(&graph);
+        let mini_bucket = MiniBucket::new(&graph, 4);
+
+        assert!((mini_bucket.log_partition_bound() - log_z).abs() < 1e-9);
+        assert!((mini_bucket.weighted_log_partition_bound() - log_z).abs() < 1e-9);
+        assert!((mini_bucket.map_bound() - log_map).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bounds_hold_with_small_i_bound() {
+        let graph = make_loop();
+        let (log_z, log_map) = exact(&graph);
+
+        for i_bound in 1..4 {
+            let mini_bucket = MiniBucket::new(&graph, i_bound);
+            assert!(mini_bucket.log_partition_bound() >= log_z - 1e-9);
+            assert!(mini_bucket.weighted_log_partition_bound() >= log_z - 1e-9);
+            assert!(mini_bucket.map_bound() >= log_map - 1e-9);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with maximum-likelihood learning of log-linear weights from fully observed data
+
+use *;
+use model::Model;
+
+/// Struct holding the features of the log-linear factors of a graph at every entry of their tables.
+#[derive(Debug)]
+pub(crate) struct FeatureTables {
+    /// Index of each log-linear factor among the graph's factors.
+    pub factors: Vec<usize>,
+
+    /// Weight index and value of every feature at each table entry, for each log-linear factor.
+    pub entries: Vec<Vec<Vec<(usize, f64)>>>,
+}
+
+impl FeatureTables {
+    /// Tabulate the features of every log-linear factor of the graph.
+    pub fn new(graph: &FactorGraph, model: &Model) -> FeatureTables {
+        let mut tables = FeatureTables { factors: vec!(), entries: vec!() };
+        for (f, factor) in graph.get_factors().iter().enumerate() {
+            if let Potential::LogLinear(ref potential) = *factor.get_potential() {
+                let table = &model.tables[f];
+                tables.factors.push(f);
+                tables.entries.push((0..table.size()).map(|index| potential.features(&table.assignment_at(index))).collect());
+            }
+        }
+        tables
+    }
+
+    /// Add the features at a full state, times `scale`, to the statistics.
+    pub fn add_state(&self, model: &Model, state: &[u32], scale: f64, statistics: &mut [f64]) {
+        for (k, &f) in self.factors.iter().enumerate() {
+            let values: Vec<u32> = model.scopes[f].iter().map(|&var| state[var]).collect();
+            for &(weight, value) in self.entries[k][model.tables[f].index_of(&values)].iter() {
+                statistics[weight] += scale * value;
+            }
+        }
+    }
+
+    /// Add the expected features of the `k`-th log-linear factor under a distribution over its table entries,
+    /// times `scale`, to the statistics.
+    pub fn add_expected(&self, k: usize, probabilities: &[f64], scale: f64, statistics: &mut [f64]) {
+        for (entry, p) in self.entries[k].iter().zip(probabilities.iter()) {
+            for &(weight, value) in entry.iter() {
+                statistics[weight] += scale * p * value;
+            }
+        }
+    }
+}
+
+/// Convert complete assignments to states of the model, panicking if any variable is unobserved.
+pub(crate) fn complete_states(model: &Model, data: &[Assignment]) -> Vec<Vec<u32>> {
+    data.iter()
+        .map(|assignment| model.clamped(assignment).iter().zip(model.names.iter())
+            .map(|(value, name)| match *value {
+                Some(x) => x,
+                None => panic!("The variable {} is not observed in every assignment", name)
+            })
+            .collect())
+        .collect()
+}
+
+/// Find the table factors, checking that they are an acyclic network of conditional distributions of distinct
+/// variables, untouched by other factors.
+pub(crate) fn network_tables(graph: &FactorGraph) -> Vec<usize> {
+    let factors = graph.get_factors();
+    let tables: Vec<usize> = factors.iter().enumerate()
+        .filter(|&(_, f)| matches!(*f.get_potential(), Potential::Table(_)))
+        .map(|(index, _)| index)
+        .collect();
+    let scope = |f: usize| factors[f].get_variables();
+    let child = |f: usize| &scope(f)[scope(f).len() - 1];
+    let parents = |f: usize| &scope(f)[..scope(f).len() - 1];
+
+    let mut children: Vec<&String> = vec!();
+    for &f in tables.iter() {
+        if children.contains(&child(f)) {
+            panic!("The variable {} is the last variable of more than one table factor", child(f));
+        }
+        children.push(child(f));
+    }
+
+    // Walking up from the child of each table through the tables of its ancestors must never reach it again.
+    for &f in tables.iter() {
+        let mut stack: Vec<&String> = parents(f).iter().collect();
+        let mut seen: Vec<&String> = vec!();
+        while let Some(var) = stack.pop() {
+            if var == child(f) {
+                panic!("The table factors have a directed cycle through the variable {}", var);
+            }
+            if !seen.contains(&var) {
+                seen.push(var);
+                if let Some(&g) = tables.iter().find(|&&g| child(g) == var) {
+                    stack.extend(parents(g).iter());
+                }
+            }
+        }
+    }
+
+    for (_, factor) in factors.iter().enumerate().filter(|&(index, _)| !tables.contains(&index)) {
+        let table_var = factor.get_variables().iter()
+            .find(|var| tables.iter().any(|&f| scope(f).contains(var)));
+        if let Some(var) = table_var {
+            panic!("Factor {} is over the variable {} of a table factor", factor.get_name(), var);
+        }
+    }
+    tables
+}
+
+/// Add the expected features under the graph's current parameters, times `scale`, to the statistics, giving
+/// the log partition function.
+pub(crate) fn add_expected_statistics(graph: &FactorGraph, features: &FeatureTables, method: InferenceMethod,
+                                      scale: f64, statistics: &mut [f64]) -> f64 {
+    match method {
+        InferenceMethod::Exact => {
+            let tree = JunctionTree::new(graph, &Assignment::new());
+            for (k, &f) in features.factors.iter().enumerate() {
+                let belief = tree.marginal(graph.get_factors()[f].get_variables()).unwrap();
+                features.add_expected(k, &belief.probabilities(), scale, statistics);
+            }
+            tree.log_partition()
+        },
+        InferenceMethod::BeliefPropagation => {
+            let beliefs = BeliefPropagation::new(graph).run();
+            for (k, &f) in features.factors.iter().enumerate() {
+                features.add_expected(k, &beliefs.factor_beliefs[f].probabilities(), scale, statistics);
+            }
+            BetheFreeEnergy::new(graph, &beliefs).log_partition()
+        }
+    }
+}
+
+/// Minimize `log Z(w) - w . statistics - offset + l2 / 2 * |w|^2`, plus the optimizer's L1 penalty, over the
+/// parameters of the graph.
+///
+/// This is the regularized negative average log-likelihood of data whose average features are `statistics` and
+/// whose factors that are not log-linear average to `offset`. The graph is left with the last parameters tried.
+pub(crate) fn fit_statistics(graph: &mut FactorGraph, features: &FeatureTables, statistics: &[f64], offset: f64,
+                             method: InferenceMethod, l2: f64, optimizer: &Lbfgs) -> LbfgsResult {
+    let initial = graph.get_parameters();
+    optimizer.minimize(&initial, |parameters| {
+        graph.set_parameters(parameters);
+        let mut gradient: Vec<f64> = statistics.iter().zip(parameters.iter()).map(|(e, w)| l2 * w - e).collect();
+
+        let log_partition = add_expected_statistics(graph, features, method, 1.0, &mut gradient);
+        let linear: f64 = parameters.iter().zip(statistics.iter()).map(|(w, e)| w * e).sum();
+        let penalty: f64 = parameters.iter().map(|w| 0.5 * l2 * w * w).sum();
+        (log_partition - linear - offset + penalty, gradient)
+    })
+}
+
+/// Enum representing how the expected features under the model are computed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InferenceMethod {
+    /// Exact marginals and partition function from a junction tree.
+    Exact,
+
+    /// Beliefs from loopy belief propagation, with the Bethe approximation to the partition function.
+    BeliefPropagation,
+}
+
+/// Struct holding the result of fitting the weights of a graph.
+#[derive(Clone, Debug)]
+pub struct TrainingResult {
+    /// Fitted parameters, which the graph is also left with.
+    pub parameters: Vec<f64>,
+
+    /// Regularized training objective, to be minimized, at the start and after every iteration.
+    pub objectives: Vec<f64>,
+
+    /// Norm of the objective's gradient at the start and after every iteration.
+    pub gradient_norms: Vec<f64>,
+
+    /// Average log-likelihood of the data under the fitted parameters, or the estimator's surrogate for it,
+    /// without regularization.
+    pub log_likelihood: f64,
+
+    /// Number of optimizer iterations that were run.
+    pub iterations: usize,
+
+    /// Whether the optimizer converged before the iteration limit.
+    pub converged: bool,
+}
+
+impl TrainingResult {
+    /// Build the result of minimizing a regularized negative average log-likelihood.
+    pub(crate) fn from_minimization(result: LbfgsResult, l2: f64, l1: f64) -> TrainingResult {
+        let penalty: f64 = result.parameters.iter().map(|w| 0.5 * l2 * w * w + l1 * w.abs()).sum();
+        TrainingResult {
+            log_likelihood: penalty - result.objectives[result.objectives.len() - 1],
+            parameters: result.parameters,
+            objectives: result.objectives,
+            gradient_norms: result.gradient_norms,
+            iterations: result.iterations,
+            converged: result.converged,
+        }
+    }
+}
+
+/// Struct fitting the parameters of a graph's log-linear factors by maximum likelihood.
+///
+/// The objective is the average negative log-likelihood of the assignments plus `l2 / 2 * |w|^2 + l1 * |w|_1`.
+/// Its gradient is the difference between the expected and the empirical features, and it is minimized by
+/// L-BFGS, or by OWL-QN with an L1 penalty. Every other factor is held fixed. With belief propagation, both the
+/// expectations and the partition function are approximate, and exact on graphs without cycles.
+#[derive(Debug)]
+pub struct MaximumLikelihood<'a> {
+    graph: &'a mut FactorGraph,
+    data: &'a [Assignment],
+    method: InferenceMethod,
+    l2: f64,
+    l1: f64,
+    optimizer: Lbfgs,
+}
+
+impl<'a> MaximumLikelihood<'a> {
+    /// Create a new MaximumLikelihood fitting the graph to complete assignments of its variables.
+    ///
+    /// Defaults to exact inference without regularization, starting from the graph's current parameters.
+    pub fn new(graph: &'a mut FactorGraph, data: &'a [Assignment]) -> MaximumLikelihood<'a> {
+        if data.is_empty() {
+            panic!("Maximum likelihood needs at least one assignment");
+        }
+
+        MaximumLikelihood {
+            graph,
+            data,
+            method: InferenceMethod::Exact,
+            l2: 0.0,
+            l1: 0.0,
+            optimizer: Lbfgs::new(),
+        }
+    }
+
+    /// Set how the expected features are computed.
+    pub fn with_method(mut self, method: InferenceMethod) -> MaximumLikelihood<'a> {
+        self.method = method;
+        self
+    }
+
+    /// Add the penalty `l2 / 2 * |w|^2` to the objective.
+    pub fn with_l2(mut self, l2: f64) -> MaximumLikelihood<'a> {
+        if l2 < 0.0 {
+            panic!("The L2 penalty must be non-negative, got {}", l2);
+        }
+
+        self.l2 = l2;
+        self
+    }
+
+    /// Add the penalty `l1 * |w|_1` to the objective.
+    pub fn with_l1(mut self, l1: f64) -> MaximumLikelihood<'a> {
+        self.optimizer = self.optimizer.with_l1(l1);
+        self.l1 = l1;
+        self
+    }
+
+    /// Set the maximum number of optimizer iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> MaximumLikelihood<'a> {
+        self.optimizer = self.optimizer.with_max_iterations(max_iterations);
+        self
+    }
+
+    /// Set the gradient norm under which the optimizer stops.
+    pub fn with_tolerance(mut self, tolerance: f64) -> MaximumLikelihood<'a> {
+        self.optimizer = self.optimizer.with_tolerance(tolerance);
+        self
+    }
+
+    /// Fit the parameters, leaving the graph with the fitted parameters.
+    pub fn run(self) -> TrainingResult {
+        let graph = self.graph;
+        let model = Model::new(graph);
+        let features = FeatureTables::new(graph, &model);
+        let states = complete_states(&model, self.data);
+
+        let scale = 1.0 / states.len() as f64;
+        let mut empirical = vec![0.0; graph.num_parameters()];
+        for state in states.iter() {
+            features.add_state(&model, state, scale, &mut empirical);
+        }
+
+        // Every factor that is not log-linear adds a constant to the log-likelihood.
+        let fixed: f64 = states.iter()
+            .map(|state| (0..model.tables.len())
+                .filter(|f| !features.factors.contains(f))
+                .map(|f| model.factor_log_potential(f, state))
+                .sum::<f64>())
+            .sum::<f64>() * scale;
+
+        let result = fit_statistics(graph, &features, &empirical, fixed, self.method, self.l2, &self.optimizer);
+        graph.set_parameters(&result.parameters);
+        TrainingResult::from_minimization(result, self.l2, self.l1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::{make_chain, make_chain_data};
+
+    #[test]
+    fn fitted_model_matches_empirical_statistics() {
+        let mut graph = make_chain();
+        let data = make_chain_data();
+        let result = MaximumLikelihood::new(&mut graph, &data).with_tolerance(1e-8).run();
+
+        assert!(result.converged);
+        assert!(result.objectives.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(graph.get_parameters(), result.parameters);
+
+        // At the maximum, the marginals of the fields match the frequencies in the data.
+        let exact = Enumeration::new(&graph).run().unwrap();
+        for (name, count) in [("a", 5.0), ("b", 5.0), ("c", 5.0)].iter() {
+            assert!((exact.marginals[*name][1] - count / 8.0).abs() < 1e-6);
+        }
+
+        let log_potential: f64 = data.iter()
+            .map(|assignment| graph.get_factors().iter()
+                .map(|f| f.log_potential(&f.get_variables().iter().map(|v| assignment[v]).collect::<Vec<u32>>()))
+                .sum::<f64>())
+            .sum();
+        assert!((result.log_likelihood - (log_potential / 8.0 - exact.log_partition)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn regularization_shrinks_weights() {
+        let data = make_chain_data();
+        let mut exact_graph = make_chain();
+        let exact = MaximumLikelihood::new(&mut exact_graph, &data).run();
+
+        let mut bp_graph = make_chain();
+        let bp = MaximumLikelihood::new(&mut bp_graph, &data).with_method(InferenceMethod::BeliefPropagation).run();
+        for (w, v) in exact.parameters.iter().zip(bp.parameters.iter()) {
+            assert!((w - v).abs() < 1e-4);
+        }
+
+        let mut l2_graph = make_chain();
+        let l2 = MaximumLikelihood::new(&mut l2_graph, &data).with_l2(1.0).run();
+        let norm = |w: &[f64]| w.iter().map(|x| x * x).sum::<f64>();
+        assert!(norm(&l2.parameters) < norm(&exact.parameters));
+
+        let mut l1_graph = make_chain();
+        let l1 = MaximumLikelihood::new(&mut l1_graph, &data).with_l1(2.0).run();
+        assert!(l1.parameters.iter().all(|&w| w == 0.0));
+    }
+}
@@ -0,0 +1,112 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Index-based view of a discrete factor graph used by the inference algorithms
+
+use *;
+
+/// Struct holding the tabulated factors of a graph with variables referred to by position.
+#[derive(Debug)]
+pub struct Model {
+    /// Variable names, in the order they were added to the graph.
+    pub names: Vec<String>,
+
+    /// Domain size of each variable.
+    pub cardinalities: Vec<usize>,
+
+    /// Tabulated log-potential of each factor.
+    pub tables: Vec<Table>,
+
+    /// Positions of the variables of each factor.
+    pub scopes: Vec<Vec<usize>>,
+
+    /// Indices of the factors touching each variable.
+    pub var_factors: Vec<Vec<usize>>,
+}
+
+impl Model {
+    /// Build the Model for a factor graph.
+    pub fn new(graph: &FactorGraph) -> Model {
+        let names = graph.get_variable_names();
+        let cardinalities: Vec<usize> = names.iter().map(|name| graph.domain_size(name)).collect();
+        let tables: Vec<Table> = graph.get_factors().iter()
+            .map(|factor| Table::from_factor(graph, factor))
+            .collect();
+
+        let mut var_factors = vec![vec!(); names.len()];
+        let mut scopes = Vec::with_capacity(tables.len());
+        for (f, table) in tables.iter().enumerate() {
+            let scope: Vec<usize> = table.get_variables().iter().map(|var| Model::position_in(&names, var)).collect();
+            for &var in scope.iter() {
+                var_factors[var].push(f);
+            }
+            scopes.push(scope);
+        }
+
+        Model {
+            names,
+            cardinalities,
+            tables,
+            scopes,
+            var_factors,
+        }
+    }
+
+    fn position_in(names: &[String], name: &str) -> usize {
+        match names.iter().position(|n| n == name) {
+            Some(x) => x,
+            None => panic!("The variable {} was not found in the factor graph.", name)
+        }
+    }
+
+    /// Get the position of a variable by name.
+    pub fn position(&self, name: &str) -> usize {
+        Model::position_in(&self.names, name)
+    }
+
+    /// Get the log-potential of factor `f` for a full state.
+    pub fn factor_log_potential(&self, f: usize, state: &[u32]) -> f64 {
+        let values: Vec<u32> = self.scopes[f].iter().map(|&var| state[var]).collect();
+        self.tables[f].log_value(&values)
+    }
+
+    /// Get the unnormalized log-probability of a full state.
+    pub fn log_potential(&self, state: &[u32]) -> f64 {
+        (0..self.tables.len()).map(|f| self.factor_log_potential(f, state)).sum()
+    }
+
+    /// Get the unnormalized log-probability of each value of `var` with the rest of `state` fixed.
+    pub fn conditional(&self, state: &mut [u32], var: usize) -> Vec<f64> {
+        let current = state[var];
+        let log_probs = (0..self.cardinalities[var] as u32)
+            .map(|val| {
+                state[var] = val;
+                self.var_factors[var].iter().map(|&f| self.factor_log_potential(f, state)).sum()
+            })
+            .collect();
+        state[var] = current;
+        log_probs
+    }
+
+    /// Turn evidence into a per-variable list of clamped values.
+    pub fn clamped(&self, evidence: &Assignment) -> Vec<Option<u32>> {
+        let mut clamped = vec![None; self.names.len()];
+        for (name, &val) in evidence.iter() {
+            let var = self.position(name);
+            if val as usize >= self.cardinalities[var] {
+                panic!("Evidence value {} is out of range for variable {}", val, name);
+            }
+            clamped[var] = Some(val);
+        }
+        clamped
+    }
+
+    /// Convert a full state into an Assignment.
+    pub fn to_assignment(&self, state: &[u32]) -> Assignment {
+        self.names.iter().cloned().zip(state.iter().cloned()).collect()
+    }
+}
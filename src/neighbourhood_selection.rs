@@ -0,0 +1,252 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with learning the structure of binary pairwise models by L1-regularized neighbourhood selection
+
+use *;
+
+/// Enum representing how the neighbourhoods estimated for each variable are combined into edges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetrization {
+    /// Keep an edge when both of its variables select each other.
+    And,
+
+    /// Keep an edge when either of its variables selects the other.
+    Or,
+}
+
+/// Struct learning an Ising model over binary variables by L1-regularized neighbourhood selection.
+///
+/// Writing each variable as a spin `s = 2x - 1`, the model is `p(s) ~ exp(sum_i h_i s_i + sum_ij J_ij s_i s_j)`,
+/// so the conditional of each spin given the rest is a logistic regression on the other spins with intercept
+/// `2 h_i` and coefficients `2 J_ij`. Fitting every regression with an L1 penalty on the coefficients selects
+/// the neighbourhood of each variable, and the neighbourhoods are made symmetric with the AND or OR rule. With
+/// enough data and a suitable penalty, this recovers the true edges with high probability (Ravikumar, Wainwright
+/// and Lafferty, 2010).
+#[derive(Debug)]
+pub struct NeighbourhoodSelection<'a> {
+    data: &'a [Assignment],
+    variables: Vec<String>,
+    l1: f64,
+    symmetrization: Symmetrization,
+    optimizer: Lbfgs,
+}
+
+impl<'a> NeighbourhoodSelection<'a> {
+    /// Create a new NeighbourhoodSelection for complete assignments of the same binary variables.
+    ///
+    /// The variables are sorted by name. Defaults to an L1 penalty of 0.05 and the AND rule.
+    pub fn new(data: &'a [Assignment]) -> NeighbourhoodSelection<'a> {
+        if data.is_empty() {
+            panic!("Structure learning needs at least one assignment");
+        }
+
+        let mut variables: Vec<String> = data[0].keys().cloned().collect();
+        variables.sort();
+        for assignment in data.iter() {
+            if assignment.len() != variables.len() {
+                panic!("Every assignment needs to observe the same variables");
+            }
+            for var in variables.iter() {
+                match assignment.get(var) {
+                    Some(&x) if x < 2 => (),
+                    Some(&x) => panic!("Value {} of variable {} is not binary", x, var),
+                    None => panic!("The variable {} is not observed in every assignment", var)
+                }
+            }
+        }
+
+        NeighbourhoodSelection {
+            data,
+            variables,
+            l1: 0.05,
+            symmetrization: Symmetrization::And,
+            optimizer: Lbfgs::new().with_unpenalized(1),
+        }
+    }
+
+    /// Set the L1 penalty on the coefficients of every regression.
+    pub fn with_l1(mut self, l1: f64) -> NeighbourhoodSelection<'a> {
+        if l1 < 0.0 {
+            panic!("The L1 penalty must be non-negative, got {}", l1);
+        }
+
+        self.l1 = l1;
+        self
+    }
+
+    /// Set how the neighbourhoods are combined into edges.
+    pub fn with_symmetrization(mut self, symmetrization: Symmetrization) -> NeighbourhoodSelection<'a> {
+        self.symmetrization = symmetrization;
+        self
+    }
+
+    /// Set the maximum number of optimizer iterations for every regression.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> NeighbourhoodSelection<'a> {
+        self.optimizer = self.optimizer.with_max_iterations(max_iterations);
+        self
+    }
+
+    /// Set the gradient norm under which the optimizer stops.
+    pub fn with_tolerance(mut self, tolerance: f64) -> NeighbourhoodSelection<'a> {
+        self.optimizer = self.optimizer.with_tolerance(tolerance);
+        self
+    }
+
+    /// Get the variables, in the order used by `regressions`.
+    pub fn get_variables(&self) -> &Vec<String> {
+        &self.variables
+    }
+
+    /// Fit the regression of every variable on the others.
+    ///
+    /// Row `i` holds the intercept of the regression of variable `i` at position `i` and its coefficient on
+    /// variable `j` at every other position `j`.
+    pub fn regressions(&self) -> Vec<Vec<f64>> {
+        let spins: Vec<Vec<f64>> = self.data.iter()
+            .map(|assignment| self.variables.iter().map(|var| 2.0 * assignment[var] as f64 - 1.0).collect())
+            .collect();
+        let n = self.variables.len();
+        let scale = 1.0 / spins.len() as f64;
+        let optimizer = self.optimizer.with_l1(self.l1);
+
+        (0..n)
+            .map(|target| {
+                // The intercept comes first so the optimizer leaves it unpenalized.
+                let others: Vec<usize> = (0..n).filter(|&j| j != target).collect();
+                let result = optimizer.minimize(&vec![0.0; n], |parameters| {
+                    let mut value = 0.0;
+                    let mut gradient = vec![0.0; n];
+                    for row in spins.iter() {
+                        let margin = row[target] * others.iter().zip(parameters[1..].iter())
+                            .fold(parameters[0], |acc, (&j, w)| acc + w * row[j]);
+
+                        // log(1 + exp(-m)) and its derivative -1 / (1 + exp(m)), computed stably.
+                        value += scale * if margin > 0.0 { (-margin).exp().ln_1p() } else { margin.exp().ln_1p() - margin };
+                        let slope = -scale * row[target] / (1.0 + margin.exp());
+                        gradient[0] += slope;
+                        for (g, &j) in gradient[1..].iter_mut().zip(others.iter()) {
+                            *g += slope * row[j];
+                        }
+                    }
+                    (value, gradient)
+                });
+
+                let mut coefficients = vec![0.0; n];
+                coefficients[target] = result.parameters[0];
+                for (&j, &w) in others.iter().zip(result.parameters[1..].iter()) {
+                    coefficients[j] = w;
+                }
+                coefficients
+            })
+            .collect()
+    }
+
+    /// Get the selected edges, with each pair of names in sorted order.
+    pub fn edges(&self) -> Vec<(String, String)> {
+        self.select(&self.regressions()).into_iter()
+            .map(|(i, j)| (self.variables[i].clone(), self.variables[j].clone()))
+            .collect()
+    }
+
+    /// Combine the neighbourhoods into pairs of positions `i < j`.
+    fn select(&self, regressions: &[Vec<f64>]) -> Vec<(usize, usize)> {
+        let n = self.variables.len();
+        (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .filter(|&(i, j)| match self.symmetrization {
+                Symmetrization::And => regressions[i][j] != 0.0 && regressions[j][i] != 0.0,
+                Symmetrization::Or => regressions[i][j] != 0.0 || regressions[j][i] != 0.0
+            })
+            .collect()
+    }
+
+    /// Learn the model, with a log-linear field on every variable and a log-linear coupling on every edge.
+    ///
+    /// Each field is half the intercept of its variable's regression, and each coupling is half the average of
+    /// the non-zero coefficients between its variables. The graph's parameters are the fields, in the order of
+    /// the variables, followed by the couplings of the edges in the order `edges` gives them.
+    pub fn run(&self) -> FactorGraph {
+        let regressions = self.regressions();
+        let spin = |x: u32| 2.0 * x as f64 - 1.0;
+
+        let mut graph = FactorGraph::new();
+        for (i, var) in self.variables.iter().enumerate() {
+            graph.add_discrete_var(var, vec![0, 1]);
+            let field = graph.add_parameter(0.5 * regressions[i][i]);
+            graph.add_log_linear_factor(vec!(var.clone()), LogLinearPotential::new().with_feature(field, move |v| spin(v[0])));
+        }
+
+        for (i, j) in self.select(&regressions) {
+            let estimates: Vec<f64> = [regressions[i][j], regressions[j][i]].iter().cloned().filter(|&w| w != 0.0).collect();
+            let coupling = graph.add_parameter(0.5 * estimates.iter().sum::<f64>() / estimates.len() as f64);
+            graph.add_log_linear_factor(vec!(self.variables[i].clone(), self.variables[j].clone()),
+                                        LogLinearPotential::new().with_feature(coupling, move |v| spin(v[0]) * spin(v[1])));
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::sample_exact;
+
+    /// Build the Ising chain a - b - c - d - e with the given coupling and a field on a.
+    fn make_ising_chain(coupling: f64) -> FactorGraph {
+        let names = ["a", "b", "c", "d", "e"];
+        let mut graph = FactorGraph::new();
+        for name in names.iter() {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+        let field = graph.add_parameter(0.3);
+        let weight = graph.add_parameter(coupling);
+        graph.add_log_linear_factor(vec!(String::from("a")),
+                                    LogLinearPotential::new().with_feature(field, |v| 2.0 * v[0] as f64 - 1.0));
+        for pair in names.windows(2) {
+            graph.add_log_linear_factor(vec!(String::from(pair[0]), String::from(pair[1])), LogLinearPotential::new()
+                .with_feature(weight, |v| if v[0] == v[1] { 1.0 } else { -1.0 }));
+        }
+        graph
+    }
+
+    fn pairs(edges: &[(&str, &str)]) -> Vec<(String, String)> {
+        edges.iter().map(|&(a, b)| (String::from(a), String::from(b))).collect()
+    }
+
+    #[test]
+    fn recovers_the_chain() {
+        let data = sample_exact(&make_ising_chain(0.6), 3000, 13);
+        let chain = pairs(&[("a", "b"), ("b", "c"), ("c", "d"), ("d", "e")]);
+        assert_eq!(NeighbourhoodSelection::new(&data).edges(), chain);
+
+        let selection = NeighbourhoodSelection::new(&data).with_l1(0.005).with_symmetrization(Symmetrization::Or);
+        let or_edges = selection.edges();
+        let and_edges = NeighbourhoodSelection::new(&data).with_l1(0.005).edges();
+        assert!(and_edges.iter().all(|edge| or_edges.contains(edge)));
+        assert!(or_edges.len() > chain.len());
+
+        assert!(NeighbourhoodSelection::new(&data).with_l1(10.0).edges().is_empty());
+    }
+
+    #[test]
+    fn fitted_model_has_the_true_parameters() {
+        let data = sample_exact(&make_ising_chain(0.6), 3000, 13);
+        let graph = NeighbourhoodSelection::new(&data).with_l1(0.02).run();
+        let parameters = graph.get_parameters();
+
+        assert_eq!(parameters.len(), 5 + 4);
+        assert!((parameters[0] - 0.3).abs() < 0.1);
+        for &field in parameters[1..5].iter() {
+            assert!(field.abs() < 0.1);
+        }
+        // The penalty shrinks the couplings towards zero.
+        for &coupling in parameters[5..].iter() {
+            assert!(coupling < 0.6 && coupling > 0.45);
+        }
+    }
+}
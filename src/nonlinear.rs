@@ -0,0 +1,359 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with least-squares factors given by residual functions of continuous variables
+
+use std::rc::Rc;
+
+use *;
+
+/// Step used for central differences when a residual function does not provide its Jacobians.
+const DIFFERENCE_STEP: f64 = 1e-6;
+
+/// Trait representing a vector-valued residual of some continuous variables, to be driven towards zero.
+pub trait ResidualFunction : std::fmt::Debug {
+    /// Evaluate the residual at the given values of the factor's variables, in the factor's order.
+    fn residual(&self, values: &[&[f64]]) -> Vec<f64>;
+
+    /// Evaluate the Jacobian of the residual with respect to each of the factor's variables.
+    ///
+    /// Defaults to central differences of `residual`.
+    fn jacobians(&self, values: &[&[f64]]) -> Vec<Matrix> {
+        numerical_jacobians(self, values)
+    }
+}
+
+/// Approximate the Jacobians of a residual function by central differences.
+pub fn numerical_jacobians<F: ResidualFunction + ?Sized>(function: &F, values: &[&[f64]]) -> Vec<Matrix> {
+    let mut shifted: Vec<Vec<f64>> = values.iter().map(|v| v.to_vec()).collect();
+    let rows = function.residual(values).len();
+
+    let mut jacobians = vec!();
+    for var in 0..values.len() {
+        let mut jacobian = Matrix::zeros(rows, values[var].len());
+        for component in 0..values[var].len() {
+            let original = shifted[var][component];
+
+            shifted[var][component] = original + DIFFERENCE_STEP;
+            let plus = function.residual(&shifted.iter().map(|v| v.as_slice()).collect::<Vec<&[f64]>>());
+            shifted[var][component] = original - DIFFERENCE_STEP;
+            let minus = function.residual(&shifted.iter().map(|v| v.as_slice()).collect::<Vec<&[f64]>>());
+            shifted[var][component] = original;
+
+            for (row, (p, m)) in plus.iter().zip(minus.iter()).enumerate() {
+                jacobian[(row, component)] = (p - m) / (2.0 * DIFFERENCE_STEP);
+            }
+        }
+        jacobians.push(jacobian);
+    }
+    jacobians
+}
+
+/// Enum representing robust loss kernels, which grow more slowly than the squared error for large residuals.
+///
+/// Each kernel is applied to the norm `e` of the whitened residual, with its parameter setting the scale
+/// beyond which residuals are treated as outliers. All of them match `e^2 / 2` for small `e`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RobustKernel {
+    /// Quadratic up to `k` and linear beyond it.
+    Huber(f64),
+
+    /// Logarithmic loss `c^2 / 2 ln(1 + (e / c)^2)`.
+    Cauchy(f64),
+
+    /// Tukey's biweight, which is constant beyond `c` so that larger residuals are ignored entirely.
+    Tukey(f64),
+
+    /// Geman-McClure loss `c^2 e^2 / (2 (c^2 + e^2))`, which saturates at `c^2 / 2`.
+    GemanMcClure(f64),
+}
+
+impl RobustKernel {
+    /// Get the scale parameter of the kernel.
+    pub fn get_parameter(&self) -> f64 {
+        match *self {
+            RobustKernel::Huber(k) => k,
+            RobustKernel::Cauchy(c) | RobustKernel::Tukey(c) | RobustKernel::GemanMcClure(c) => c,
+        }
+    }
+
+    /// Evaluate the loss of a residual with norm `e`.
+    pub fn loss(&self, e: f64) -> f64 {
+        let e = e.abs();
+        match *self {
+            RobustKernel::Huber(k) => if e <= k { 0.5 * e * e } else { k * (e - 0.5 * k) },
+            RobustKernel::Cauchy(c) => 0.5 * c * c * (1.0 + (e / c).powi(2)).ln(),
+            RobustKernel::Tukey(c) => {
+                if e <= c {
+                    c * c / 6.0 * (1.0 - (1.0 - (e / c).powi(2)).powi(3))
+                } else {
+                    c * c / 6.0
+                }
+            },
+            RobustKernel::GemanMcClure(c) => 0.5 * c * c * e * e / (c * c + e * e),
+        }
+    }
+
+    /// Get the weight `loss'(e) / e` that iteratively reweighted least squares gives a residual with norm `e`.
+    pub fn weight(&self, e: f64) -> f64 {
+        let e = e.abs();
+        match *self {
+            RobustKernel::Huber(k) => if e <= k { 1.0 } else { k / e },
+            RobustKernel::Cauchy(c) => 1.0 / (1.0 + (e / c).powi(2)),
+            RobustKernel::Tukey(c) => if e <= c { (1.0 - (e / c).powi(2)).powi(2) } else { 0.0 },
+            RobustKernel::GemanMcClure(c) => (c * c / (c * c + e * e)).powi(2),
+        }
+    }
+}
+
+/// Residual `psi(s) r(x)` of a switchable constraint, where the switch `s` is the factor's last variable.
+///
+/// `psi` clamps the switch to `[0, 1]`, so driving the switch to zero turns the constraint off.
+#[derive(Debug)]
+struct SwitchableResidual {
+    function: Rc<dyn ResidualFunction>,
+}
+
+impl SwitchableResidual {
+    fn split<'v, 'w>(values: &'v [&'w [f64]]) -> (&'v [&'w [f64]], f64) {
+        let (switch, rest) = values.split_last().expect("A switchable constraint needs a switch variable");
+        (rest, switch[0])
+    }
+}
+
+impl ResidualFunction for SwitchableResidual {
+    fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+        let (values, switch) = SwitchableResidual::split(values);
+        let psi = switch.clamp(0.0, 1.0);
+        self.function.residual(values).iter().map(|r| psi * r).collect()
+    }
+
+    fn jacobians(&self, values: &[&[f64]]) -> Vec<Matrix> {
+        let (values, switch) = SwitchableResidual::split(values);
+        let psi = switch.clamp(0.0, 1.0);
+        let slope = if (0.0..=1.0).contains(&switch) { 1.0 } else { 0.0 };
+
+        let residual = self.function.residual(values);
+        let mut jacobians: Vec<Matrix> = self.function.jacobians(values).iter().map(|j| j.scaled(psi)).collect();
+        jacobians.push(Matrix::new(residual.len(), 1, residual.iter().map(|r| slope * r).collect()));
+        jacobians
+    }
+}
+
+/// Residual `s - 1` pulling a switch variable towards leaving its constraint on.
+#[derive(Debug)]
+struct SwitchPrior;
+
+impl ResidualFunction for SwitchPrior {
+    fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+        vec!(values[0][0] - 1.0)
+    }
+
+    fn jacobians(&self, _values: &[&[f64]]) -> Vec<Matrix> {
+        vec!(Matrix::identity(1))
+    }
+}
+
+/// Struct representing the potential `exp(-rho(|r(x)|))` of a residual function `r`.
+///
+/// The residual is whitened by the standard deviation of each component, and `rho` is either a robust
+/// kernel or the squared error `e^2 / 2`.
+#[derive(Clone, Debug)]
+pub struct NonlinearPotential {
+    function: Rc<dyn ResidualFunction>,
+    sigmas: Option<Vec<f64>>,
+    kernel: Option<RobustKernel>,
+}
+
+impl NonlinearPotential {
+    /// Create a new NonlinearPotential from a residual function, with unit noise and no robust kernel.
+    pub fn new<F: ResidualFunction + 'static>(function: F) -> NonlinearPotential {
+        NonlinearPotential {
+            function: Rc::new(function),
+            sigmas: None,
+            kernel: None,
+        }
+    }
+
+    /// Create the prior that pulls the switch of a switchable constraint towards one.
+    pub fn switch_prior(sigma: f64) -> NonlinearPotential {
+        NonlinearPotential::new(SwitchPrior).with_sigmas(vec!(sigma))
+    }
+
+    /// Set the standard deviation of each component of the residual.
+    pub fn with_sigmas(mut self, sigmas: Vec<f64>) -> NonlinearPotential {
+        if let Some(sigma) = sigmas.iter().find(|&&sigma| sigma <= 0.0) {
+            panic!("Noise standard deviations must be positive, got {}", sigma);
+        }
+
+        self.sigmas = Some(sigmas);
+        self
+    }
+
+    /// Replace the squared error with a robust kernel, reweighting the residual at every linearization.
+    pub fn with_kernel(mut self, kernel: RobustKernel) -> NonlinearPotential {
+        if kernel.get_parameter() <= 0.0 {
+            panic!("Robust kernel parameter must be positive, got {:?}", kernel);
+        }
+
+        self.kernel = Some(kernel);
+        self
+    }
+
+    /// Turn this into the potential of a switchable constraint, which takes a scalar switch variable
+    /// after the original variables.
+    ///
+    /// Together with `switch_prior` on the switch, the optimizer can switch off constraints that disagree
+    /// with the rest of the graph instead of being dragged away by them.
+    pub fn switchable(self) -> NonlinearPotential {
+        NonlinearPotential {
+            function: Rc::new(SwitchableResidual { function: self.function }),
+            ..self
+        }
+    }
+
+    /// Function to get the residual function.
+    pub fn get_function(&self) -> &dyn ResidualFunction {
+        &*self.function
+    }
+
+    /// Function to get the robust kernel, if there is one.
+    pub fn get_kernel(&self) -> Option<RobustKernel> {
+        self.kernel
+    }
+
+    /// Evaluate the residual divided by the standard deviation of each component.
+    pub fn whitened_residual(&self, values: &[&[f64]]) -> Vec<f64> {
+        let mut residual = self.function.residual(values);
+        if let Some(ref sigmas) = self.sigmas {
+            if sigmas.len() != residual.len() {
+                panic!("Residual of length {} cannot have {} standard deviations", residual.len(), sigmas.len());
+            }
+            for (r, sigma) in residual.iter_mut().zip(sigmas.iter()) {
+                *r /= sigma;
+            }
+        }
+        residual
+    }
+
+    /// Evaluate the error `rho(|r(x)|)` at the given values of the factor's variables.
+    pub fn error(&self, values: &[&[f64]]) -> f64 {
+        let norm = self.whitened_residual(values).iter().map(|r| r * r).sum::<f64>().sqrt();
+        match self.kernel {
+            Some(kernel) => kernel.loss(norm),
+            None => 0.5 * norm * norm,
+        }
+    }
+
+    /// Linearize the residual at the given values, as a Gaussian potential over the update to each variable.
+    ///
+    /// With whitened Jacobian `J`, whitened residual `r` and robust weight `w`, minimizing
+    /// `w |r + J dx|^2 / 2` gives precision `w J^T J` and information `-w J^T r`.
+    pub fn linearize(&self, values: &[&[f64]]) -> GaussianPotential {
+        let residual = self.whitened_residual(values);
+        let jacobians = self.function.jacobians(values);
+
+        let cols = jacobians.iter().map(|j| j.get_cols()).sum();
+        let mut jacobian = Matrix::zeros(residual.len(), cols);
+        let mut offset = 0;
+        for (block, &value) in jacobians.iter().zip(values.iter()) {
+            if block.get_rows() != residual.len() || block.get_cols() != value.len() {
+                panic!("Jacobian of a residual of length {} with respect to a variable with {} components \
+                        cannot be {}x{}", residual.len(), value.len(), block.get_rows(), block.get_cols());
+            }
+            jacobian.add_block(0, offset, block);
+            offset += block.get_cols();
+        }
+        if let Some(ref sigmas) = self.sigmas {
+            for (row, sigma) in sigmas.iter().enumerate() {
+                for col in 0..cols {
+                    jacobian[(row, col)] /= sigma;
+                }
+            }
+        }
+
+        let weight = match self.kernel {
+            Some(kernel) => kernel.weight(residual.iter().map(|r| r * r).sum::<f64>().sqrt()),
+            None => 1.0,
+        };
+        let jt = jacobian.transpose().scaled(weight);
+        GaussianPotential::new(&jt * &jacobian, jt.mul_vec(&residual).iter().map(|v| -v).collect())
+    }
+}
+
+/// Get the nonlinear potential of a factor, panicking if it holds any other kind.
+pub(crate) fn nonlinear_potential(factor: &Factor) -> &NonlinearPotential {
+    match *factor.get_potential() {
+        Potential::Nonlinear(ref potential) => potential,
+        _ => panic!("Factor {} does not have a nonlinear potential", factor.get_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Residual of the distance between a 2D point and a fixed beacon, against a measured range.
+    #[derive(Debug)]
+    struct Range {
+        beacon: (f64, f64),
+        measured: f64,
+    }
+
+    impl ResidualFunction for Range {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            let (dx, dy) = (values[0][0] - self.beacon.0, values[0][1] - self.beacon.1);
+            vec!((dx * dx + dy * dy).sqrt() - self.measured)
+        }
+
+        fn jacobians(&self, values: &[&[f64]]) -> Vec<Matrix> {
+            let (dx, dy) = (values[0][0] - self.beacon.0, values[0][1] - self.beacon.1);
+            let distance = (dx * dx + dy * dy).sqrt();
+            vec!(Matrix::new(1, 2, vec!(dx / distance, dy / distance)))
+        }
+    }
+
+    #[test]
+    fn numerical_jacobians_match_analytic() {
+        let range = Range { beacon: (1.0, -2.0), measured: 3.0 };
+        let point = [4.0, 2.0];
+        let analytic = range.jacobians(&[&point]);
+        let numerical = numerical_jacobians(&range, &[&point]);
+
+        for (a, n) in analytic[0].get_data().iter().zip(numerical[0].get_data().iter()) {
+            assert!((a - n).abs() < 1e-8);
+        }
+
+        let potential = NonlinearPotential::new(range);
+        assert!((potential.error(&[&point]) - 2.0).abs() < 1e-12);
+
+        let linear = potential.linearize(&[&point]);
+        for (x, e) in linear.get_information().iter().zip([-1.2, -1.6].iter()) {
+            assert!((x - e).abs() < 1e-12);
+        }
+
+        // Halving the noise doubles the whitened residual.
+        let noisy = potential.with_sigmas(vec!(0.5));
+        assert!((noisy.error(&[&point]) - 8.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn kernel_weights_are_loss_derivatives() {
+        let kernels = [RobustKernel::Huber(1.0), RobustKernel::Cauchy(1.5), RobustKernel::Tukey(2.0),
+                       RobustKernel::GemanMcClure(0.8)];
+        for kernel in kernels.iter() {
+            assert!((kernel.loss(1e-3) - 0.5e-6).abs() < 1e-10);
+            for &e in [0.3, 0.9, 1.7, 4.0].iter() {
+                let derivative = (kernel.loss(e + 1e-6) - kernel.loss(e - 1e-6)) / 2e-6;
+                assert!((kernel.weight(e) * e - derivative).abs() < 1e-6);
+            }
+        }
+
+        assert!(RobustKernel::Huber(1.0).loss(10.0) < 10.0);
+        assert_eq!(RobustKernel::Tukey(2.0).weight(3.0), 0.0);
+    }
+}
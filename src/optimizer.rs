@@ -0,0 +1,418 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with Gauss-Newton and Levenberg-Marquardt optimization of nonlinear least-squares factor graphs
+
+use *;
+use elimination::min_fill_order;
+use nonlinear::nonlinear_potential;
+
+/// Largest Levenberg-Marquardt damping tried before giving up on decreasing the error.
+const MAX_LAMBDA: f64 = 1e10;
+
+/// Look up the values of a factor's variables, in the factor's order.
+fn factor_values<'v>(graph: &FactorGraph, factor: &Factor, values: &'v Values) -> Vec<&'v [f64]> {
+    factor.get_variables().iter()
+        .map(|var| match values.get(var) {
+            Some(value) if value.len() == graph.dimension(var) => value.as_slice(),
+            Some(value) => panic!("Variable {} has {} components, got a value with {}",
+                                  var, graph.dimension(var), value.len()),
+            None => panic!("No value for the variable {}", var)
+        })
+        .collect()
+}
+
+/// Enum representing the ways the optimizer can choose its steps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptimizationMethod {
+    /// Take the full step that minimizes the linearized problem.
+    GaussNewton,
+
+    /// Damp the linearized problem towards small steps, adapting the damping so each step lowers the error.
+    LevenbergMarquardt,
+}
+
+/// Struct holding the result of optimizing a nonlinear factor graph.
+#[derive(Clone, Debug)]
+pub struct OptimizationResult {
+    /// Optimized value of every variable.
+    pub values: Values,
+
+    /// Total error of the factors at the optimized values.
+    pub error: f64,
+
+    /// Total error at the initial values and after every accepted step.
+    pub errors: Vec<f64>,
+
+    /// Number of linearizations that were made.
+    pub iterations: usize,
+
+    /// Whether the error stopped decreasing before the iteration limit.
+    pub converged: bool,
+}
+
+/// Struct minimizing the total error of the nonlinear factors of a graph by repeated linearization.
+///
+/// Each iteration linearizes every factor at the current values, eliminates the resulting Gaussian factors
+/// in a min-fill order to solve the sparse normal equations, and updates the values by the solution.
+#[derive(Debug)]
+pub struct NonlinearOptimizer<'a> {
+    graph: &'a FactorGraph,
+    initial: Values,
+    method: OptimizationMethod,
+    max_iterations: usize,
+    relative_tolerance: f64,
+    absolute_tolerance: f64,
+    initial_lambda: f64,
+}
+
+impl<'a> NonlinearOptimizer<'a> {
+    /// Create a new NonlinearOptimizer for the graph starting from the given values, using Levenberg-Marquardt.
+    pub fn new(graph: &'a FactorGraph, initial: Values) -> NonlinearOptimizer<'a> {
+        NonlinearOptimizer {
+            graph,
+            initial,
+            method: OptimizationMethod::LevenbergMarquardt,
+            max_iterations: 100,
+            relative_tolerance: 1e-10,
+            absolute_tolerance: 1e-12,
+            initial_lambda: 1e-3,
+        }
+    }
+
+    /// Set the method used to choose steps.
+    pub fn with_method(mut self, method: OptimizationMethod) -> NonlinearOptimizer<'a> {
+        self.method = method;
+        self
+    }
+
+    /// Set the maximum number of linearizations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> NonlinearOptimizer<'a> {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Stop once a step lowers the error by less than `relative` times the error, or the error is below `absolute`.
+    pub fn with_tolerances(mut self, relative: f64, absolute: f64) -> NonlinearOptimizer<'a> {
+        self.relative_tolerance = relative;
+        self.absolute_tolerance = absolute;
+        self
+    }
+
+    /// Set the Levenberg-Marquardt damping used for the first step.
+    pub fn with_initial_lambda(mut self, lambda: f64) -> NonlinearOptimizer<'a> {
+        if lambda <= 0.0 {
+            panic!("Initial damping must be positive, got {}", lambda);
+        }
+
+        self.initial_lambda = lambda;
+        self
+    }
+
+    /// Evaluate the total error of the nonlinear factors at the given values.
+    pub fn error(&self, values: &Values) -> f64 {
+        self.graph.get_factors().iter()
+            .map(|factor| nonlinear_potential(factor).error(&factor_values(self.graph, factor, values)))
+            .sum()
+    }
+
+    /// Linearize every nonlinear factor at the given values into a Gaussian factor over the updates.
+    pub fn linearize(&self, values: &Values) -> Vec<GaussianFactor> {
+        self.graph.get_factors().iter()
+            .map(|factor| {
+                let potential = nonlinear_potential(factor).linearize(&factor_values(self.graph, factor, values));
+                let dimensions = factor.get_variables().iter().map(|var| self.graph.dimension(var)).collect();
+                GaussianFactor::new(factor.get_variables().clone(), dimensions, potential)
+            })
+            .collect()
+    }
+
+    /// Linearize at the given values and eliminate into the square-root information factor of the updates.
+    ///
+    /// At the optimized values, this gives the Gaussian approximation used by `CovarianceRecovery`.
+    pub fn eliminate(&self, values: &Values) -> Result<GaussianBayesNet, IndeterminateSystem> {
+        GaussianBayesNet::eliminate(self.linearize(values), &self.ordering())
+    }
+
+    /// Get a min-fill elimination order of the variables that have factors.
+    fn ordering(&self) -> Vec<String> {
+        let variables: Vec<String> = self.graph.get_variable_names().into_iter()
+            .filter(|var| self.graph.get_factors().iter().any(|f| f.get_variables().contains(var)))
+            .collect();
+        let scopes: Vec<Vec<String>> = self.graph.get_factors().iter().map(|f| f.get_variables().clone()).collect();
+        min_fill_order(&variables, &scopes)
+    }
+
+    /// Run the optimization from the initial values.
+    ///
+    /// Returns an error if Gauss-Newton meets a linearized problem that does not determine every update.
+    pub fn optimize(&self) -> Result<OptimizationResult, IndeterminateSystem> {
+        let order = self.ordering();
+
+        let mut values = self.initial.clone();
+        let mut error = self.error(&values);
+        let mut errors = vec!(error);
+        let mut lambda = self.initial_lambda;
+        let mut iterations = 0;
+        let mut converged = error <= self.absolute_tolerance;
+
+        while iterations < self.max_iterations && !converged {
+            iterations += 1;
+            let linear = self.linearize(&values);
+
+            let (next, next_error) = match self.method {
+                OptimizationMethod::GaussNewton => {
+                    let delta = GaussianBayesNet::eliminate(linear, &order)?.solve();
+                    let next = self.retract(&values, &delta);
+                    let next_error = self.error(&next);
+                    (next, next_error)
+                },
+                OptimizationMethod::LevenbergMarquardt => {
+                    match self.damped_step(&linear, &order, &values, error, &mut lambda) {
+                        Some(step) => step,
+                        None => break,
+                    }
+                }
+            };
+
+            converged = (error - next_error).abs() <= self.relative_tolerance * error || next_error <= self.absolute_tolerance;
+            values = next;
+            error = next_error;
+            errors.push(error);
+        }
+
+        Ok(OptimizationResult {
+            values,
+            error,
+            errors,
+            iterations,
+            converged,
+        })
+    }
+
+    /// Find a Levenberg-Marquardt step that does not increase the error, raising the damping until one does.
+    ///
+    /// Returns None once the damping exceeds its limit without finding such a step.
+    fn damped_step(&self, linear: &[GaussianFactor], order: &[String], values: &Values, error: f64,
+                   lambda: &mut f64) -> Option<(Values, f64)> {
+        while *lambda <= MAX_LAMBDA {
+            let mut damped = linear.to_vec();
+            for var in order {
+                let dimension = self.graph.dimension(var);
+                damped.push(GaussianFactor::new(vec!(var.clone()), vec!(dimension),
+                                                GaussianPotential::new(Matrix::identity(dimension).scaled(*lambda),
+                                                                       vec![0.0; dimension])));
+            }
+
+            if let Ok(bayes_net) = GaussianBayesNet::eliminate(damped, order) {
+                let next = self.retract(values, &bayes_net.solve());
+                let next_error = self.error(&next);
+                if next_error <= error {
+                    *lambda = (*lambda / 10.0).max(1e-12);
+                    return Some((next, next_error));
+                }
+            }
+            *lambda *= 10.0;
+        }
+        None
+    }
+
+    /// Add an update to the values of the variables it covers.
+    fn retract(&self, values: &Values, delta: &Values) -> Values {
+        let mut result = values.clone();
+        for (var, step) in delta.iter() {
+            for (x, dx) in result.get_mut(var).unwrap().iter_mut().zip(step.iter()) {
+                *x += dx;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::names;
+
+    /// Residual of a measured difference between two scalar positions.
+    #[derive(Debug)]
+    struct Odometry {
+        measured: f64,
+    }
+
+    impl ResidualFunction for Odometry {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            vec!(values[1][0] - values[0][0] - self.measured)
+        }
+    }
+
+    /// Residual of a measured value of a variable, with every component weighted equally.
+    #[derive(Debug)]
+    struct Prior {
+        measured: Vec<f64>,
+    }
+
+    impl ResidualFunction for Prior {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            values[0].iter().zip(self.measured.iter()).map(|(x, m)| x - m).collect()
+        }
+    }
+
+    /// Residual of the distance between a 2D point and a fixed beacon, against a measured range.
+    #[derive(Debug)]
+    struct Range {
+        beacon: (f64, f64),
+        measured: f64,
+    }
+
+    impl ResidualFunction for Range {
+        fn residual(&self, values: &[&[f64]]) -> Vec<f64> {
+            let (dx, dy) = (values[0][0] - self.beacon.0, values[0][1] - self.beacon.1);
+            vec!((dx * dx + dy * dy).sqrt() - self.measured)
+        }
+    }
+
+    /// Build a chain of five scalar poses one apart, with an outlying loop closure claiming x4 is 1 from x0.
+    fn make_chain_with_outlier(closure: Option<RobustKernel>, switchable: bool) -> (FactorGraph, Values) {
+        let mut graph = FactorGraph::new();
+        let mut initial = Values::new();
+        for i in 0..5 {
+            let name = format!("x{}", i);
+            graph.add_gaussian_var(&name, 1);
+            initial.insert(name, vec!(i as f64 * 1.05));
+        }
+
+        graph.add_nonlinear_factor(names(&["x0"]),
+                                   NonlinearPotential::new(Prior { measured: vec!(0.0) }).with_sigmas(vec!(0.1)));
+        for i in 1..5 {
+            graph.add_nonlinear_factor(vec!(format!("x{}", i - 1), format!("x{}", i)),
+                                       NonlinearPotential::new(Odometry { measured: 1.0 }).with_sigmas(vec!(0.1)));
+        }
+
+        let mut loop_closure = NonlinearPotential::new(Odometry { measured: 1.0 }).with_sigmas(vec!(0.1));
+        if let Some(kernel) = closure {
+            loop_closure = loop_closure.with_kernel(kernel);
+        }
+        if switchable {
+            graph.add_switchable_factor(names(&["x0", "x4"]), loop_closure, "s", 1.0);
+            initial.insert(String::from("s"), vec!(1.0));
+        } else {
+            graph.add_nonlinear_factor(names(&["x0", "x4"]), loop_closure);
+        }
+        (graph, initial)
+    }
+
+    #[test]
+    fn gauss_newton_solves_linear_problems_in_one_step() {
+        let mut graph = FactorGraph::new();
+        for name in &["x0", "x1", "x2"] {
+            graph.add_gaussian_var(name, 1);
+        }
+        graph.add_nonlinear_factor(names(&["x0"]), NonlinearPotential::new(Prior { measured: vec!(0.0) }));
+        graph.add_nonlinear_factor(names(&["x0", "x1"]), NonlinearPotential::new(Odometry { measured: 1.0 }));
+        graph.add_nonlinear_factor(names(&["x1", "x2"]), NonlinearPotential::new(Odometry { measured: 1.0 }));
+        graph.add_nonlinear_factor(names(&["x2"]), NonlinearPotential::new(Prior { measured: vec!(2.3) }));
+
+        let initial: Values = names(&["x0", "x1", "x2"]).into_iter().map(|v| (v, vec!(5.0))).collect();
+        let result = NonlinearOptimizer::new(&graph, initial)
+            .with_method(OptimizationMethod::GaussNewton)
+            .optimize()
+            .unwrap();
+
+        // The 0.3 disagreement is shared equally by the four factors.
+        let expected = [0.075, 1.15, 2.225];
+        for (name, e) in ["x0", "x1", "x2"].iter().zip(expected.iter()) {
+            assert!((result.values[*name][0] - e).abs() < 1e-6);
+        }
+        assert!((result.error - 2.0 * 0.075 * 0.075).abs() < 1e-10);
+        assert!(result.errors[1] - result.error < 1e-10);
+    }
+
+    #[test]
+    fn levenberg_marquardt_localizes_from_ranges() {
+        let truth: (f64, f64) = (2.0, 1.0);
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("p", 2);
+        for &beacon in [(0.0, 0.0), (5.0, 0.0), (0.0, 4.0)].iter() {
+            let measured = (truth.0 - beacon.0).hypot(truth.1 - beacon.1);
+            graph.add_nonlinear_factor(names(&["p"]), NonlinearPotential::new(Range { beacon, measured }));
+        }
+
+        let mut initial = Values::new();
+        initial.insert(String::from("p"), vec!(8.0, -6.0));
+        let result = NonlinearOptimizer::new(&graph, initial.clone()).optimize().unwrap();
+
+        assert!(result.converged);
+        assert!((result.values["p"][0] - truth.0).abs() < 1e-6);
+        assert!((result.values["p"][1] - truth.1).abs() < 1e-6);
+        assert!(result.errors.windows(2).all(|w| w[1] <= w[0]));
+
+        // A single range leaves the point undetermined for Gauss-Newton.
+        let mut underdetermined = FactorGraph::new();
+        underdetermined.add_gaussian_var("p", 2);
+        underdetermined.add_nonlinear_factor(names(&["p"]),
+                                             NonlinearPotential::new(Range { beacon: (0.0, 0.0), measured: 1.0 }));
+        let error = NonlinearOptimizer::new(&underdetermined, initial)
+            .with_method(OptimizationMethod::GaussNewton)
+            .optimize()
+            .unwrap_err();
+        assert_eq!(error.variable, "p");
+    }
+
+    #[test]
+    fn robust_kernels_reject_outlying_loop_closures() {
+        let (graph, initial) = make_chain_with_outlier(None, false);
+        let result = NonlinearOptimizer::new(&graph, initial).optimize().unwrap();
+        assert!((result.values["x4"][0] - 4.0).abs() > 1.0);
+
+        for kernel in [RobustKernel::Cauchy(1.0), RobustKernel::Tukey(3.0), RobustKernel::GemanMcClure(1.0)].iter() {
+            let (graph, initial) = make_chain_with_outlier(Some(*kernel), false);
+            let result = NonlinearOptimizer::new(&graph, initial).optimize().unwrap();
+            assert!(result.converged);
+            assert!((result.values["x4"][0] - 4.0).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn switchable_constraints_turn_off_outliers() {
+        let (graph, initial) = make_chain_with_outlier(None, true);
+        let result = NonlinearOptimizer::new(&graph, initial).optimize().unwrap();
+
+        assert!(result.converged);
+        assert!(result.values["s"][0] < 0.05);
+        assert!((result.values["x4"][0] - 4.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn covariances_grow_along_odometry_chains() {
+        let mut graph = FactorGraph::new();
+        let mut initial = Values::new();
+        for i in 0..4 {
+            let name = format!("x{}", i);
+            graph.add_gaussian_var(&name, 1);
+            initial.insert(name, vec!(0.0));
+        }
+        graph.add_nonlinear_factor(names(&["x0"]), NonlinearPotential::new(Prior { measured: vec!(0.0) }));
+        for i in 1..4 {
+            graph.add_nonlinear_factor(vec!(format!("x{}", i - 1), format!("x{}", i)),
+                                       NonlinearPotential::new(Odometry { measured: 1.0 }).with_sigmas(vec!(0.5)));
+        }
+
+        let optimizer = NonlinearOptimizer::new(&graph, initial);
+        let result = optimizer.optimize().unwrap();
+        let bayes_net = optimizer.eliminate(&result.values).unwrap();
+        let mut recovery = CovarianceRecovery::new(&bayes_net);
+
+        // Each odometry step adds its variance of 0.25 to the prior's unit variance.
+        for i in 0..4 {
+            let variance = recovery.marginal_covariance(&format!("x{}", i))[(0, 0)];
+            assert!((variance - (1.0 + 0.25 * i as f64)).abs() < 1e-9);
+        }
+        let joint = recovery.joint_covariance(&["x1", "x3"]);
+        assert!((joint[(0, 1)] - 1.25).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,395 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with particle belief propagation over continuous variables with arbitrary potentials
+
+use std::rc::Rc;
+
+use *;
+use rng::Rng;
+use table::log_sum_exp;
+
+type LogPotentialFunc = dyn Fn(&[&[f64]]) -> f64;
+
+/// Struct representing an arbitrary potential over continuous variables, given by its natural log.
+#[derive(Clone)]
+pub struct ContinuousPotential {
+    log_potential: Rc<LogPotentialFunc>,
+}
+
+impl ContinuousPotential {
+    /// Create a new ContinuousPotential from a function giving the log-potential at values of the factor's
+    /// variables, in the factor's order.
+    pub fn new<F: Fn(&[&[f64]]) -> f64 + 'static>(log_potential: F) -> ContinuousPotential {
+        ContinuousPotential {
+            log_potential: Rc::new(log_potential),
+        }
+    }
+
+    /// Evaluate the log-potential at the given values of the factor's variables.
+    pub fn log_value(&self, values: &[&[f64]]) -> f64 {
+        (self.log_potential)(values)
+    }
+}
+
+impl std::fmt::Debug for ContinuousPotential {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ContinuousPotential {{ <log_potential_func> }}")
+    }
+}
+
+/// Evaluate the log-potential of a continuous factor, up to a constant.
+pub(crate) fn factor_log_value(factor: &Factor, values: &[&[f64]]) -> f64 {
+    match *factor.get_potential() {
+        Potential::Continuous(ref potential) => potential.log_value(values),
+        Potential::Gaussian(ref potential) => {
+            let x: Vec<f64> = values.iter().flat_map(|v| v.iter().cloned()).collect();
+            let px = potential.get_precision().mul_vec(&x);
+            x.iter().zip(px.iter()).zip(potential.get_information().iter())
+                .map(|((xi, pxi), hi)| hi * xi - 0.5 * xi * pxi)
+                .sum()
+        },
+        Potential::Nonlinear(ref potential) => -potential.error(values),
+        _ => panic!("Factor {} cannot be used in particle belief propagation", factor.get_name())
+    }
+}
+
+/// Struct holding the weighted particles for one variable, with the density they were drawn from.
+#[derive(Clone)]
+struct ParticleSet {
+    particles: Vec<Vec<f64>>,
+    log_proposal: Vec<f64>,
+}
+
+impl ParticleSet {
+    /// Draw particles from a Gaussian kernel density estimate of weighted particles.
+    ///
+    /// The bandwidth in each dimension follows Silverman's rule for the effective sample size.
+    fn resample(&self, log_weights: &[f64], rng: &mut Rng) -> ParticleSet {
+        let log_total = log_sum_exp(log_weights);
+        let weights: Vec<f64> = log_weights.iter().map(|w| (w - log_total).exp()).collect();
+        let dimension = self.particles[0].len();
+        let effective = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let bandwidths: Vec<f64> = (0..dimension)
+            .map(|d| {
+                let mean: f64 = weights.iter().zip(self.particles.iter()).map(|(w, x)| w * x[d]).sum();
+                let variance: f64 = weights.iter().zip(self.particles.iter())
+                    .map(|(w, x)| w * (x[d] - mean) * (x[d] - mean))
+                    .sum();
+                let factor = (4.0 / ((dimension as f64 + 2.0) * effective)).powf(1.0 / (dimension as f64 + 4.0));
+                (variance.sqrt() * factor).max(1e-9)
+            })
+            .collect();
+
+        let particles: Vec<Vec<f64>> = (0..self.particles.len())
+            .map(|_| {
+                let source = &self.particles[rng.sample_index(&weights)];
+                source.iter().zip(bandwidths.iter()).map(|(x, h)| x + h * rng.next_gaussian()).collect()
+            })
+            .collect();
+
+        let log_proposal = particles.iter()
+            .map(|x| {
+                let terms: Vec<f64> = self.particles.iter().zip(weights.iter())
+                    .filter(|&(_, w)| *w > 0.0)
+                    .map(|(source, w)| {
+                        w.ln() + x.iter().zip(source.iter()).zip(bandwidths.iter())
+                            .map(|((a, b), h)| -0.5 * ((a - b) / h).powi(2) - h.ln())
+                            .sum::<f64>()
+                    })
+                    .collect();
+                log_sum_exp(&terms)
+            })
+            .collect();
+
+        ParticleSet {
+            particles,
+            log_proposal,
+        }
+    }
+}
+
+/// Struct holding the beliefs computed by particle belief propagation.
+#[derive(Clone, Debug)]
+pub struct ParticleBpResult {
+    /// Particles representing the belief over each variable.
+    pub particles: HashMap<String, Vec<Vec<f64>>>,
+
+    /// Normalized weight of each particle in the belief.
+    pub weights: HashMap<String, Vec<f64>>,
+
+    /// Mean of the belief over each variable.
+    pub means: Values,
+}
+
+/// Struct running particle belief propagation on continuous variables.
+///
+/// Every message is represented by its values at a set of particles for the receiving variable, with
+/// the sums over the other variables of a factor estimated by importance sampling from their particles.
+/// After each round of messages, the particles of every variable are redrawn from a kernel density estimate
+/// of its current belief, so that they concentrate where the belief has mass. Factors may hold continuous,
+/// Gaussian or nonlinear least-squares potentials.
+#[derive(Debug)]
+pub struct ParticleBeliefPropagation<'a> {
+    graph: &'a FactorGraph,
+    initial: Values,
+    spread: f64,
+    num_particles: usize,
+    iterations: usize,
+    seed: u64,
+}
+
+impl<'a> ParticleBeliefPropagation<'a> {
+    /// Create a new ParticleBeliefPropagation for the graph, drawing the first particles of each variable
+    /// around its initial value.
+    ///
+    /// Defaults to 100 particles, 10 iterations and a unit standard deviation for the first particles.
+    pub fn new(graph: &'a FactorGraph, initial: Values) -> ParticleBeliefPropagation<'a> {
+        ParticleBeliefPropagation {
+            graph,
+            initial,
+            spread: 1.0,
+            num_particles: 100,
+            iterations: 10,
+            seed: 0,
+        }
+    }
+
+    /// Set the standard deviation of the Gaussian the first particles are drawn from.
+    pub fn with_spread(mut self, spread: f64) -> ParticleBeliefPropagation<'a> {
+        if spread <= 0.0 {
+            panic!("Spread must be positive, got {}", spread);
+        }
+
+        self.spread = spread;
+        self
+    }
+
+    /// Set the number of particles for each variable.
+    pub fn with_num_particles(mut self, num_particles: usize) -> ParticleBeliefPropagation<'a> {
+        if num_particles == 0 {
+            panic!("Particle belief propagation needs at least one particle");
+        }
+
+        self.num_particles = num_particles;
+        self
+    }
+
+    /// Set the number of rounds of message passing and resampling.
+    pub fn with_iterations(mut self, iterations: usize) -> ParticleBeliefPropagation<'a> {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Set the seed of the random number generator.
+    pub fn with_seed(mut self, seed: u64) -> ParticleBeliefPropagation<'a> {
+        self.seed = seed;
+        self
+    }
+
+    /// Pass messages and resample, then compute the beliefs.
+    pub fn run(&self) -> ParticleBpResult {
+        let mut rng = Rng::new(self.seed);
+        let names: Vec<String> = self.graph.get_variable_names().into_iter()
+            .filter(|v| self.graph.dimension(v) > 0)
+            .collect();
+        let factors = self.graph.get_factors();
+        let scopes: Vec<Vec<usize>> = factors.iter()
+            .map(|f| f.get_variables().iter()
+                .map(|var| match names.iter().position(|v| v == var) {
+                    Some(x) => x,
+                    None => panic!("The variable {} is not continuous", var)
+                })
+                .collect())
+            .collect();
+
+        let mut sets: Vec<ParticleSet> = names.iter()
+            .map(|name| {
+                let center = match self.initial.get(name) {
+                    Some(x) => x,
+                    None => panic!("No initial value for the variable {}", name)
+                };
+                let particles: Vec<Vec<f64>> = (0..self.num_particles)
+                    .map(|_| center.iter().map(|c| c + self.spread * rng.next_gaussian()).collect())
+                    .collect();
+                let log_proposal = particles.iter()
+                    .map(|x| x.iter().zip(center.iter()).map(|(a, c)| -0.5 * ((a - c) / self.spread).powi(2)).sum())
+                    .collect();
+                ParticleSet { particles, log_proposal }
+            })
+            .collect();
+
+        // Messages from factors are indexed by factor and position within the factor's scope, and hold the
+        // log-message at each particle of the receiving variable.
+        let mut to_var: Vec<Vec<Vec<f64>>> = scopes.iter()
+            .map(|scope| vec![vec![0.0; self.num_particles]; scope.len()])
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let to_factor: Vec<Vec<Vec<f64>>> = scopes.iter().enumerate()
+                .map(|(f, scope)| (0..scope.len())
+                    .map(|pos| self.variable_message(&scopes, &to_var, f, pos))
+                    .collect())
+                .collect();
+
+            let next_sets: Vec<ParticleSet> = if iteration == 0 {
+                sets.clone()
+            } else {
+                sets.iter().enumerate()
+                    .map(|(v, set)| set.resample(&self.log_weights(&scopes, &to_var, &sets, v), &mut rng))
+                    .collect()
+            };
+
+            to_var = scopes.iter().enumerate()
+                .map(|(f, scope)| (0..scope.len())
+                    .map(|pos| {
+                        let target = &next_sets[scope[pos]];
+                        target.particles.iter()
+                            .map(|x| {
+                                let terms: Vec<f64> = (0..self.num_particles)
+                                    .map(|j| {
+                                        let mut log_value = 0.0;
+                                        let values: Vec<&[f64]> = scope.iter().enumerate()
+                                            .map(|(other_pos, &u)| if other_pos == pos {
+                                                x.as_slice()
+                                            } else {
+                                                log_value += to_factor[f][other_pos][j] - sets[u].log_proposal[j];
+                                                sets[u].particles[j].as_slice()
+                                            })
+                                            .collect();
+                                        log_value + factor_log_value(&factors[f], &values)
+                                    })
+                                    .collect();
+                                if scope.len() == 1 { terms[0] } else { log_sum_exp(&terms) }
+                            })
+                            .collect::<Vec<f64>>()
+                    })
+                    .map(|mut message| {
+                        let log_total = log_sum_exp(&message);
+                        if log_total.is_finite() {
+                            for m in message.iter_mut() {
+                                *m -= log_total;
+                            }
+                        }
+                        message
+                    })
+                    .collect())
+                .collect();
+            sets = next_sets;
+        }
+
+        let mut result = ParticleBpResult {
+            particles: HashMap::new(),
+            weights: HashMap::new(),
+            means: Values::new(),
+        };
+        for (v, name) in names.iter().enumerate() {
+            let log_weights = self.log_weights(&scopes, &to_var, &sets, v);
+            let log_total = log_sum_exp(&log_weights);
+            let weights: Vec<f64> = log_weights.iter().map(|w| (w - log_total).exp()).collect();
+
+            let dimension = self.graph.dimension(name);
+            let mean = (0..dimension)
+                .map(|d| weights.iter().zip(sets[v].particles.iter()).map(|(w, x)| w * x[d]).sum())
+                .collect();
+            result.particles.insert(name.clone(), sets[v].particles.clone());
+            result.weights.insert(name.clone(), weights);
+            result.means.insert(name.clone(), mean);
+        }
+        result
+    }
+
+    /// Compute the message from the variable at position `pos` of factor `f` to that factor, at the
+    /// variable's particles.
+    fn variable_message(&self, scopes: &[Vec<usize>], to_var: &[Vec<Vec<f64>>], f: usize, pos: usize) -> Vec<f64> {
+        let var = scopes[f][pos];
+        let mut message = vec![0.0; self.num_particles];
+        for (g, scope) in scopes.iter().enumerate() {
+            for (other_pos, &other) in scope.iter().enumerate() {
+                if other == var && (g, other_pos) != (f, pos) {
+                    for (m, incoming) in message.iter_mut().zip(to_var[g][other_pos].iter()) {
+                        *m += incoming;
+                    }
+                }
+            }
+        }
+        message
+    }
+
+    /// Get the log importance weight of each particle of variable `v` under its belief.
+    fn log_weights(&self, scopes: &[Vec<usize>], to_var: &[Vec<Vec<f64>>], sets: &[ParticleSet], v: usize) -> Vec<f64> {
+        let mut log_weights: Vec<f64> = sets[v].log_proposal.iter().map(|q| -q).collect();
+        for (f, scope) in scopes.iter().enumerate() {
+            for (pos, &var) in scope.iter().enumerate() {
+                if var == v {
+                    for (w, incoming) in log_weights.iter_mut().zip(to_var[f][pos].iter()) {
+                        *w += incoming;
+                    }
+                }
+            }
+        }
+        log_weights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use test_support::names;
+
+    fn gaussian_log_density(x: f64, mean: f64, variance: f64) -> f64 {
+        -0.5 * ((x - mean) * (x - mean) / variance + (2.0 * PI * variance).ln())
+    }
+
+    #[test]
+    fn particle_beliefs_match_gaussian_bp() {
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("x1", 1);
+        graph.add_gaussian_var("x2", 1);
+        graph.add_gaussian_factor(names(&["x1"]),
+                                  GaussianPotential::from_moments(&[0.0], &Matrix::from_diagonal(&[1.0])));
+        graph.add_continuous_factor(names(&["x1", "x2"]),
+                                    ContinuousPotential::new(|v| gaussian_log_density(v[1][0], v[0][0], 0.5)));
+        graph.add_continuous_factor(names(&["x2"]),
+                                    ContinuousPotential::new(|v| gaussian_log_density(2.0, v[0][0], 0.5)));
+
+        let initial: Values = names(&["x1", "x2"]).into_iter().map(|v| (v, vec!(0.0))).collect();
+        let result = ParticleBeliefPropagation::new(&graph, initial).with_spread(2.0).with_num_particles(200).run();
+
+        // Precision [[3, -2], [-2, 4]] and information [0, 4] give means 1 and 1.5.
+        assert!((result.means["x1"][0] - 1.0).abs() < 0.15);
+        assert!((result.means["x2"][0] - 1.5).abs() < 0.15);
+        assert!((result.weights["x1"].iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn particles_track_multimodal_beliefs() {
+        let mut graph = FactorGraph::new();
+        graph.add_gaussian_var("x1", 1);
+        graph.add_gaussian_var("x2", 1);
+
+        // A range of 2 from the origin leaves x1 at either -2 or 2 until x2 = x1 + 1 is observed near 3.
+        graph.add_continuous_factor(names(&["x1"]),
+                                    ContinuousPotential::new(|v| gaussian_log_density(v[0][0].abs(), 2.0, 0.05)));
+        graph.add_continuous_factor(names(&["x1", "x2"]),
+                                    ContinuousPotential::new(|v| gaussian_log_density(v[1][0], v[0][0] + 1.0, 0.05)));
+        graph.add_continuous_factor(names(&["x2"]),
+                                    ContinuousPotential::new(|v| gaussian_log_density(3.0, v[0][0], 0.5)));
+
+        let initial: Values = names(&["x1", "x2"]).into_iter().map(|v| (v, vec!(0.0))).collect();
+        let result = ParticleBeliefPropagation::new(&graph, initial).with_spread(3.0).with_num_particles(200).run();
+
+        let negative: f64 = result.particles["x1"].iter().zip(result.weights["x1"].iter())
+            .filter(|&(x, _)| x[0] < 0.0)
+            .map(|(_, w)| w)
+            .sum();
+        assert!(negative < 0.05);
+        assert!((result.means["x1"][0] - 2.0).abs() < 0.2);
+    }
+}
@@ -0,0 +1,254 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with pseudo-likelihood and block composite likelihood learning of log-linear weights
+
+use *;
+use table::log_sum_exp;
+
+/// Struct representing one joint value of a block, with everything else fixed at an assignment from the data.
+#[derive(Debug)]
+struct Choice {
+    /// Log-potential of the block's neighbouring factors that are not log-linear.
+    fixed: f64,
+
+    /// Weight index and value of the features of the block's neighbouring log-linear factors.
+    features: Vec<(usize, f64)>,
+}
+
+impl Choice {
+    fn log_potential(&self, parameters: &[f64]) -> f64 {
+        self.features.iter().fold(self.fixed, |acc, &(weight, value)| acc + parameters[weight] * value)
+    }
+}
+
+/// Struct representing the conditional distribution of one block given the rest of one assignment.
+#[derive(Debug)]
+struct Term {
+    choices: Vec<Choice>,
+    observed: usize,
+}
+
+/// Struct fitting the parameters of a graph's log-linear factors by maximizing a composite likelihood.
+///
+/// The objective is the average over the assignments of `-sum_B log p(x_B | x_rest)` over blocks of variables `B`,
+/// plus `l2 / 2 * |w|^2 + l1 * |w|_1`. Each conditional only involves the factors returned by `get_factors` for
+/// the variables of its block, so no partition function over the whole graph is needed. With one block per
+/// variable, the default, this is the pseudo-likelihood; larger blocks trade computation for statistical
+/// efficiency, and a single block holding every variable gives back maximum likelihood. Both estimators are
+/// consistent, so they agree with maximum likelihood given enough data from the model.
+#[derive(Debug)]
+pub struct PseudoLikelihood<'a> {
+    graph: &'a mut FactorGraph,
+    data: &'a [Assignment],
+    blocks: Vec<Vec<String>>,
+    l2: f64,
+    l1: f64,
+    optimizer: Lbfgs,
+}
+
+impl<'a> PseudoLikelihood<'a> {
+    /// Create a new PseudoLikelihood fitting the graph to complete assignments of its variables.
+    ///
+    /// Defaults to one block per variable without regularization, starting from the graph's current parameters.
+    pub fn new(graph: &'a mut FactorGraph, data: &'a [Assignment]) -> PseudoLikelihood<'a> {
+        if data.is_empty() {
+            panic!("Pseudo-likelihood needs at least one assignment");
+        }
+
+        let blocks = graph.get_variable_names().into_iter().map(|name| vec!(name)).collect();
+        PseudoLikelihood {
+            graph,
+            data,
+            blocks,
+            l2: 0.0,
+            l1: 0.0,
+            optimizer: Lbfgs::new(),
+        }
+    }
+
+    /// Use the conditionals of the given blocks of variables, giving a composite likelihood.
+    pub fn with_blocks(mut self, blocks: Vec<Vec<String>>) -> PseudoLikelihood<'a> {
+        for var in blocks.iter().flat_map(|block| block.iter()) {
+            if self.graph.get_variable(var).is_none() {
+                panic!("The variable {} was not found in the factor graph.", var);
+            }
+        }
+        if blocks.iter().any(|block| block.is_empty()) {
+            panic!("Every block needs at least one variable");
+        }
+
+        self.blocks = blocks;
+        self
+    }
+
+    /// Add the penalty `l2 / 2 * |w|^2` to the objective.
+    pub fn with_l2(mut self, l2: f64) -> PseudoLikelihood<'a> {
+        if l2 < 0.0 {
+            panic!("The L2 penalty must be non-negative, got {}", l2);
+        }
+
+        self.l2 = l2;
+        self
+    }
+
+    /// Add the penalty `l1 * |w|_1` to the objective.
+    pub fn with_l1(mut self, l1: f64) -> PseudoLikelihood<'a> {
+        self.optimizer = self.optimizer.with_l1(l1);
+        self.l1 = l1;
+        self
+    }
+
+    /// Set the maximum number of optimizer iterations.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> PseudoLikelihood<'a> {
+        self.optimizer = self.optimizer.with_max_iterations(max_iterations);
+        self
+    }
+
+    /// Set the gradient norm under which the optimizer stops.
+    pub fn with_tolerance(mut self, tolerance: f64) -> PseudoLikelihood<'a> {
+        self.optimizer = self.optimizer.with_tolerance(tolerance);
+        self
+    }
+
+    /// Fit the parameters, leaving the graph with the fitted parameters.
+    pub fn run(self) -> TrainingResult {
+        let terms = self.terms();
+        let scale = 1.0 / self.data.len() as f64;
+        let l2 = self.l2;
+
+        let initial = self.graph.get_parameters();
+        let result = self.optimizer.minimize(&initial, |parameters| {
+            let mut value: f64 = parameters.iter().map(|w| 0.5 * l2 * w * w).sum();
+            let mut gradient: Vec<f64> = parameters.iter().map(|w| l2 * w).collect();
+
+            for term in terms.iter() {
+                let log_potentials: Vec<f64> = term.choices.iter().map(|c| c.log_potential(parameters)).collect();
+                let log_z = log_sum_exp(&log_potentials);
+                value -= scale * (log_potentials[term.observed] - log_z);
+
+                for &(weight, feature) in term.choices[term.observed].features.iter() {
+                    gradient[weight] -= scale * feature;
+                }
+                for (choice, log_potential) in term.choices.iter().zip(log_potentials.iter()) {
+                    let p = (log_potential - log_z).exp();
+                    for &(weight, feature) in choice.features.iter() {
+                        gradient[weight] += scale * p * feature;
+                    }
+                }
+            }
+            (value, gradient)
+        });
+
+        self.graph.set_parameters(&result.parameters);
+        TrainingResult::from_minimization(result, self.l2, self.l1)
+    }
+
+    /// Tabulate the conditional of every block for every assignment, which does not depend on the parameters.
+    fn terms(&self) -> Vec<Term> {
+        let mut terms = vec!();
+        for block in self.blocks.iter() {
+            let mut neighbourhood: Vec<&Factor> = vec!();
+            for var in block.iter() {
+                for factor in self.graph.get_variable(var).unwrap().get_factors().iter() {
+                    if neighbourhood.iter().all(|f| f.get_id() != factor.get_id()) {
+                        neighbourhood.push(factor);
+                    }
+                }
+            }
+            let cardinalities: Vec<usize> = block.iter().map(|var| self.graph.domain_size(var)).collect();
+            let configurations: usize = cardinalities.iter().product();
+
+            for assignment in self.data.iter() {
+                let observed = block.iter().zip(cardinalities.iter())
+                    .fold(0, |index, (var, card)| index * card + self.observed(assignment, var) as usize);
+
+                let mut values = assignment.clone();
+                let choices = (0..configurations)
+                    .map(|configuration| {
+                        let mut rest = configuration;
+                        for (var, &card) in block.iter().zip(cardinalities.iter()).rev() {
+                            values.insert(var.clone(), (rest % card) as u32);
+                            rest /= card;
+                        }
+
+                        let mut choice = Choice { fixed: 0.0, features: vec!() };
+                        for factor in neighbourhood.iter() {
+                            let factor_values: Vec<u32> = factor.get_variables().iter()
+                                .map(|var| self.observed(&values, var))
+                                .collect();
+                            match *factor.get_potential() {
+                                Potential::LogLinear(ref potential) => choice.features.extend(potential.features(&factor_values)),
+                                _ => choice.fixed += factor.log_potential(&factor_values)
+                            }
+                        }
+                        choice
+                    })
+                    .collect();
+                terms.push(Term { choices, observed });
+            }
+        }
+        terms
+    }
+
+    /// Get the value of a variable in an assignment, panicking if it is unobserved.
+    fn observed(&self, assignment: &Assignment, var: &str) -> u32 {
+        match assignment.get(var) {
+            Some(&x) if (x as usize) < self.graph.domain_size(var) => x,
+            Some(&x) => panic!("Value {} is out of range for variable {}", x, var),
+            None => panic!("The variable {} is not observed in every assignment", var)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::{make_cycle, sample_exact};
+
+    #[test]
+    fn pseudo_likelihood_agrees_with_maximum_likelihood() {
+        let truth = [0.5, -0.3, 0.2, 0.0, 0.8];
+        let mut truth_graph = make_cycle();
+        truth_graph.set_parameters(&truth);
+        let data = sample_exact(&truth_graph, 5000, 7);
+
+        let mut mle_graph = make_cycle();
+        let mle = MaximumLikelihood::new(&mut mle_graph, &data).run();
+        let mut pl_graph = make_cycle();
+        let pl = PseudoLikelihood::new(&mut pl_graph, &data).run();
+        let mut cl_graph = make_cycle();
+        let blocks = vec!(vec!(String::from("a"), String::from("b")), vec!(String::from("c"), String::from("d")));
+        let cl = PseudoLikelihood::new(&mut cl_graph, &data).with_blocks(blocks).run();
+
+        assert!(pl.converged && cl.converged);
+        for (k, w) in truth.iter().enumerate() {
+            assert!((mle.parameters[k] - w).abs() < 0.15);
+            assert!((pl.parameters[k] - mle.parameters[k]).abs() < 0.1);
+            assert!((cl.parameters[k] - mle.parameters[k]).abs() < 0.1);
+        }
+        assert_eq!(pl_graph.get_parameters(), pl.parameters);
+    }
+
+    #[test]
+    fn single_block_is_maximum_likelihood() {
+        let mut truth = make_cycle();
+        truth.set_parameters(&[0.5, -0.3, 0.2, 0.0, 0.8]);
+        let data = sample_exact(&truth, 200, 7);
+        let mut mle_graph = make_cycle();
+        let mle = MaximumLikelihood::new(&mut mle_graph, &data).with_l2(0.1).with_tolerance(1e-9).run();
+
+        let mut cl_graph = make_cycle();
+        let everything = vec!(cl_graph.get_variable_names());
+        let cl = PseudoLikelihood::new(&mut cl_graph, &data).with_blocks(everything).with_l2(0.1).with_tolerance(1e-9).run();
+
+        assert!((cl.log_likelihood - mle.log_likelihood).abs() < 1e-9);
+        for (w, v) in cl.parameters.iter().zip(mle.parameters.iter()) {
+            assert!((w - v).abs() < 1e-6);
+        }
+    }
+}
@@ -62,7 +62,7 @@ impl<'a> dot::GraphWalk<'a, Nd, Ed> for FactorGraph {
 
     fn edges(&'a self) -> dot::Edges<'a, Ed> {
         let mut edges = vec!();
-        for (_, variable) in &self.variables {
+        for variable in self.variables.values() {
             for factor in variable.get_factors() {
                 edges.push((variable.get_id() as usize, factor.get_id() as usize));
             }
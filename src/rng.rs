@@ -0,0 +1,77 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Small seedable random number generator for the sampling algorithms
+
+use table::log_sum_exp;
+
+/// Struct holding the state of a xorshift64* generator.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new Rng from a seed.
+    pub fn new(seed: u64) -> Rng {
+        // Scramble the seed with splitmix64 so that small seeds still give well-mixed states.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        Rng {
+            state: if z == 0 { 0x2545_F491_4F6C_DD1D } else { z },
+        }
+    }
+
+    /// Get the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Get a uniform sample from `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Get a sample from the standard normal distribution, by the Box-Muller transform.
+    pub fn next_gaussian(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    /// Sample an index with probability proportional to the given weights.
+    pub fn sample_index(&mut self, weights: &[f64]) -> usize {
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 || !total.is_finite() {
+            panic!("Cannot sample from weights {:?}", weights);
+        }
+
+        let mut target = self.next_f64() * total;
+        for (i, w) in weights.iter().enumerate() {
+            if target < *w {
+                return i;
+            }
+            target -= w;
+        }
+
+        // Rounding can leave a sliver of mass past the end, which belongs to the last positive weight.
+        weights.iter().rposition(|w| *w > 0.0).unwrap()
+    }
+
+    /// Sample an index with probability proportional to the exponentials of the given log-weights.
+    pub fn sample_log_index(&mut self, log_weights: &[f64]) -> usize {
+        let log_total = log_sum_exp(log_weights);
+        let weights: Vec<f64> = log_weights.iter().map(|w| (w - log_total).exp()).collect();
+        self.sample_index(&weights)
+    }
+}
@@ -0,0 +1,501 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with sampling-based estimators over discrete factor graphs
+
+use *;
+use model::Model;
+use rng::Rng;
+
+/// Two-sided 95% quantile of the standard normal distribution.
+const Z_95: f64 = 1.959_963_984_540_054;
+
+/// Struct holding a sampling-based estimate of the log partition function and marginals.
+#[derive(Clone, Debug)]
+pub struct SamplingEstimate {
+    /// Estimate of the log partition function, summing only over assignments consistent with the evidence.
+    pub log_partition: f64,
+
+    /// Approximate 95% confidence interval for the log partition function.
+    pub confidence_interval: (f64, f64),
+
+    /// Effective sample size of the importance weights.
+    pub effective_sample_size: f64,
+
+    /// Estimated marginals of every variable given the evidence.
+    pub marginals: Marginals,
+}
+
+impl SamplingEstimate {
+    /// Summarize weighted samples into an estimate.
+    fn from_weighted_samples(model: &Model, states: &[Vec<u32>], log_weights: &[f64]) -> SamplingEstimate {
+        let n = log_weights.len() as f64;
+        let max = log_weights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        if max == f64::NEG_INFINITY {
+            panic!("Every sample has zero weight; the proposal does not cover the model");
+        }
+
+        // Work with weights scaled by the largest one to avoid overflow.
+        let weights: Vec<f64> = log_weights.iter().map(|w| (w - max).exp()).collect();
+        let total: f64 = weights.iter().sum();
+        let mean = total / n;
+        let variance = weights.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        let std_error = (variance / n).sqrt();
+
+        let lower = if mean - Z_95 * std_error > 0.0 {
+            max + (mean - Z_95 * std_error).ln()
+        } else {
+            f64::NEG_INFINITY
+        };
+        let upper = max + (mean + Z_95 * std_error).ln();
+
+        let mut marginals: Vec<Vec<f64>> = model.cardinalities.iter().map(|&card| vec![0.0; card]).collect();
+        for (state, w) in states.iter().zip(weights.iter()) {
+            for (var, &val) in state.iter().enumerate() {
+                marginals[var][val as usize] += w / total;
+            }
+        }
+
+        SamplingEstimate {
+            log_partition: max + mean.ln(),
+            confidence_interval: (lower, upper),
+            effective_sample_size: total * total / weights.iter().map(|w| w * w).sum::<f64>(),
+            marginals: model.names.iter().cloned().zip(marginals).collect(),
+        }
+    }
+}
+
+/// Get the log-probabilities of a proposal in variable order, checking it covers every free variable.
+fn proposal_logs(model: &Model, proposal: &Marginals, clamped: &[Option<u32>]) -> Vec<Vec<f64>> {
+    model.names.iter().enumerate()
+        .map(|(var, name)| match proposal.get(name) {
+            Some(probs) => {
+                if probs.len() != model.cardinalities[var] {
+                    panic!("Proposal for variable {} has {} values, expected {}",
+                           name, probs.len(), model.cardinalities[var]);
+                }
+                probs.iter().map(|p| p.ln()).collect()
+            },
+            None if clamped[var].is_some() => vec![0.0; model.cardinalities[var]],
+            None => panic!("The proposal has no distribution for variable {}", name)
+        })
+        .collect()
+}
+
+/// Draw a state from a fully factorized proposal, returning it with its log-probability under the proposal.
+fn sample_proposal(log_q: &[Vec<f64>], clamped: &[Option<u32>], rng: &mut Rng) -> (Vec<u32>, f64) {
+    let mut log_prob = 0.0;
+    let state = log_q.iter().zip(clamped.iter())
+        .map(|(logs, clamp)| match *clamp {
+            Some(val) => val,
+            None => {
+                let val = rng.sample_log_index(logs);
+                log_prob += logs[val];
+                val as u32
+            }
+        })
+        .collect();
+    (state, log_prob)
+}
+
+/// Get the log-probability of the free variables of a state under a fully factorized proposal.
+fn proposal_log_prob(log_q: &[Vec<f64>], clamped: &[Option<u32>], state: &[u32]) -> f64 {
+    (0..state.len())
+        .filter(|&var| clamped[var].is_none())
+        .map(|var| log_q[var][state[var] as usize])
+        .sum()
+}
+
+/// Resample every free variable once from its conditional distribution.
+///
+/// With `tempering` set to `(beta, log_q)`, the target is instead proportional to `q^(1 - beta) * f^beta`.
+fn gibbs_sweep(model: &Model, clamped: &[Option<u32>], state: &mut [u32], rng: &mut Rng,
+               tempering: Option<(f64, &[Vec<f64>])>) {
+    for var in 0..state.len() {
+        if clamped[var].is_some() {
+            continue;
+        }
+
+        let mut log_probs = model.conditional(state, var);
+        if let Some((beta, log_q)) = tempering.filter(|&(beta, _)| beta < 1.0) {
+            for (val, lp) in log_probs.iter_mut().enumerate() {
+                *lp = beta * *lp + (1.0 - beta) * log_q[var][val];
+            }
+        }
+        state[var] = rng.sample_log_index(&log_probs) as u32;
+    }
+}
+
+/// Struct running a single-site Gibbs sampler over the discrete variables of a factor graph.
+#[derive(Debug)]
+pub struct GibbsSampler {
+    model: Model,
+    clamped: Vec<Option<u32>>,
+    state: Vec<u32>,
+    rng: Rng,
+}
+
+impl GibbsSampler {
+    /// Create a new GibbsSampler for the graph, starting from a uniformly random state.
+    pub fn new(graph: &FactorGraph, seed: u64) -> GibbsSampler {
+        let model = Model::new(graph);
+        let mut rng = Rng::new(seed);
+        let state = model.cardinalities.iter().map(|&card| (rng.next_u64() % card as u64) as u32).collect();
+        let clamped = vec![None; model.names.len()];
+
+        GibbsSampler {
+            model,
+            clamped,
+            state,
+            rng,
+        }
+    }
+
+    /// Clamp the given variables to observed values.
+    pub fn with_evidence(mut self, evidence: Assignment) -> GibbsSampler {
+        self.clamped = self.model.clamped(&evidence);
+        for (var, clamp) in self.clamped.iter().enumerate() {
+            if let Some(val) = *clamp {
+                self.state[var] = val;
+            }
+        }
+        self
+    }
+
+    /// Get the current state of the chain.
+    pub fn get_state(&self) -> Assignment {
+        self.model.to_assignment(&self.state)
+    }
+
+    /// Move the chain to the given state. Variables missing from `state` and clamped variables are left alone.
+    pub fn set_state(&mut self, state: &Assignment) {
+        for (name, &val) in state.iter() {
+            let var = self.model.position(name);
+            if self.clamped[var].is_none() {
+                self.state[var] = val;
+            }
+        }
+    }
+
+    /// Tabulate the log-linear factors of the graph again, keeping the chain's state, evidence and random number
+    /// generator.
+    ///
+    /// The graph must have the same variables and factors as the one the sampler was created for, but its
+    /// parameters may have changed through `FactorGraph::set_parameters`. Other factors are not tabulated again.
+    pub fn reload(&mut self, graph: &FactorGraph) {
+        let factors = graph.get_factors();
+        if graph.get_variable_names() != self.model.names || factors.len() != self.model.tables.len() {
+            panic!("The graph's variables or factors differ from those the sampler was created for");
+        }
+
+        for (table, factor) in self.model.tables.iter_mut().zip(factors.iter()) {
+            if let Potential::LogLinear(_) = *factor.get_potential() {
+                *table = Table::from_factor(graph, factor);
+            }
+        }
+    }
+
+    /// Resample every free variable once.
+    pub fn sweep(&mut self) {
+        gibbs_sweep(&self.model, &self.clamped, &mut self.state, &mut self.rng, None);
+    }
+
+    /// Discard `burn_in` sweeps, then record the state after each of `num_samples` further sweeps.
+    pub fn sample(&mut self, num_samples: usize, burn_in: usize) -> Vec<Assignment> {
+        for _ in 0..burn_in {
+            self.sweep();
+        }
+
+        (0..num_samples)
+            .map(|_| {
+                self.sweep();
+                self.get_state()
+            })
+            .collect()
+    }
+
+    /// Estimate the marginal of every variable from `num_samples` sweeps after `burn_in` discarded ones.
+    pub fn marginals(&mut self, num_samples: usize, burn_in: usize) -> Marginals {
+        if num_samples == 0 {
+            panic!("Estimating marginals needs at least one sample");
+        }
+
+        for _ in 0..burn_in {
+            self.sweep();
+        }
+
+        let mut counts: Vec<Vec<f64>> = self.model.cardinalities.iter().map(|&card| vec![0.0; card]).collect();
+        for _ in 0..num_samples {
+            self.sweep();
+            for (var, &val) in self.state.iter().enumerate() {
+                counts[var][val as usize] += 1.0 / num_samples as f64;
+            }
+        }
+
+        self.model.names.iter().cloned().zip(counts).collect()
+    }
+}
+
+/// Struct estimating the log partition function and marginals by importance sampling.
+///
+/// Samples are drawn from a fully factorized proposal, such as marginals from `MeanField` or
+/// `BeliefPropagation`, with any evidence clamped. With evidence this is likelihood weighting, and the
+/// estimated log partition function only sums over assignments consistent with the evidence.
+#[derive(Debug)]
+pub struct ImportanceSampler<'a> {
+    graph: &'a FactorGraph,
+    proposal: Marginals,
+    evidence: Assignment,
+    defensive_mixture: f64,
+    num_samples: usize,
+    seed: u64,
+}
+
+impl<'a> ImportanceSampler<'a> {
+    /// Create a new ImportanceSampler for the graph drawing 1000 samples from the proposal.
+    pub fn new(graph: &'a FactorGraph, proposal: Marginals) -> ImportanceSampler<'a> {
+        ImportanceSampler {
+            graph,
+            proposal,
+            evidence: Assignment::new(),
+            defensive_mixture: 0.0,
+            num_samples: 1000,
+            seed: 0,
+        }
+    }
+
+    /// Clamp the given variables to observed values.
+    pub fn with_evidence(mut self, evidence: Assignment) -> ImportanceSampler<'a> {
+        self.evidence = evidence;
+        self
+    }
+
+    /// Mix the proposal with the uniform distribution, giving it weight `epsilon`.
+    ///
+    /// Approximations like mean-field are often overconfident, and a little uniform mass keeps the
+    /// importance weights from having heavy tails.
+    pub fn with_defensive_mixture(mut self, epsilon: f64) -> ImportanceSampler<'a> {
+        if !(0.0..=1.0).contains(&epsilon) {
+            panic!("Defensive mixture weight must be in [0, 1], got {}", epsilon);
+        }
+
+        self.defensive_mixture = epsilon;
+        self
+    }
+
+    /// Set the number of samples to draw.
+    pub fn with_num_samples(mut self, num_samples: usize) -> ImportanceSampler<'a> {
+        if num_samples == 0 {
+            panic!("Importance sampling needs at least one sample");
+        }
+
+        self.num_samples = num_samples;
+        self
+    }
+
+    /// Set the seed of the random number generator.
+    pub fn with_seed(mut self, seed: u64) -> ImportanceSampler<'a> {
+        self.seed = seed;
+        self
+    }
+
+    /// Draw the samples and summarize them.
+    pub fn run(&self) -> SamplingEstimate {
+        let model = Model::new(self.graph);
+        let clamped = model.clamped(&self.evidence);
+        let mut log_q = proposal_logs(&model, &self.proposal, &clamped);
+        if self.defensive_mixture > 0.0 {
+            for logs in log_q.iter_mut() {
+                let uniform = self.defensive_mixture / logs.len() as f64;
+                for lp in logs.iter_mut() {
+                    *lp = ((1.0 - self.defensive_mixture) * lp.exp() + uniform).ln();
+                }
+            }
+        }
+        let mut rng = Rng::new(self.seed);
+
+        let mut states = Vec::with_capacity(self.num_samples);
+        let mut log_weights = Vec::with_capacity(self.num_samples);
+        for _ in 0..self.num_samples {
+            let (state, log_prob) = sample_proposal(&log_q, &clamped, &mut rng);
+            log_weights.push(model.log_potential(&state) - log_prob);
+            states.push(state);
+        }
+
+        SamplingEstimate::from_weighted_samples(&model, &states, &log_weights)
+    }
+}
+
+/// Struct estimating the log partition function and marginals by annealed importance sampling.
+///
+/// Each sample starts from the fully factorized proposal `q` and moves through the distributions
+/// proportional to `q^(1 - beta) * f^beta` along the temperature path, taking a Gibbs sweep at each
+/// temperature.
+#[derive(Debug)]
+pub struct AnnealedImportanceSampler<'a> {
+    graph: &'a FactorGraph,
+    proposal: Marginals,
+    evidence: Assignment,
+    temperatures: Vec<f64>,
+    num_samples: usize,
+    seed: u64,
+}
+
+impl<'a> AnnealedImportanceSampler<'a> {
+    /// Create a new AnnealedImportanceSampler drawing 100 samples along 100 evenly spaced temperatures.
+    pub fn new(graph: &'a FactorGraph, proposal: Marginals) -> AnnealedImportanceSampler<'a> {
+        AnnealedImportanceSampler {
+            graph,
+            proposal,
+            evidence: Assignment::new(),
+            temperatures: (0..101).map(|k| f64::from(k) / 100.0).collect(),
+            num_samples: 100,
+            seed: 0,
+        }
+    }
+
+    /// Clamp the given variables to observed values.
+    pub fn with_evidence(mut self, evidence: Assignment) -> AnnealedImportanceSampler<'a> {
+        self.evidence = evidence;
+        self
+    }
+
+    /// Use the given temperature path, which must increase from 0 to 1.
+    pub fn with_temperatures(mut self, temperatures: Vec<f64>) -> AnnealedImportanceSampler<'a> {
+        if temperatures.first() != Some(&0.0) || temperatures.last() != Some(&1.0)
+            || temperatures.windows(2).any(|w| w[1] < w[0]) {
+            panic!("Temperatures must increase from 0 to 1, got {:?}", temperatures);
+        }
+
+        self.temperatures = temperatures;
+        self
+    }
+
+    /// Set the number of annealing runs.
+    pub fn with_num_samples(mut self, num_samples: usize) -> AnnealedImportanceSampler<'a> {
+        if num_samples == 0 {
+            panic!("Annealed importance sampling needs at least one annealing run");
+        }
+
+        self.num_samples = num_samples;
+        self
+    }
+
+    /// Set the seed of the random number generator.
+    pub fn with_seed(mut self, seed: u64) -> AnnealedImportanceSampler<'a> {
+        self.seed = seed;
+        self
+    }
+
+    /// Run the annealing chains and summarize them.
+    pub fn run(&self) -> SamplingEstimate {
+        let model = Model::new(self.graph);
+        let clamped = model.clamped(&self.evidence);
+        let log_q = proposal_logs(&model, &self.proposal, &clamped);
+        let mut rng = Rng::new(self.seed);
+
+        let mut states = Vec::with_capacity(self.num_samples);
+        let mut log_weights = Vec::with_capacity(self.num_samples);
+        for _ in 0..self.num_samples {
+            let (mut state, mut log_prob) = sample_proposal(&log_q, &clamped, &mut rng);
+            let mut log_f = model.log_potential(&state);
+            let mut log_weight = 0.0;
+
+            for step in self.temperatures.windows(2) {
+                if step[1] > step[0] {
+                    log_weight += (step[1] - step[0]) * (log_f - log_prob);
+                }
+
+                gibbs_sweep(&model, &clamped, &mut state, &mut rng, Some((step[1], &log_q)));
+                log_f = model.log_potential(&state);
+                log_prob = proposal_log_prob(&log_q, &clamped, &state);
+            }
+
+            log_weights.push(log_weight);
+            states.push(state);
+        }
+
+        SamplingEstimate::from_weighted_samples(&model, &states, &log_weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coupling(vals: &[u32]) -> i32 {
+        if vals[0] == vals[1] { 3 } else { 1 }
+    }
+
+    fn field(vals: &[u32]) -> i32 {
+        2 * vals[0] as i32 + 1
+    }
+
+    fn make_loop() -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        for name in &["a", "b", "c"] {
+            graph.add_discrete_var(name, vec![0, 1]);
+        }
+
+        for &(x, y) in [("a", "b"), ("b", "c"), ("c", "a")].iter() {
+            graph.add_factor::<i32>(vec!(String::from(x), String::from(y)), coupling);
+        }
+        graph.add_factor::<i32>(vec!(String::from("a")), field);
+        graph
+    }
+
+    #[test]
+    fn importance_sampling_estimates_log_z() {
+        let graph = make_loop();
+        let proposal = MeanField::new(&graph).run();
+        let estimate = ImportanceSampler::new(&graph, proposal)
+            .with_defensive_mixture(0.5)
+            .with_num_samples(5000)
+            .run();
+        let exact = Enumeration::new(&graph).run().unwrap();
+
+        assert!((estimate.log_partition - exact.log_partition).abs() < 0.05);
+        assert!(estimate.confidence_interval.0 < estimate.log_partition);
+        assert!(estimate.confidence_interval.1 > estimate.log_partition);
+        assert!(estimate.effective_sample_size > 1000.0);
+    }
+
+    #[test]
+    fn likelihood_weighting_clamps_evidence() {
+        let graph = make_loop();
+        let mut evidence = Assignment::new();
+        evidence.insert(String::from("a"), 1);
+
+        let uniform: Marginals = graph.get_variable_names().into_iter().map(|n| (n, vec![0.5, 0.5])).collect();
+        let estimate = ImportanceSampler::new(&graph, uniform).with_evidence(evidence.clone())
+            .with_num_samples(5000)
+            .run();
+        let exact = Enumeration::new(&graph).with_evidence(evidence).run().unwrap();
+
+        assert!((estimate.marginals["a"][1] - 1.0).abs() < 1e-9);
+        assert!((estimate.log_partition - exact.log_partition).abs() < 0.05);
+        assert!((estimate.marginals["b"][1] - exact.marginals["b"][1]).abs() < 0.03);
+    }
+
+    #[test]
+    fn annealed_importance_sampling_estimates_log_z() {
+        let graph = make_loop();
+        let uniform: Marginals = graph.get_variable_names().into_iter().map(|n| (n, vec![0.5, 0.5])).collect();
+        let estimate = AnnealedImportanceSampler::new(&graph, uniform).with_num_samples(500).run();
+        let exact = Enumeration::new(&graph).run().unwrap();
+
+        assert!((estimate.log_partition - exact.log_partition).abs() < 0.05);
+    }
+
+    #[test]
+    #[should_panic]
+    fn importance_sampling_needs_samples() {
+        let graph = make_loop();
+        let uniform: Marginals = graph.get_variable_names().into_iter().map(|n| (n, vec![0.5, 0.5])).collect();
+        ImportanceSampler::new(&graph, uniform).with_num_samples(0);
+    }
+}
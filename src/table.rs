@@ -0,0 +1,301 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with dense potential tables over discrete variables
+
+use *;
+
+/// Compute `log(sum(exp(values)))` without overflow.
+pub(crate) fn log_sum_exp(values: &[f64]) -> f64 {
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max.is_infinite() {
+        return max;
+    }
+
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+/// Struct representing a dense table of log-potentials over several discrete variables.
+///
+/// Entries are stored in row-major order, so the last variable changes fastest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    variables: Vec<String>,
+    cardinalities: Vec<usize>,
+    log_values: Vec<f64>,
+}
+
+impl Table {
+    /// Create a new Table from its variables, their domain sizes and the log-potential of each entry.
+    pub fn new(variables: Vec<String>, cardinalities: Vec<usize>, log_values: Vec<f64>) -> Table {
+        if variables.len() != cardinalities.len() {
+            panic!("Table has {} variables but {} cardinalities", variables.len(), cardinalities.len());
+        }
+
+        let size: usize = cardinalities.iter().product();
+        if log_values.len() != size {
+            panic!("Table over {:?} needs {} entries, got {}", variables, size, log_values.len());
+        }
+
+        Table {
+            variables,
+            cardinalities,
+            log_values,
+        }
+    }
+
+    /// Create a Table over the given variables with every log-potential zero.
+    pub fn uniform(variables: Vec<String>, cardinalities: Vec<usize>) -> Table {
+        let size = cardinalities.iter().product();
+        Table::new(variables, cardinalities, vec![0.0; size])
+    }
+
+    /// Create a Table with no variables holding a single log-potential.
+    pub fn constant(log_value: f64) -> Table {
+        Table::new(vec!(), vec!(), vec!(log_value))
+    }
+
+    /// Tabulate the log-potential of a factor in the given graph.
+    pub fn from_factor(graph: &FactorGraph, factor: &Factor) -> Table {
+        let variables = factor.get_variables().clone();
+        let cardinalities: Vec<usize> = variables.iter().map(|var| graph.domain_size(var)).collect();
+
+        let mut table = Table { variables, cardinalities, log_values: vec!() };
+        table.log_values = (0..table.size())
+            .map(|index| factor.log_potential(&table.assignment_at(index)))
+            .collect();
+        table
+    }
+
+    /// Function to get the variables this table is defined over.
+    pub fn get_variables(&self) -> &Vec<String> {
+        &self.variables
+    }
+
+    /// Function to get the domain size of each of this table's variables.
+    pub fn get_cardinalities(&self) -> &Vec<usize> {
+        &self.cardinalities
+    }
+
+    /// Function to get the log-potential of every entry in the table.
+    pub fn get_log_values(&self) -> &Vec<f64> {
+        &self.log_values
+    }
+
+    /// Get the number of entries in the table.
+    pub fn size(&self) -> usize {
+        self.cardinalities.iter().product()
+    }
+
+    /// Test whether the table is defined over a variable.
+    pub fn contains(&self, var: &str) -> bool {
+        self.variables.iter().any(|v| v == var)
+    }
+
+    /// Get the position of a variable within this table's variables.
+    fn position(&self, var: &str) -> usize {
+        match self.variables.iter().position(|v| v == var) {
+            Some(x) => x,
+            None => panic!("Variable {} is not in table over {:?}", var, self.variables)
+        }
+    }
+
+    /// Get the distance between consecutive values of the variable at `pos`.
+    fn stride(&self, pos: usize) -> usize {
+        self.cardinalities[pos + 1..].iter().product()
+    }
+
+    /// Get the entry index for an assignment to this table's variables.
+    pub fn index_of(&self, assignment: &[u32]) -> usize {
+        let mut index = 0;
+        for (val, card) in assignment.iter().zip(self.cardinalities.iter()) {
+            index = index * card + *val as usize;
+        }
+        index
+    }
+
+    /// Get the assignment to this table's variables for an entry index.
+    pub fn assignment_at(&self, index: usize) -> Vec<u32> {
+        let mut assignment = vec![0; self.variables.len()];
+        let mut rest = index;
+        for pos in (0..self.variables.len()).rev() {
+            assignment[pos] = (rest % self.cardinalities[pos]) as u32;
+            rest /= self.cardinalities[pos];
+        }
+        assignment
+    }
+
+    /// Get the log-potential for an assignment to this table's variables.
+    pub fn log_value(&self, assignment: &[u32]) -> f64 {
+        self.log_values[self.index_of(assignment)]
+    }
+
+    /// Multiply two tables, giving a table over the union of their variables.
+    pub fn product(&self, other: &Table) -> Table {
+        let mut variables = self.variables.clone();
+        let mut cardinalities = self.cardinalities.clone();
+        for (var, card) in other.variables.iter().zip(other.cardinalities.iter()) {
+            if !self.contains(var) {
+                variables.push(var.clone());
+                cardinalities.push(*card);
+            }
+        }
+
+        let other_positions: Vec<usize> = other.variables.iter()
+            .map(|var| variables.iter().position(|v| v == var).unwrap())
+            .collect();
+
+        let mut result = Table { variables, cardinalities, log_values: vec!() };
+        let num_own = self.variables.len();
+        result.log_values = (0..result.size())
+            .map(|index| {
+                let assignment = result.assignment_at(index);
+                let other_assignment: Vec<u32> = other_positions.iter().map(|&pos| assignment[pos]).collect();
+                self.log_value(&assignment[..num_own]) + other.log_value(&other_assignment)
+            })
+            .collect();
+        result
+    }
+
+    /// Combine the entries along one variable with `reduce`, removing it from the table.
+    fn reduce_out<F: Fn(&[f64]) -> f64>(&self, var: &str, reduce: F) -> Table {
+        let pos = self.position(var);
+        let card = self.cardinalities[pos];
+        let stride = self.stride(pos);
+
+        let mut variables = self.variables.clone();
+        let mut cardinalities = self.cardinalities.clone();
+        variables.remove(pos);
+        cardinalities.remove(pos);
+
+        let size: usize = cardinalities.iter().product();
+        let mut slice = vec![0.0; card];
+        let log_values = (0..size)
+            .map(|index| {
+                let outer = index / stride;
+                let inner = index % stride;
+                for (val, entry) in slice.iter_mut().enumerate() {
+                    *entry = self.log_values[(outer * card + val) * stride + inner];
+                }
+                reduce(&slice)
+            })
+            .collect();
+
+        Table::new(variables, cardinalities, log_values)
+    }
+
+    /// Get a copy of this table with its variables in the given order.
+    pub fn reordered(&self, variables: &[String]) -> Table {
+        if variables.len() != self.variables.len() || variables.iter().any(|var| !self.contains(var)) {
+            panic!("Cannot reorder table over {:?} to {:?}", self.variables, variables);
+        }
+
+        let cardinalities = variables.iter().map(|var| self.cardinalities[self.position(var)]).collect();
+        Table::uniform(variables.to_vec(), cardinalities).product(self)
+    }
+
+    /// Sum a variable out of the table.
+    pub fn sum_out(&self, var: &str) -> Table {
+        self.reduce_out(var, log_sum_exp)
+    }
+
+    /// Maximize a variable out of the table.
+    pub fn max_out(&self, var: &str) -> Table {
+        self.reduce_out(var, |slice| slice.iter().cloned().fold(f64::NEG_INFINITY, f64::max))
+    }
+
+    /// Eliminate a variable with the weighted power sum `(sum_x f(x)^(1/weight))^weight`.
+    ///
+    /// A weight of one is an ordinary sum, and the power sum tends to the maximum as the weight goes to zero.
+    pub fn power_sum_out(&self, var: &str, weight: f64) -> Table {
+        if weight <= 0.0 {
+            return self.max_out(var);
+        }
+
+        self.reduce_out(var, |slice| {
+            let scaled: Vec<f64> = slice.iter().map(|v| v / weight).collect();
+            weight * log_sum_exp(&scaled)
+        })
+    }
+
+    /// Fix a variable to a single value, removing it from the table.
+    pub fn restrict(&self, var: &str, value: u32) -> Table {
+        let pos = self.position(var);
+        if value as usize >= self.cardinalities[pos] {
+            panic!("Value {} is out of range for variable {}", value, var);
+        }
+
+        self.reduce_out(var, |slice| slice[value as usize])
+    }
+
+    /// Get the log of the sum of all entries in the table.
+    pub fn log_partition(&self) -> f64 {
+        log_sum_exp(&self.log_values)
+    }
+
+    /// Get a copy of this table scaled so its entries sum to one.
+    pub fn normalized(&self) -> Table {
+        let log_z = self.log_partition();
+        if log_z == f64::NEG_INFINITY {
+            panic!("Cannot normalize table over {:?} with zero mass", self.variables);
+        }
+
+        let mut result = self.clone();
+        for v in result.log_values.iter_mut() {
+            *v -= log_z;
+        }
+        result
+    }
+
+    /// Get the normalized probability of every entry in the table.
+    pub fn probabilities(&self) -> Vec<f64> {
+        self.normalized().log_values.iter().map(|v| v.exp()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair_table() -> Table {
+        Table::new(vec!(String::from("a"), String::from("b")), vec!(2, 3),
+                   (1..7).map(|v| f64::from(v).ln()).collect())
+    }
+
+    #[test]
+    fn assignments_round_trip() {
+        let table = pair_table();
+
+        for index in 0..table.size() {
+            assert_eq!(table.index_of(&table.assignment_at(index)), index);
+        }
+        assert_eq!(table.assignment_at(4), vec!(1, 1));
+    }
+
+    #[test]
+    fn sum_and_max_out() {
+        let table = pair_table();
+
+        let summed = table.sum_out("a");
+        assert_eq!(summed.get_variables(), &vec!(String::from("b")));
+        assert!((summed.log_value(&[2]) - 9f64.ln()).abs() < 1e-12);
+
+        let maxed = table.max_out("b");
+        assert!((maxed.log_value(&[0]) - 3f64.ln()).abs() < 1e-12);
+        assert!((table.power_sum_out("b", 1.0).log_value(&[1]) - 15f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn product_aligns_shared_variables() {
+        let table = pair_table();
+        let other = Table::new(vec!(String::from("b")), vec!(3), vec!(0.0, 1.0, 2.0));
+
+        let product = table.product(&other);
+        assert_eq!(product.get_variables(), table.get_variables());
+        assert!((product.log_value(&[1, 2]) - (6f64.ln() + 2.0)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,110 @@
+#![deny(missing_docs,
+missing_debug_implementations, missing_copy_implementations,
+trivial_casts, trivial_numeric_casts,
+unsafe_code,
+unstable_features,
+unused_import_braces, unused_qualifications)]
+
+//! Module with graphs, data and helpers shared by the unit tests
+
+use *;
+use model::Model;
+use rng::Rng;
+
+/// Convert variable names to owned strings.
+pub(crate) fn names(vars: &[&str]) -> Vec<String> {
+    vars.iter().map(|v| String::from(*v)).collect()
+}
+
+/// Draw complete assignments from a small discrete graph by enumerating its joint distribution.
+pub(crate) fn sample_exact(graph: &FactorGraph, num_samples: usize, seed: u64) -> Vec<Assignment> {
+    let model = Model::new(graph);
+    let num_states: usize = model.cardinalities.iter().product();
+    let states: Vec<Vec<u32>> = (0..num_states)
+        .map(|index| {
+            // The first variable varies slowest.
+            let mut rest = index;
+            let mut state: Vec<u32> = model.cardinalities.iter().rev()
+                .map(|&card| {
+                    let val = rest % card;
+                    rest /= card;
+                    val as u32
+                })
+                .collect();
+            state.reverse();
+            state
+        })
+        .collect();
+    let log_weights: Vec<f64> = states.iter().map(|state| model.log_potential(state)).collect();
+
+    let mut rng = Rng::new(seed);
+    (0..num_samples).map(|_| model.to_assignment(&states[rng.sample_log_index(&log_weights)])).collect()
+}
+
+/// Feature of a pair of binary variables that is 1 when they agree and -1 otherwise.
+pub(crate) fn agreement(vals: &[u32]) -> f64 {
+    if vals[0] == vals[1] { 1.0 } else { -1.0 }
+}
+
+/// Build the chain a - b - c with a field on each variable and one coupling weight shared by both edges.
+pub(crate) fn make_chain() -> FactorGraph {
+    let mut graph = FactorGraph::new();
+    for name in &["a", "b", "c"] {
+        graph.add_discrete_var(name, vec![0, 1]);
+        let field = graph.add_parameter(0.0);
+        graph.add_log_linear_factor(vec!(String::from(*name)),
+                                    LogLinearPotential::new().with_feature(field, |v| v[0] as f64));
+    }
+
+    let coupling = graph.add_parameter(0.0);
+    for pair in &[("a", "b"), ("b", "c")] {
+        graph.add_log_linear_factor(vec!(String::from(pair.0), String::from(pair.1)),
+                                    LogLinearPotential::new().with_feature(coupling, agreement));
+    }
+    graph
+}
+
+/// Get eight complete assignments of the chain.
+pub(crate) fn make_chain_data() -> Vec<Assignment> {
+    let states = [[0, 0, 0], [0, 0, 1], [1, 1, 1], [1, 1, 0], [1, 1, 1], [0, 1, 1], [1, 0, 0], [1, 1, 1]];
+    states.iter()
+        .map(|state| ["a", "b", "c"].iter().map(|v| String::from(*v)).zip(state.iter().cloned()).collect())
+        .collect()
+}
+
+/// Build the cycle a - b - c - d - a with a field on each variable and one shared coupling weight.
+pub(crate) fn make_cycle() -> FactorGraph {
+    let names = ["a", "b", "c", "d"];
+    let mut graph = FactorGraph::new();
+    for name in names.iter() {
+        graph.add_discrete_var(name, vec![0, 1]);
+        let field = graph.add_parameter(0.0);
+        graph.add_log_linear_factor(vec!(String::from(*name)),
+                                    LogLinearPotential::new().with_feature(field, |v| v[0] as f64));
+    }
+
+    let coupling = graph.add_parameter(0.0);
+    for i in 0..names.len() {
+        graph.add_log_linear_factor(vec!(String::from(names[i]), String::from(names[(i + 1) % names.len()])),
+                                    LogLinearPotential::new().with_feature(coupling, agreement));
+    }
+    graph
+}
+
+/// Build a chain CRF labelling each observation with a binary label, with weights for the observation,
+/// a bias and the agreement of neighbouring labels.
+pub(crate) fn make_tagger() -> ConditionalRandomField<Vec<f64>> {
+    ConditionalRandomField::new(3, |observations: &Vec<f64>, graph: &mut FactorGraph| {
+        for (t, &x) in observations.iter().enumerate() {
+            let label = format!("y{}", t);
+            graph.add_discrete_var(&label, vec![0, 1]);
+            graph.add_log_linear_factor(vec!(label), LogLinearPotential::new()
+                .with_feature(0, move |v| if v[0] == 1 { x } else { 0.0 })
+                .with_feature(1, |v| v[0] as f64));
+            if t > 0 {
+                graph.add_log_linear_factor(vec!(format!("y{}", t - 1), format!("y{}", t)), LogLinearPotential::new()
+                    .with_feature(2, agreement));
+            }
+        }
+    })
+}
@@ -18,6 +18,7 @@ pub struct TreeNode {
     data: u32,
     name: String,
     parent: Option<usize>,
+    weight: f64,
 
     /// Index of this node within the tree.
     pub index: usize,
@@ -47,6 +48,7 @@ impl TreeNode {
             data,
             name: String::from(name),
             parent: Some(parent),
+            weight: 1.0,
             children: vec!()
         }
     }
@@ -58,6 +60,7 @@ impl TreeNode {
             data,
             name: String::from(name),
             parent: None,
+            weight: 0.0,
             children: vec!()
         }
     }
@@ -67,6 +70,17 @@ impl TreeNode {
         self.name.clone()
     }
 
+    /// Function to get the index of this node's parent, if it has one.
+    pub fn get_parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// Function to get the weight of the edge to this node's parent, which is one in unweighted trees and zero
+    /// for the root.
+    pub fn get_weight(&self) -> f64 {
+        self.weight
+    }
+
     /// Add a child to this tree node.
     pub fn add_child(&mut self, node: usize) {
         self.children.push(node);
@@ -86,25 +100,63 @@ impl SpanningTree {
         }
     }
 
-    /// Get the tree node for the input data
-    fn get_node_for_data(&self, id: u32) -> Option<usize> {
-        for i in 0..self.all_nodes.len() {
-            if self.all_nodes[i].data == id {
-                return Some(i);
+    /// Make the spanning tree of maximum total weight over the complete graph on the named nodes.
+    ///
+    /// `weights[i][j]` is the weight of the edge between nodes `i` and `j`. The tree is grown from the first node
+    /// by Prim's algorithm, and node `i` holds `i` as its data.
+    pub fn maximum_weight(names: &[String], weights: &[Vec<f64>]) -> SpanningTree {
+        if names.is_empty() || weights.len() != names.len() || weights.iter().any(|row| row.len() != names.len()) {
+            panic!("Need a square matrix of weights for {} nodes", names.len());
+        }
+
+        let mut tree = SpanningTree::new(0, &names[0], names.len());
+        let mut best: Vec<(f64, u32)> = weights[0].iter().map(|&w| (w, 0)).collect();
+        let mut in_tree = vec![false; names.len()];
+        in_tree[0] = true;
+        for _ in 1..names.len() {
+            let next = (0..names.len())
+                .filter(|&i| !in_tree[i])
+                .fold(None, |acc: Option<usize>, i| match acc {
+                    Some(j) if best[j].0 >= best[i].0 => Some(j),
+                    _ => Some(i)
+                })
+                .unwrap();
+
+            tree.add_weighted_child(best[next].1, next as u32, &names[next], best[next].0);
+            in_tree[next] = true;
+            for i in 0..names.len() {
+                if !in_tree[i] && weights[next][i] > best[i].0 {
+                    best[i] = (weights[next][i], next as u32);
+                }
             }
         }
+        tree
+    }
 
-        None
+    /// Get the root node of the tree.
+    pub fn get_root(&self) -> &TreeNode {
+        &self.all_nodes[self.root]
+    }
+
+    /// Get the tree node for the input data
+    fn get_node_for_data(&self, id: u32) -> Option<usize> {
+        self.all_nodes.iter().position(|node| node.data == id)
     }
 
     /// Add a child to the specified node within the tree.
     pub fn add_child(&mut self, parent: u32, child_data: u32, name: &str) {
+        self.add_weighted_child(parent, child_data, name, 1.0);
+    }
+
+    /// Add a child to the specified node within the tree, with the given weight on the edge between them.
+    pub fn add_weighted_child(&mut self, parent: u32, child_data: u32, name: &str, weight: f64) {
         let parent_node = match self.get_node_for_data(parent) {
             Some(x) => x,
             None => panic!("Couldn't find input factor graph node in tree")
         };
 
-        let child_node = TreeNode::new(self.cur_index, child_data, name, parent_node);
+        let mut child_node = TreeNode::new(self.cur_index, child_data, name, parent_node);
+        child_node.weight = weight;
         self.all_nodes.push(child_node);
 
         match self.all_nodes.get_mut(parent_node) {
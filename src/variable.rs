@@ -17,10 +17,47 @@ pub trait Variable : FactorGraphItem {
     /// Add an associated factor to this variable.
     fn add_factor(&mut self, factor: Factor);
 
+    /// Replace the associated factor with the same id as the given one.
+    ///
+    /// The graph only calls this from `set_table`, on the variables it creates itself, so other implementations
+    /// need not support it.
+    fn replace_factor(&mut self, factor: Factor) {
+        panic!("Variable {} does not support replacing factor {}", self.get_name(), factor.get_name())
+    }
+
     /// Get the factors associated to this variable.
     fn get_factors(&self) -> &Vec<Factor>;
+
+    /// Get the values this variable can take, which is empty for continuous variables.
+    ///
+    /// Defaults to the empty domain.
+    fn get_domain(&self) -> &Vec<u32> {
+        &EMPTY_DOMAIN
+    }
+
+    /// Get the number of real components of this variable, which is zero for discrete variables.
+    fn get_dimension(&self) -> usize {
+        0
+    }
+
+    /// Get the display name of one of this variable's values.
+    ///
+    /// Defaults to the value itself.
+    fn get_val_name(&self, val: u32) -> String {
+        val.to_string()
+    }
+}
+
+/// Replace the factors with the same id as the given one.
+fn replace_in(factors: &mut [Factor], factor: Factor) {
+    for existing in factors.iter_mut().filter(|f| f.get_id() == factor.get_id()) {
+        *existing = factor.clone();
+    }
 }
 
+/// Domain shared by every variable without discrete values.
+static EMPTY_DOMAIN: Vec<u32> = Vec::new();
+
 /// Struct representing a single variable.
 #[derive(Debug)]
 pub struct DiscreteVariable<T: std::fmt::Debug + 'static> {
@@ -47,16 +84,31 @@ impl<T: std::fmt::Debug + 'static> DiscreteVariable<T> {
 
 impl<T: std::fmt::Debug + 'static> Variable for DiscreteVariable<T> {
     fn get_var_id(&self) -> u32 {
-        self.id.clone()
+        self.id
     }
 
     fn add_factor(&mut self, factor: Factor) {
         self.factors.push(factor);
     }
 
+    fn replace_factor(&mut self, factor: Factor) {
+        replace_in(&mut self.factors, factor);
+    }
+
     fn get_factors(&self) -> &Vec<Factor> {
         &self.factors
     }
+
+    fn get_domain(&self) -> &Vec<u32> {
+        &self.domain
+    }
+
+    fn get_val_name(&self, val: u32) -> String {
+        match self.val_names.get(val as usize) {
+            Some(name) => format!("{:?}", name),
+            None => panic!("Value {} is not in the domain of variable {}", val, self.name)
+        }
+    }
 }
 
 impl<T: std::fmt::Debug + Sized> FactorGraphItem for DiscreteVariable<T> {
@@ -65,7 +117,78 @@ impl<T: std::fmt::Debug + Sized> FactorGraphItem for DiscreteVariable<T> {
     }
 
     fn get_id(&self) -> u32 {
-        self.id.clone()
+        self.id
+    }
+
+    fn is_factor(&self) -> bool {
+        false
+    }
+
+    fn add_to_tree(&self, parent_id: u32, tree: &mut SpanningTree) {
+        if !tree.has_node(self.id) {
+            tree.add_child(parent_id, self.id, &self.name);
+        }
+    }
+}
+
+/// Struct representing a continuous, possibly vector-valued, variable with Gaussian factors.
+#[derive(Debug)]
+pub struct GaussianVariable {
+    id: u32,
+    name: String,
+    factors: Vec<Factor>,
+    dimension: usize,
+}
+
+impl GaussianVariable {
+    /// Create a new GaussianVariable with the given number of real components.
+    pub fn new(id: u32, name: &str, dimension: usize) -> GaussianVariable {
+        if dimension == 0 {
+            panic!("Gaussian variable {} must have at least one component", name);
+        }
+
+        GaussianVariable {
+            id,
+            name: String::from(name),
+            factors: vec!(),
+            dimension,
+        }
+    }
+}
+
+impl Variable for GaussianVariable {
+    fn get_var_id(&self) -> u32 {
+        self.id
+    }
+
+    fn add_factor(&mut self, factor: Factor) {
+        self.factors.push(factor);
+    }
+
+    fn replace_factor(&mut self, factor: Factor) {
+        replace_in(&mut self.factors, factor);
+    }
+
+    fn get_factors(&self) -> &Vec<Factor> {
+        &self.factors
+    }
+
+    fn get_val_name(&self, val: u32) -> String {
+        panic!("Continuous variable {} has no value {}", self.name, val)
+    }
+
+    fn get_dimension(&self) -> usize {
+        self.dimension
+    }
+}
+
+impl FactorGraphItem for GaussianVariable {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_id(&self) -> u32 {
+        self.id
     }
 
     fn is_factor(&self) -> bool {